@@ -0,0 +1,529 @@
+//! Resolving the compiled circom artifacts by circuit name from `circuits.json`, and checking
+//! that they fit the curve and IO we prove with before handing them to circom-scotia.
+//!
+//! An r1cs compiled for another prime (`--prime bn128` vs `--prime vesta`, see `package.json`)
+//! or for another IO shape otherwise loads fine and only shows up as failing or garbage proofs.
+//!
+//! With the `embed-artifacts` feature, `circuits.json` and the compiled r1cs, wasm and sym of
+//! `blake3_nova` and `blake3_nova_pasta` are bundled into the binary (run `yarn build` and
+//! `yarn build:pasta` first), and are preferred over the build directory.
+use ff::PrimeField;
+use num_bigint::BigUint;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::blake3_circuit::{CircomArtifacts, CIRCOM_IO_ARITY};
+
+pub const CIRCUITS_JSON_PATH: &str = "../circuits.json";
+/// Where `yarn build` and `yarn build:pasta` write the artifacts
+pub const BUILD_DIR: &str = "../build";
+
+const R1CS_MAGIC: &[u8; 4] = b"r1cs";
+const R1CS_HEADER_SECTION: u32 = 1;
+
+#[derive(Debug)]
+pub enum ArtifactError {
+    Io(PathBuf, std::io::Error),
+    Json(serde_json::Error),
+    UnknownCircuit(String),
+    InvalidR1cs(PathBuf, String),
+    InvalidWasm(PathBuf, String),
+    WrongPrime {
+        r1cs: PathBuf,
+        expected: BigUint,
+        found: BigUint,
+    },
+    WrongArity {
+        r1cs: PathBuf,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for ArtifactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArtifactError::Io(path, err) => write!(f, "Failed to read {}: {}", path.display(), err),
+            ArtifactError::Json(err) => write!(f, "Failed to parse circuits.json: {}", err),
+            ArtifactError::UnknownCircuit(name) => {
+                write!(f, "No circuit named {} in circuits.json", name)
+            }
+            ArtifactError::InvalidR1cs(path, reason) => {
+                write!(f, "{} is not a valid r1cs file: {}", path.display(), reason)
+            }
+            ArtifactError::InvalidWasm(path, reason) => {
+                write!(f, "Failed to load the witness generator {}: {}", path.display(), reason)
+            }
+            ArtifactError::WrongPrime {
+                r1cs,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} was compiled for the prime {:#x}, but the proving curve's scalar field is {:#x}",
+                r1cs.display(),
+                found,
+                expected
+            ),
+            ArtifactError::WrongArity {
+                r1cs,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} has {} public outputs, but the step circuit's IO has {}",
+                r1cs.display(),
+                found,
+                expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArtifactError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactPaths {
+    pub wasm: PathBuf,
+    pub r1cs: PathBuf,
+    pub sym: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArtifactRegistry {
+    build_dir: PathBuf,
+    /// The circomkit entries of `circuits.json`. Only the names matter here, the build
+    /// outputs are named after them
+    circuits: HashMap<String, serde_json::Value>,
+}
+
+impl ArtifactRegistry {
+    /// The embedded `circuits.json` with the `embed-artifacts` feature, otherwise the one at
+    /// `CIRCUITS_JSON_PATH` with artifacts in `BUILD_DIR`
+    pub fn builtin() -> Result<Self, ArtifactError> {
+        match embedded::CIRCUITS_JSON {
+            Some(json) => Self::parse(json, BUILD_DIR),
+            None => Self::load(CIRCUITS_JSON_PATH, BUILD_DIR),
+        }
+    }
+
+    pub fn load(
+        circuits_json: impl AsRef<Path>,
+        build_dir: impl AsRef<Path>,
+    ) -> Result<Self, ArtifactError> {
+        let path = circuits_json.as_ref();
+        let json =
+            std::fs::read_to_string(path).map_err(|err| ArtifactError::Io(path.into(), err))?;
+        Self::parse(&json, build_dir)
+    }
+
+    fn parse(json: &str, build_dir: impl AsRef<Path>) -> Result<Self, ArtifactError> {
+        Ok(ArtifactRegistry {
+            build_dir: build_dir.as_ref().into(),
+            circuits: serde_json::from_str(json).map_err(ArtifactError::Json)?,
+        })
+    }
+
+    /// Where the artifacts of the circuit `name` are, following the `-o build` layout of
+    /// the circom commands in `package.json`
+    pub fn paths(&self, name: &str) -> Result<ArtifactPaths, ArtifactError> {
+        if !self.circuits.contains_key(name) {
+            return Err(ArtifactError::UnknownCircuit(name.into()));
+        }
+        Ok(ArtifactPaths {
+            wasm: self
+                .build_dir
+                .join(format!("{}_js", name))
+                .join(format!("{}.wasm", name)),
+            r1cs: self.build_dir.join(format!("{}.r1cs", name)),
+            sym: self.build_dir.join(format!("{}.sym", name)),
+        })
+    }
+
+    /// Load the artifacts of the circuit `name` after checking its r1cs is for the field `F`
+    /// and has `CIRCOM_IO_ARITY` public outputs. Embedded artifacts are used if there are any
+    /// for `name`
+    pub fn load_artifacts<F: PrimeField>(
+        &self,
+        name: &str,
+    ) -> Result<CircomArtifacts<F>, ArtifactError> {
+        let paths = match embedded::artifacts(name) {
+            Some((wasm, r1cs, sym)) => unpack_embedded(&unpack_root(), name, wasm, r1cs, sym)?,
+            None => self.paths(name)?,
+        };
+        // `CircomArtifacts::load` checks the r1cs is for `F`
+        CircomArtifacts::load(&paths.wasm.to_string_lossy(), &paths.r1cs.to_string_lossy())
+    }
+}
+
+/// Where embedded artifacts are unpacked: the user's cache directory, or the system temp
+/// directory if there is none
+fn unpack_root() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("blake3_fold")
+}
+
+/// Create `dir` if needed and make it private to the current user. Changing the permissions
+/// fails on a directory someone else created, so it is never shared with them
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Distinguishes the temporary files of concurrent unpacks within this process
+static UNPACK_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// circom-scotia only loads artifacts from files, so the embedded ones are written to a private
+/// directory under `root` named after their hash and loaded from there
+fn unpack_embedded(
+    root: &Path,
+    name: &str,
+    wasm: &[u8],
+    r1cs: &[u8],
+    sym: &[u8],
+) -> Result<ArtifactPaths, ArtifactError> {
+    let mut hasher = blake3::Hasher::new();
+    for bytes in [wasm, r1cs, sym] {
+        hasher.update(bytes);
+    }
+    let dir = root.join(&hasher.finalize().to_hex()[..16]);
+    for dir in [root, &dir] {
+        create_private_dir(dir).map_err(|err| ArtifactError::Io(dir.into(), err))?;
+    }
+    let paths = ArtifactPaths {
+        wasm: dir.join(format!("{}.wasm", name)),
+        r1cs: dir.join(format!("{}.r1cs", name)),
+        sym: dir.join(format!("{}.sym", name)),
+    };
+    for (path, bytes) in [(&paths.wasm, wasm), (&paths.r1cs, r1cs), (&paths.sym, sym)] {
+        // Files from an earlier run are only reused if they still hold the embedded bytes
+        if std::fs::read(path).map_or(false, |existing| existing == bytes) {
+            continue;
+        }
+        // Written next to the destination under a name unique to this process and call, then
+        // renamed, so concurrent loads never see or write into a partial file
+        let tmp = path.with_extension(format!(
+            "tmp{}.{}",
+            std::process::id(),
+            UNPACK_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&tmp, bytes)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|err| {
+                let _ = std::fs::remove_file(&tmp);
+                ArtifactError::Io(path.clone(), err)
+            })?;
+    }
+    Ok(paths)
+}
+
+#[cfg(feature = "embed-artifacts")]
+mod embedded {
+    macro_rules! build_file {
+        ($path:literal) => {
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/../build/", $path))
+        };
+    }
+
+    pub(super) const CIRCUITS_JSON: Option<&str> = Some(include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../circuits.json"
+    )));
+
+    /// The wasm, r1cs and sym of the circuit `name`
+    pub(super) fn artifacts(name: &str) -> Option<(&'static [u8], &'static [u8], &'static [u8])> {
+        match name {
+            "blake3_nova" => Some((
+                build_file!("blake3_nova_js/blake3_nova.wasm"),
+                build_file!("blake3_nova.r1cs"),
+                build_file!("blake3_nova.sym"),
+            )),
+            "blake3_nova_pasta" => Some((
+                build_file!("blake3_nova_pasta_js/blake3_nova_pasta.wasm"),
+                build_file!("blake3_nova_pasta.r1cs"),
+                build_file!("blake3_nova_pasta.sym"),
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(feature = "embed-artifacts"))]
+mod embedded {
+    pub(super) const CIRCUITS_JSON: Option<&str> = None;
+
+    pub(super) fn artifacts(_name: &str) -> Option<(&'static [u8], &'static [u8], &'static [u8])> {
+        None
+    }
+}
+
+/// The parts of the r1cs header section we check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct R1csHeader {
+    pub(crate) prime: BigUint,
+    pub(crate) n_wires: u32,
+    pub(crate) n_pub_out: u32,
+    pub(crate) n_pub_in: u32,
+    pub(crate) n_prv_in: u32,
+    pub(crate) n_constraints: u32,
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read the header section of an r1cs file, see
+/// https://github.com/iden3/r1csfile/blob/master/doc/r1cs_bin_format.md
+pub(crate) fn read_r1cs_header<R: Read + Seek>(reader: &mut R) -> Result<R1csHeader, String> {
+    let err = |e: std::io::Error| e.to_string();
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).map_err(err)?;
+    if &magic != R1CS_MAGIC {
+        return Err("bad magic number".into());
+    }
+    let _version = read_u32(reader).map_err(err)?;
+    let n_sections = read_u32(reader).map_err(err)?;
+    for _ in 0..n_sections {
+        let section_type = read_u32(reader).map_err(err)?;
+        let section_size = read_u64(reader).map_err(err)?;
+        if section_type != R1CS_HEADER_SECTION {
+            reader
+                .seek(SeekFrom::Current(section_size as i64))
+                .map_err(err)?;
+            continue;
+        }
+        let field_size = read_u32(reader).map_err(err)?;
+        let mut prime = vec![0u8; field_size as usize];
+        reader.read_exact(&mut prime).map_err(err)?;
+        let n_wires = read_u32(reader).map_err(err)?;
+        let n_pub_out = read_u32(reader).map_err(err)?;
+        let n_pub_in = read_u32(reader).map_err(err)?;
+        let n_prv_in = read_u32(reader).map_err(err)?;
+        let _n_labels = read_u64(reader).map_err(err)?;
+        let n_constraints = read_u32(reader).map_err(err)?;
+        return Ok(R1csHeader {
+            prime: BigUint::from_bytes_le(&prime),
+            n_wires,
+            n_pub_out,
+            n_pub_in,
+            n_prv_in,
+            n_constraints,
+        });
+    }
+    Err("no header section".into())
+}
+
+pub(crate) fn field_modulus<F: PrimeField>() -> BigUint {
+    BigUint::parse_bytes(F::MODULUS.trim_start_matches("0x").as_bytes(), 16)
+        .expect("PrimeField::MODULUS is hex")
+}
+
+/// Check the r1cs at `path` was compiled for the field `F` and that its public outputs, which
+/// `synthesize_with_vec` returns as the step's output, match `CIRCOM_IO_ARITY`
+pub fn check_r1cs<F: PrimeField>(path: &Path) -> Result<(), ArtifactError> {
+    let file = File::open(path).map_err(|err| ArtifactError::Io(path.into(), err))?;
+    let header = read_r1cs_header(&mut BufReader::new(file))
+        .map_err(|reason| ArtifactError::InvalidR1cs(path.into(), reason))?;
+    let expected = field_modulus::<F>();
+    if header.prime != expected {
+        return Err(ArtifactError::WrongPrime {
+            r1cs: path.into(),
+            expected,
+            found: header.prime,
+        });
+    }
+    if header.n_pub_out as usize != CIRCOM_IO_ARITY {
+        return Err(ArtifactError::WrongArity {
+            r1cs: path.into(),
+            expected: CIRCOM_IO_ARITY,
+            found: header.n_pub_out as usize,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2curves::bn256::Fr;
+    use pasta_curves::Fq;
+
+    use crate::utils::TempDir;
+
+    fn r1cs_bytes(prime: &BigUint, n_pub_out: u32) -> Vec<u8> {
+        let mut prime = prime.to_bytes_le();
+        prime.resize(32, 0);
+        let mut header = vec![];
+        header.extend_from_slice(&32u32.to_le_bytes());
+        header.extend_from_slice(&prime);
+        for n in [100u32, n_pub_out, 12, 10] {
+            header.extend_from_slice(&n.to_le_bytes());
+        }
+        header.extend_from_slice(&0u64.to_le_bytes());
+        header.extend_from_slice(&50u32.to_le_bytes());
+
+        let mut bytes = R1CS_MAGIC.to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        // A constraints section before the header, which must be skipped
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&3u64.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3]);
+        bytes.extend_from_slice(&R1CS_HEADER_SECTION.to_le_bytes());
+        bytes.extend_from_slice(&(header.len() as u64).to_le_bytes());
+        bytes.extend(header);
+        bytes
+    }
+
+    fn write_r1cs(dir: &TempDir, name: &str, bytes: &[u8]) -> PathBuf {
+        let path = dir.path().join(format!("{}.r1cs", name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_r1cs_header() {
+        let prime = field_modulus::<Fq>();
+        let bytes = r1cs_bytes(&prime, CIRCOM_IO_ARITY as u32);
+        let header = read_r1cs_header(&mut std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(
+            header,
+            R1csHeader {
+                prime,
+                n_wires: 100,
+                n_pub_out: CIRCOM_IO_ARITY as u32,
+                n_pub_in: 12,
+                n_prv_in: 10,
+                n_constraints: 50,
+            }
+        );
+        assert!(read_r1cs_header(&mut std::io::Cursor::new(b"wasm".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_check_r1cs() {
+        let dir = TempDir::new("check_r1cs");
+        let vesta = write_r1cs(
+            &dir,
+            "vesta",
+            &r1cs_bytes(&field_modulus::<Fq>(), CIRCOM_IO_ARITY as u32),
+        );
+        assert!(check_r1cs::<Fq>(&vesta).is_ok());
+        // A vesta r1cs for the BN256 cycle
+        assert!(matches!(
+            check_r1cs::<Fr>(&vesta),
+            Err(ArtifactError::WrongPrime { .. })
+        ));
+
+        let wrong_arity = write_r1cs(
+            &dir,
+            "wrong_arity",
+            &r1cs_bytes(&field_modulus::<Fq>(), CIRCOM_IO_ARITY as u32 - 1),
+        );
+        assert!(matches!(
+            check_r1cs::<Fq>(&wrong_arity),
+            Err(ArtifactError::WrongArity { .. })
+        ));
+        assert!(matches!(
+            check_r1cs::<Fq>(Path::new("does/not/exist.r1cs")),
+            Err(ArtifactError::Io(..))
+        ));
+    }
+
+    #[test]
+    fn test_unpack_embedded() {
+        let dir = TempDir::new("unpack_embedded");
+        let root = dir.path().join("cache");
+        let r1cs = r1cs_bytes(&field_modulus::<Fq>(), CIRCOM_IO_ARITY as u32);
+        let unpack = || {
+            unpack_embedded(
+                &root,
+                "unpack_test",
+                b"not really wasm",
+                &r1cs,
+                b"1,1,0,main.a",
+            )
+        };
+        let paths = unpack().unwrap();
+        assert_eq!(std::fs::read(&paths.r1cs).unwrap(), r1cs);
+        assert_eq!(std::fs::read(&paths.sym).unwrap(), b"1,1,0,main.a");
+        assert!(check_r1cs::<Fq>(&paths.r1cs).is_ok());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(paths.r1cs.parent().unwrap())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        // Unpacking again reuses the files, unless they were changed
+        assert_eq!(unpack().unwrap(), paths);
+        std::fs::write(&paths.r1cs, b"tampered").unwrap();
+        assert_eq!(unpack().unwrap(), paths);
+        assert_eq!(std::fs::read(&paths.r1cs).unwrap(), r1cs);
+
+        // Threads rewriting the same files do not clobber each other's temporary files
+        std::fs::write(&paths.r1cs, b"tampered").unwrap();
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4).map(|_| scope.spawn(unpack)).collect();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap().unwrap(), paths);
+            }
+        });
+        assert_eq!(std::fs::read(&paths.r1cs).unwrap(), r1cs);
+        let leftover = std::fs::read_dir(paths.r1cs.parent().unwrap())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().contains(".tmp")
+            })
+            .count();
+        assert_eq!(leftover, 0);
+    }
+
+    #[test]
+    fn test_builtin_registry() {
+        let registry = ArtifactRegistry::builtin().unwrap();
+        assert!(registry.paths("blake3_nova").is_ok());
+        assert!(registry.paths("blake3_nova_pasta").is_ok());
+    }
+
+    #[test]
+    fn test_registry_paths() {
+        let registry = ArtifactRegistry::load(CIRCUITS_JSON_PATH, BUILD_DIR).unwrap();
+        assert_eq!(
+            registry.paths("blake3_nova_pasta").unwrap(),
+            ArtifactPaths {
+                wasm: PathBuf::from(crate::PASTA_CIRCOM_WASM_PATH),
+                r1cs: PathBuf::from(crate::PASTA_CIRCOM_R1CS_PATH),
+                sym: PathBuf::from(crate::PASTA_CIRCOM_SYM_PATH),
+            }
+        );
+        assert!(matches!(
+            registry.paths("blake3_nova_bls"),
+            Err(ArtifactError::UnknownCircuit(_))
+        ));
+    }
+}
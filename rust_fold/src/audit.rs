@@ -0,0 +1,555 @@
+//! The storage audit protocol: a verifier sends a `Challenge` for chunks of a file it knows the
+//! root and length of, and the provider answers with a `Response` holding a compressed proof per
+//! chunk (see `backend::ChunkProof`).
+//!
+//! Both use the `codec` encoding, and decoding only accepts the one encoding of a value, so a
+//! challenge's id (the hash of its encoding) is well defined. A response is checked against its
+//! challenge (which chunks, which root, on time) before any SNARK is verified, so malformed or
+//! late responses are turned down cheaply.
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::backend::{ChunkProof, ChunkProver, ProverError};
+use crate::blake3_hash::Blake3HashProof;
+use crate::codec::{self, CodecError, Kind};
+use crate::{utils, MAX_BYTES_PER_CHUNK};
+
+const SELECTION_CONTEXT: &str = "blake3_fold 2024-01 audit chunk selection";
+const CHALLENGE_ID_CONTEXT: &str = "blake3_fold 2024-01 audit challenge id";
+/// The most chunks one challenge may ask for. Challenges asking for more are turned down when
+/// decoded or checked, so a hostile count cannot make either side draw billions of chunks
+pub const MAX_CHALLENGE_CHUNKS: u32 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChunkSelection {
+    Indices(Vec<u64>),
+    /// `count` distinct chunks drawn from the seed and the challenge nonce, or every chunk if
+    /// the file has fewer. `count` is at most `MAX_CHALLENGE_CHUNKS`
+    Seed {
+        seed: [u8; 32],
+        count: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Challenge {
+    pub root: [u8; 32],
+    pub file_len: u64,
+    pub nonce: [u8; 32],
+    pub selection: ChunkSelection,
+    /// Milliseconds since the Unix epoch
+    pub deadline_unix_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub challenge_id: [u8; 32],
+    pub prover_id: String,
+    /// One proof per challenged chunk, in the order of `Challenge::chunk_indices`
+    pub bundles: Vec<ChunkProof>,
+}
+
+#[derive(Debug)]
+pub enum AuditError {
+    Codec(CodecError),
+    /// The bytes decode, but are not the canonical encoding of what they decode to
+    NonCanonical,
+    ChunkOutOfRange {
+        chunk_idx: u64,
+        n_chunks: u64,
+    },
+    WrongChallenge,
+    /// The challenge asks for more than `MAX_CHALLENGE_CHUNKS` chunks
+    TooManyChunks {
+        count: u64,
+    },
+    Late {
+        deadline_unix_ms: u64,
+        received_unix_ms: u64,
+    },
+    WrongCount {
+        expected: usize,
+        found: usize,
+    },
+    WrongChunk {
+        expected: u64,
+        found: u64,
+    },
+    WrongRoot {
+        chunk_idx: u64,
+    },
+    /// The proof is not bound to the challenge's nonce, so may have been made before it
+    WrongNonce {
+        chunk_idx: u64,
+    },
+    /// The proof's block count or depth are not those of the chunk in a file of this length
+    WrongStatement {
+        chunk_idx: u64,
+    },
+    Proof {
+        chunk_idx: u64,
+        err: ProverError,
+    },
+}
+
+impl From<CodecError> for AuditError {
+    fn from(err: CodecError) -> Self {
+        AuditError::Codec(err)
+    }
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::Codec(err) => write!(f, "{}", err),
+            AuditError::NonCanonical => write!(f, "Not a canonical encoding"),
+            AuditError::ChunkOutOfRange {
+                chunk_idx,
+                n_chunks,
+            } => write!(f, "Chunk {} of a file with {} chunks", chunk_idx, n_chunks),
+            AuditError::WrongChallenge => write!(f, "Response is for another challenge"),
+            AuditError::TooManyChunks { count } => write!(
+                f,
+                "Challenge asks for {} chunks, at most {} are allowed",
+                count, MAX_CHALLENGE_CHUNKS
+            ),
+            AuditError::Late {
+                deadline_unix_ms,
+                received_unix_ms,
+            } => write!(
+                f,
+                "Response received at {} after the deadline {}",
+                received_unix_ms, deadline_unix_ms
+            ),
+            AuditError::WrongCount { expected, found } => {
+                write!(f, "Expected {} proofs, found {}", expected, found)
+            }
+            AuditError::WrongChunk { expected, found } => {
+                write!(
+                    f,
+                    "Expected a proof for chunk {}, found {}",
+                    expected, found
+                )
+            }
+            AuditError::WrongRoot { chunk_idx } => {
+                write!(f, "Proof for chunk {} is for another root", chunk_idx)
+            }
+            AuditError::WrongNonce { chunk_idx } => {
+                write!(f, "Proof for chunk {} is for another nonce", chunk_idx)
+            }
+            AuditError::WrongStatement { chunk_idx } => write!(
+                f,
+                "Proof for chunk {} has the wrong block count or depth",
+                chunk_idx
+            ),
+            AuditError::Proof { chunk_idx, err } => {
+                write!(f, "Proof for chunk {} failed: {}", chunk_idx, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+/// Decode `bytes`, only accepting them if they are how the value is encoded
+fn decode_canonical<T: Serialize + serde::de::DeserializeOwned>(
+    kind: Kind,
+    bytes: &[u8],
+) -> Result<T, AuditError> {
+    let value: T = codec::decode(kind, bytes)?;
+    if codec::encode(kind, &value) != bytes {
+        return Err(AuditError::NonCanonical);
+    }
+    Ok(value)
+}
+
+/// The depth of the leaf `chunk_idx` in the BLAKE3 tree of `n_chunks` chunks, counting the
+/// leaf itself, as `Blake3BlockCompressCircuit` counts `total_depth`
+pub(crate) fn leaf_depth(n_chunks: u64, chunk_idx: u64) -> usize {
+    let (mut n, mut idx, mut depth) = (n_chunks, chunk_idx, 1);
+    // The left subtree holds the largest power of two number of chunks less than n
+    while n > 1 {
+        let left = 1u64 << (63 - (n - 1).leading_zeros());
+        if idx < left {
+            n = left;
+        } else {
+            idx -= left;
+            n -= left;
+        }
+        depth += 1;
+    }
+    depth
+}
+
+impl Challenge {
+    pub fn n_chunks(&self) -> u64 {
+        // An empty file still has one (empty) chunk
+        ((self.file_len + MAX_BYTES_PER_CHUNK as u64 - 1) / MAX_BYTES_PER_CHUNK as u64).max(1)
+    }
+
+    /// The challenged chunks, in the order the response must prove them
+    pub fn chunk_indices(&self) -> Vec<u64> {
+        match &self.selection {
+            ChunkSelection::Indices(indices) => indices.clone(),
+            ChunkSelection::Seed { seed, count } => {
+                let n_chunks = self.n_chunks();
+                let count = (*count).min(MAX_CHALLENGE_CHUNKS) as u64;
+                let count = count.min(n_chunks) as usize;
+                let mut hasher = blake3::Hasher::new_derive_key(SELECTION_CONTEXT);
+                hasher.update(seed);
+                hasher.update(&self.nonce);
+                let mut xof = hasher.finalize_xof();
+                // Rejection sampling keeps every chunk equally likely
+                let zone = u64::MAX - (u64::MAX % n_chunks);
+                let mut indices = Vec::with_capacity(count);
+                let mut drawn = HashSet::with_capacity(count);
+                while indices.len() < count {
+                    let mut buf = [0u8; 8];
+                    xof.fill(&mut buf);
+                    let v = u64::from_le_bytes(buf);
+                    if v < zone && drawn.insert(v % n_chunks) {
+                        indices.push(v % n_chunks);
+                    }
+                }
+                indices
+            }
+        }
+    }
+
+    /// The block count and total depth a proof for `chunk_idx` must have
+    pub fn expected_statement(&self, chunk_idx: u64) -> Result<(usize, usize), AuditError> {
+        let n_chunks = self.n_chunks();
+        if chunk_idx >= n_chunks {
+            return Err(AuditError::ChunkOutOfRange {
+                chunk_idx,
+                n_chunks,
+            });
+        }
+        let start = chunk_idx * MAX_BYTES_PER_CHUNK as u64;
+        let chunk_len = (self.file_len - start).min(MAX_BYTES_PER_CHUNK as u64);
+        Ok((
+            utils::n_blocks_from_bytes(chunk_len as usize),
+            leaf_depth(n_chunks, chunk_idx),
+        ))
+    }
+
+    /// Check a provider's path for `chunk_idx` has the challenged file's shape before proving
+    pub fn check_hash_proof(&self, hash_proof: &Blake3HashProof) -> Result<(), AuditError> {
+        let (n_blocks, total_depth) = self.expected_statement(hash_proof.chunk_idx)?;
+        if utils::n_blocks_from_bytes(hash_proof.bytes.len()) != n_blocks
+            || hash_proof.parent_path.len() + 1 != total_depth
+        {
+            return Err(AuditError::WrongStatement {
+                chunk_idx: hash_proof.chunk_idx,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        codec::encode(Kind::Challenge, self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AuditError> {
+        let challenge: Self = decode_canonical(Kind::Challenge, bytes)?;
+        challenge.check_count()?;
+        Ok(challenge)
+    }
+
+    /// Turn down challenges for more than `MAX_CHALLENGE_CHUNKS` chunks
+    fn check_count(&self) -> Result<(), AuditError> {
+        let count = match &self.selection {
+            ChunkSelection::Indices(indices) => indices.len() as u64,
+            ChunkSelection::Seed { count, .. } => *count as u64,
+        };
+        if count > MAX_CHALLENGE_CHUNKS as u64 {
+            return Err(AuditError::TooManyChunks { count });
+        }
+        Ok(())
+    }
+
+    pub fn id(&self) -> [u8; 32] {
+        blake3::derive_key(CHALLENGE_ID_CONTEXT, &self.to_bytes())
+    }
+
+    /// Check everything about `response` short of verifying its proofs
+    pub fn check_response(
+        &self,
+        response: &Response,
+        received_unix_ms: u64,
+    ) -> Result<(), AuditError> {
+        if response.challenge_id != self.id() {
+            return Err(AuditError::WrongChallenge);
+        }
+        self.check_count()?;
+        if received_unix_ms > self.deadline_unix_ms {
+            return Err(AuditError::Late {
+                deadline_unix_ms: self.deadline_unix_ms,
+                received_unix_ms,
+            });
+        }
+        let indices = self.chunk_indices();
+        if response.bundles.len() != indices.len() {
+            return Err(AuditError::WrongCount {
+                expected: indices.len(),
+                found: response.bundles.len(),
+            });
+        }
+        for (&chunk_idx, proof) in indices.iter().zip(&response.bundles) {
+            if proof.chunk_idx != chunk_idx {
+                return Err(AuditError::WrongChunk {
+                    expected: chunk_idx,
+                    found: proof.chunk_idx,
+                });
+            }
+            if proof.output_hash != self.root {
+                return Err(AuditError::WrongRoot { chunk_idx });
+            }
+            if proof.nonce != Some(self.nonce) {
+                return Err(AuditError::WrongNonce { chunk_idx });
+            }
+            if (proof.n_blocks, proof.total_depth) != self.expected_statement(chunk_idx)? {
+                return Err(AuditError::WrongStatement { chunk_idx });
+            }
+        }
+        Ok(())
+    }
+
+    /// `check_response`, then verify every proof with `verifier`
+    pub fn verify_response(
+        &self,
+        response: &Response,
+        received_unix_ms: u64,
+        verifier: &dyn ChunkProver,
+    ) -> Result<(), AuditError> {
+        self.check_response(response, received_unix_ms)?;
+        for (proof, res) in response
+            .bundles
+            .iter()
+            .zip(verifier.verify_batch(&response.bundles))
+        {
+            res.map_err(|err| AuditError::Proof {
+                chunk_idx: proof.chunk_idx,
+                err,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Response {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        codec::encode(Kind::Response, self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AuditError> {
+        decode_canonical(Kind::Response, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, FakeProver};
+    use crate::blake3_hash::hash_with_path;
+    use crate::proof_stats::ProofStats;
+
+    fn challenge(data: &[u8], selection: ChunkSelection) -> Challenge {
+        Challenge {
+            root: *blake3::hash(data).as_bytes(),
+            file_len: data.len() as u64,
+            nonce: [7u8; 32],
+            selection,
+            deadline_unix_ms: 1000,
+        }
+    }
+
+    /// What the provider would answer, with empty SNARKs
+    fn response(challenge: &Challenge, data: &[u8]) -> Response {
+        let bundles = challenge
+            .chunk_indices()
+            .into_iter()
+            .map(|chunk_idx| {
+                let (hash, hash_proof) = hash_with_path(data, chunk_idx as usize).unwrap();
+                challenge.check_hash_proof(&hash_proof).unwrap();
+                ChunkProof {
+                    backend: Backend::Pasta,
+                    chunk_idx,
+                    n_blocks: utils::n_blocks_from_bytes(hash_proof.bytes.len()),
+                    total_depth: hash_proof.parent_path.len() + 1,
+                    compressions_per_step: 1,
+                    setup_digest: [0u8; 32],
+                    nonce: Some(challenge.nonce),
+                    output_hash: hash.as_bytes().to_vec(),
+                    snark: vec![],
+                    stats: ProofStats::default(),
+                }
+            })
+            .collect();
+        Response {
+            challenge_id: challenge.id(),
+            prover_id: "provider".into(),
+            bundles,
+        }
+    }
+
+    #[test]
+    fn test_leaf_depth() {
+        for n_chunks in [1, 2, 3, 5, 8, 13] {
+            let data = vec![1u8; 1024 * n_chunks];
+            for chunk_idx in 0..n_chunks {
+                let (_, hash_proof) = hash_with_path(&data, chunk_idx).unwrap();
+                assert_eq!(
+                    leaf_depth(n_chunks as u64, chunk_idx as u64),
+                    hash_proof.parent_path.len() + 1
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_seeded_selection() {
+        let data = vec![3u8; 1024 * 20 + 5];
+        let selection = ChunkSelection::Seed {
+            seed: [1u8; 32],
+            count: 5,
+        };
+        let mut c = challenge(&data, selection.clone());
+        let indices = c.chunk_indices();
+        assert_eq!(indices.len(), 5);
+        assert!(indices.iter().all(|&i| i < 21));
+        let mut distinct = indices.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 5);
+        // Deterministic for a nonce, but changes with it
+        assert_eq!(c.chunk_indices(), indices);
+        c.nonce = [8u8; 32];
+        assert_ne!(c.chunk_indices(), indices);
+
+        let small = challenge(&[1u8; 10], selection);
+        assert_eq!(small.chunk_indices(), vec![0]);
+
+        // Every chunk of the file, each once
+        let all = challenge(
+            &data,
+            ChunkSelection::Seed {
+                seed: [2u8; 32],
+                count: 21,
+            },
+        );
+        let mut indices = all.chunk_indices();
+        indices.sort();
+        assert_eq!(indices, (0..21).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_too_many_chunks() {
+        let c = challenge(
+            &[1u8; 10],
+            ChunkSelection::Seed {
+                seed: [1u8; 32],
+                count: u32::MAX,
+            },
+        );
+        // Drawing is capped even for a challenge built in memory
+        assert_eq!(c.chunk_indices(), vec![0]);
+        assert!(matches!(
+            Challenge::from_bytes(&c.to_bytes()),
+            Err(AuditError::TooManyChunks { count }) if count == u32::MAX as u64
+        ));
+        let response = Response {
+            challenge_id: c.id(),
+            prover_id: "p".to_string(),
+            bundles: vec![],
+        };
+        assert!(matches!(
+            c.check_response(&response, 0),
+            Err(AuditError::TooManyChunks { .. })
+        ));
+
+        let indices = challenge(
+            &[1u8; 10],
+            ChunkSelection::Indices(vec![0; MAX_CHALLENGE_CHUNKS as usize + 1]),
+        );
+        assert!(matches!(
+            Challenge::from_bytes(&indices.to_bytes()),
+            Err(AuditError::TooManyChunks { .. })
+        ));
+    }
+
+    #[test]
+    fn test_encoding() {
+        let c = challenge(&[2u8; 3000], ChunkSelection::Indices(vec![2, 0]));
+        let bytes = c.to_bytes();
+        assert_eq!(Challenge::from_bytes(&bytes).unwrap(), c);
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            Challenge::from_bytes(&trailing),
+            Err(AuditError::NonCanonical)
+        ));
+        let mut other = c.clone();
+        other.nonce[0] ^= 1;
+        assert_ne!(other.id(), c.id());
+    }
+
+    #[test]
+    fn test_check_response() {
+        let data = vec![4u8; 1024 * 6 + 100];
+        let c = challenge(&data, ChunkSelection::Indices(vec![6, 1, 3]));
+        let r = response(&c, &data);
+        assert!(c.check_response(&r, 1000).is_ok());
+        let r = Response::from_bytes(&r.to_bytes()).unwrap();
+        assert!(c.check_response(&r, 1000).is_ok());
+
+        assert!(matches!(
+            c.check_response(&r, 1001),
+            Err(AuditError::Late { .. })
+        ));
+        let mut other = c.clone();
+        other.nonce = [0u8; 32];
+        assert!(matches!(
+            other.check_response(&r, 0),
+            Err(AuditError::WrongChallenge)
+        ));
+
+        let mut reordered = r.clone();
+        reordered.bundles.swap(0, 1);
+        assert!(matches!(
+            c.check_response(&reordered, 0),
+            Err(AuditError::WrongChunk { .. })
+        ));
+        let mut missing = r.clone();
+        missing.bundles.pop();
+        assert!(matches!(
+            c.check_response(&missing, 0),
+            Err(AuditError::WrongCount { .. })
+        ));
+        let mut wrong_root = r.clone();
+        wrong_root.bundles[2].output_hash = vec![0u8; 32];
+        assert!(matches!(
+            c.check_response(&wrong_root, 0),
+            Err(AuditError::WrongRoot { .. })
+        ));
+        let mut replayed = r.clone();
+        replayed.bundles[0].nonce = None;
+        assert!(matches!(
+            c.check_response(&replayed, 0),
+            Err(AuditError::WrongNonce { chunk_idx: 6 })
+        ));
+        let mut wrong_depth = r.clone();
+        wrong_depth.bundles[1].total_depth += 1;
+        assert!(matches!(
+            c.check_response(&wrong_depth, 0),
+            Err(AuditError::WrongStatement { .. })
+        ));
+
+        // The fake proofs pass the checks but not verification
+        assert!(matches!(
+            c.verify_response(&r, 0, &FakeProver),
+            Err(AuditError::Proof { chunk_idx: 6, .. })
+        ));
+    }
+}
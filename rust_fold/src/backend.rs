@@ -0,0 +1,479 @@
+//! Choosing the curve cycle at runtime.
+//!
+//! Each cycle needs its own circom artifacts, compiled for its scalar field (see `package.json`),
+//! and its own SNARK types. A `Backend` picks both, and hands out a `ChunkProver` so callers
+//! such as a service can prove and verify without naming any of the engine types.
+use arecibo::errors::NovaError;
+use arecibo::provider::ipa_pc;
+use arecibo::provider::non_hiding_zeromorph::ZMPCS;
+use arecibo::provider::{Bn256EngineZM, GrumpkinEngine, PallasEngine, VestaEngine};
+use arecibo::traits::snark::RelaxedR1CSSNARKTrait;
+use arecibo::traits::Engine;
+use arecibo::{spartan, CompressedSNARK, ProverKey, VerifierKey};
+use ff::Field;
+use halo2curves::bn256::Bn256;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::artifacts::{ArtifactError, ArtifactRegistry};
+use crate::blake3_circuit::{Blake3CompressPubIO, CircomArtifacts};
+use crate::blake3_hash::Blake3HashProof;
+use crate::codec::{self, CodecError, Kind};
+use crate::proof_stats::ProofStats;
+use crate::setup_digest::{self, SetupDigest};
+use crate::{
+    compress_snark, prove_chunk_hash_checkpointed, setup_params, utils, FoldParams, C1, C2,
+    MAX_BLOCKS_PER_CHUNK, MAX_BYTES_PER_CHUNK,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Backend {
+    /// BN256/Grumpkin with ZeroMorph, which the solidity verifier checks
+    Bn256Zm,
+    /// Pallas/Vesta with IPA
+    Pasta,
+}
+
+impl Backend {
+    /// The name of the backend's circuit in `circuits.json`
+    pub fn circuit_name(&self) -> &'static str {
+        match self {
+            Backend::Bn256Zm => "blake3_nova",
+            Backend::Pasta => "blake3_nova_pasta",
+        }
+    }
+
+    /// Load the backend's artifacts and set up its keys for `compressions_per_step`
+    pub fn prover(
+        &self,
+        compressions_per_step: usize,
+    ) -> Result<Box<dyn ChunkProver>, ProverError> {
+        Ok(match self {
+            Backend::Bn256Zm => Box::new(CycleProver::<
+                Bn256EngineZM,
+                GrumpkinEngine,
+                spartan::ppsnark::RelaxedR1CSSNARK<Bn256EngineZM, ZMPCS<Bn256, Bn256EngineZM>>,
+                spartan::ppsnark::RelaxedR1CSSNARK<
+                    GrumpkinEngine,
+                    ipa_pc::EvaluationEngine<GrumpkinEngine>,
+                >,
+            >::setup(*self, compressions_per_step)?),
+            Backend::Pasta => Box::new(CycleProver::<
+                PallasEngine,
+                VestaEngine,
+                spartan::snark::RelaxedR1CSSNARK<
+                    PallasEngine,
+                    ipa_pc::EvaluationEngine<PallasEngine>,
+                >,
+                spartan::snark::RelaxedR1CSSNARK<
+                    VestaEngine,
+                    ipa_pc::EvaluationEngine<VestaEngine>,
+                >,
+            >::setup(*self, compressions_per_step)?),
+        })
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bn256_zm" => Ok(Backend::Bn256Zm),
+            "pasta" => Ok(Backend::Pasta),
+            _ => Err(format!("Unknown backend {}, expected bn256_zm or pasta", s)),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Bn256Zm => write!(f, "bn256_zm"),
+            Backend::Pasta => write!(f, "pasta"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProverError {
+    Artifact(ArtifactError),
+    Nova(NovaError),
+    Codec(bincode::Error),
+    WrongBackend {
+        expected: Backend,
+        found: Backend,
+    },
+    WrongCompressionsPerStep {
+        expected: usize,
+        found: usize,
+    },
+    /// The proof was made with other keys than the verifier's
+    SetupMismatch {
+        expected: SetupDigest,
+        found: SetupDigest,
+    },
+    /// The chunk's block count or depth cannot be those of a real chunk
+    InvalidStatement,
+    /// The chunk has more bytes than a BLAKE3 chunk can hold
+    ChunkTooLarge(usize),
+    /// The proof verified, but for another hash than the one claimed
+    OutputMismatch,
+    /// A step's output is not what the native reference model computes, see
+    /// `CircomArtifacts::with_reference_check`
+    ReferenceMismatch {
+        step: usize,
+        /// Where the step starts, e.g. "leaf block 3" or "parent at depth 2"
+        position: String,
+        /// Each IO element which differs, with the expected and the proven value
+        elements: Vec<(usize, String, String)>,
+    },
+}
+
+impl From<ArtifactError> for ProverError {
+    fn from(err: ArtifactError) -> Self {
+        ProverError::Artifact(err)
+    }
+}
+
+impl From<NovaError> for ProverError {
+    fn from(err: NovaError) -> Self {
+        ProverError::Nova(err)
+    }
+}
+
+impl From<bincode::Error> for ProverError {
+    fn from(err: bincode::Error) -> Self {
+        ProverError::Codec(err)
+    }
+}
+
+impl fmt::Display for ProverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProverError::Artifact(err) => write!(f, "{}", err),
+            ProverError::Nova(err) => write!(f, "Nova error: {:?}", err),
+            ProverError::Codec(err) => write!(f, "Failed to encode or decode the proof: {}", err),
+            ProverError::WrongBackend { expected, found } => {
+                write!(f, "Proof is for backend {}, expected {}", found, expected)
+            }
+            ProverError::WrongCompressionsPerStep { expected, found } => write!(
+                f,
+                "Proof has {} compressions per step, expected {}",
+                found, expected
+            ),
+            ProverError::SetupMismatch { expected, found } => write!(
+                f,
+                "Proof is for verifier key {}, expected {}",
+                setup_digest::to_hex(found),
+                setup_digest::to_hex(expected)
+            ),
+            ProverError::InvalidStatement => write!(f, "Invalid chunk block count or depth"),
+            ProverError::ChunkTooLarge(len) => write!(
+                f,
+                "Chunk has {} bytes, at most {} fit in a chunk",
+                len, MAX_BYTES_PER_CHUNK
+            ),
+            ProverError::OutputMismatch => write!(f, "Proof output does not match its hash"),
+            ProverError::ReferenceMismatch {
+                step,
+                position,
+                elements,
+            } => {
+                write!(
+                    f,
+                    "Step {} (from {}) diverges from the reference model at IO elements",
+                    step, position
+                )?;
+                for (idx, expected, found) in elements {
+                    write!(f, " {} (expected {}, found {})", idx, expected, found)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProverError {}
+
+/// A compressed proof for the path from a chunk to the root, with the statement it proves
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkProof {
+    pub backend: Backend,
+    pub chunk_idx: u64,
+    pub n_blocks: usize,
+    pub total_depth: usize,
+    pub compressions_per_step: usize,
+    /// The digest of the verifier key the proof was made for
+    pub setup_digest: SetupDigest,
+    /// The verifier's nonce the proof is bound to, if it was made for a challenge
+    pub nonce: Option<[u8; 32]>,
+    /// The root hash
+    pub output_hash: Vec<u8>,
+    /// The bincode encoded `CompressedSNARK`
+    pub snark: Vec<u8>,
+    pub stats: ProofStats,
+}
+
+impl ChunkProof {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        codec::encode(Kind::ChunkProof, self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CodecError> {
+        codec::decode(Kind::ChunkProof, bytes)
+    }
+}
+
+pub trait ChunkProver: Send + Sync {
+    fn backend(&self) -> Backend;
+
+    /// Prove the chunk's path, bound to `nonce` if there is one
+    fn prove(
+        &self,
+        hash_proof: Blake3HashProof,
+        nonce: Option<[u8; 32]>,
+    ) -> Result<ChunkProof, ProverError>;
+
+    /// Check `proof` and return the root hash the chunk was proven to be under
+    fn verify(&self, proof: &ChunkProof) -> Result<Vec<u8>, ProverError>;
+
+    /// `verify` every proof, in parallel and sharing this prover's keys
+    fn verify_batch(&self, proofs: &[ChunkProof]) -> Vec<Result<Vec<u8>, ProverError>> {
+        proofs.par_iter().map(|proof| self.verify(proof)).collect()
+    }
+}
+
+struct CycleProver<E1, E2, SS1, SS2>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    backend: Backend,
+    artifacts: CircomArtifacts<<E1 as Engine>::Scalar>,
+    setup_digest: SetupDigest,
+    params: FoldParams<E1, E2>,
+    pk: ProverKey<E1, E2, C1<E1>, C2<E2>, SS1, SS2>,
+    vk: VerifierKey<E1, E2, C1<E1>, C2<E2>, SS1, SS2>,
+}
+
+impl<E1, E2, SS1, SS2> CycleProver<E1, E2, SS1, SS2>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    fn setup(backend: Backend, compressions_per_step: usize) -> Result<Self, ProverError> {
+        let artifacts = ArtifactRegistry::builtin()?
+            .load_artifacts::<<E1 as Engine>::Scalar>(backend.circuit_name())?;
+        let params = setup_params::<E1, E2, SS1, SS2>(&artifacts, compressions_per_step);
+        let (pk, vk) = CompressedSNARK::<_, _, _, _, SS1, SS2>::setup(&params.pp)?;
+        Ok(CycleProver {
+            backend,
+            artifacts,
+            setup_digest: setup_digest::verifier_key_digest(&vk),
+            params,
+            pk,
+            vk,
+        })
+    }
+}
+
+impl<E1, E2, SS1, SS2> ChunkProver for CycleProver<E1, E2, SS1, SS2>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    fn prove(
+        &self,
+        hash_proof: Blake3HashProof,
+        nonce: Option<[u8; 32]>,
+    ) -> Result<ChunkProof, ProverError> {
+        let chunk_idx = hash_proof.chunk_idx;
+        let n_blocks = utils::n_blocks_from_bytes(hash_proof.bytes.len());
+        let total_depth = hash_proof.parent_path.len() + 1;
+        let (output_hash, recursive_snark, stats) = prove_chunk_hash_checkpointed(
+            &self.params,
+            hash_proof,
+            self.artifacts.clone(),
+            nonce.as_ref(),
+            None,
+        )?;
+        let (snark, compress_stats) =
+            compress_snark(&self.params.pp, &self.pk, &self.vk, &recursive_snark)?;
+        Ok(ChunkProof {
+            backend: self.backend,
+            chunk_idx,
+            n_blocks,
+            total_depth,
+            compressions_per_step: self.params.compressions_per_step,
+            setup_digest: self.setup_digest,
+            nonce,
+            output_hash,
+            snark: bincode::serialize(&snark)?,
+            stats: stats.with_compression(&compress_stats),
+        })
+    }
+
+    fn verify(&self, proof: &ChunkProof) -> Result<Vec<u8>, ProverError> {
+        if proof.backend != self.backend {
+            return Err(ProverError::WrongBackend {
+                expected: self.backend,
+                found: proof.backend,
+            });
+        }
+        if proof.compressions_per_step != self.params.compressions_per_step {
+            return Err(ProverError::WrongCompressionsPerStep {
+                expected: self.params.compressions_per_step,
+                found: proof.compressions_per_step,
+            });
+        }
+        if proof.setup_digest != self.setup_digest {
+            return Err(ProverError::SetupMismatch {
+                expected: self.setup_digest,
+                found: proof.setup_digest,
+            });
+        }
+        // Max depth is 64
+        if proof.n_blocks == 0
+            || proof.n_blocks > MAX_BLOCKS_PER_CHUNK
+            || proof.total_depth == 0
+            || proof.total_depth > 64
+        {
+            return Err(ProverError::InvalidStatement);
+        }
+
+        let snark: CompressedSNARK<E1, E2, C1<E1>, C2<E2>, SS1, SS2> =
+            bincode::deserialize(&proof.snark)?;
+        let n_compressions = proof.n_blocks + proof.total_depth - 1;
+        let num_steps = (n_compressions + self.params.compressions_per_step - 1)
+            / self.params.compressions_per_step;
+        let mut z0_primary = Blake3CompressPubIO::<<E1 as Engine>::GE>::initial(
+            proof.chunk_idx,
+            proof.n_blocks,
+            proof.total_depth,
+        );
+        if let Some(nonce) = &proof.nonce {
+            z0_primary = z0_primary.with_nonce(nonce);
+        }
+        let z0_primary = z0_primary.to_vec();
+        let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+        let (zn_primary, _) = snark.verify(&self.vk, num_steps, &z0_primary, &z0_secondary)?;
+
+        let output_hash = utils::format_scalar_blake_hash::<<E1 as Engine>::GE>(
+            zn_primary[2..10].try_into().unwrap(),
+        );
+        if output_hash != proof.output_hash {
+            return Err(ProverError::OutputMismatch);
+        }
+        Ok(output_hash)
+    }
+}
+
+/// Answers straight away with an empty proof for the chunk, for testing what is built around
+/// the provers without proving
+#[cfg(test)]
+pub(crate) struct FakeProver;
+
+#[cfg(test)]
+impl ChunkProver for FakeProver {
+    fn backend(&self) -> Backend {
+        Backend::Pasta
+    }
+
+    fn prove(
+        &self,
+        hash_proof: Blake3HashProof,
+        nonce: Option<[u8; 32]>,
+    ) -> Result<ChunkProof, ProverError> {
+        Ok(ChunkProof {
+            backend: Backend::Pasta,
+            chunk_idx: hash_proof.chunk_idx,
+            n_blocks: 1,
+            total_depth: 1,
+            compressions_per_step: 1,
+            setup_digest: [0u8; 32],
+            nonce,
+            output_hash: vec![],
+            snark: vec![],
+            stats: ProofStats::default(),
+        })
+    }
+
+    fn verify(&self, _proof: &ChunkProof) -> Result<Vec<u8>, ProverError> {
+        Err(ProverError::InvalidStatement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blake3_hash::hash_with_path;
+
+    #[test]
+    fn test_backend_from_str() {
+        for backend in [Backend::Bn256Zm, Backend::Pasta] {
+            assert_eq!(backend.to_string().parse::<Backend>(), Ok(backend));
+        }
+        assert!("bls12_381".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn test_pasta_prover() {
+        let prover = Backend::Pasta.prover(2).unwrap();
+        let data = vec![5u8; 1024 * 3 + 5];
+        let (hash, hash_proof) = hash_with_path(&data, 2).unwrap();
+        let proof = prover.prove(hash_proof.clone(), None).unwrap();
+        let proof = ChunkProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(proof.output_hash, hash.as_bytes().to_vec());
+        assert_eq!(prover.verify(&proof).unwrap(), hash.as_bytes().to_vec());
+
+        let mut other_chunk = proof.clone();
+        other_chunk.chunk_idx = 1;
+        assert!(prover.verify(&other_chunk).is_err());
+        let results = prover.verify_batch(&[proof.clone(), other_chunk]);
+        assert!(results[0].is_ok() && results[1].is_err());
+
+        let mut other_setup = proof.clone();
+        other_setup.setup_digest = [0u8; 32];
+        assert!(matches!(
+            prover.verify(&other_setup),
+            Err(ProverError::SetupMismatch { .. })
+        ));
+
+        let mut other_backend = proof;
+        other_backend.backend = Backend::Bn256Zm;
+        assert!(matches!(
+            prover.verify(&other_backend),
+            Err(ProverError::WrongBackend { .. })
+        ));
+    }
+
+    #[test]
+    fn test_pasta_prover_nonce() {
+        let prover = Backend::Pasta.prover(4).unwrap();
+        let data = vec![6u8; 1024 * 2 + 1];
+        let (hash, hash_proof) = hash_with_path(&data, 1).unwrap();
+        let proof = prover.prove(hash_proof, Some([1u8; 32])).unwrap();
+        assert_eq!(prover.verify(&proof).unwrap(), hash.as_bytes().to_vec());
+
+        // Replaying the proof for another nonce, or for none, fails
+        let mut other_nonce = proof.clone();
+        other_nonce.nonce = Some([2u8; 32]);
+        assert!(prover.verify(&other_nonce).is_err());
+        let mut no_nonce = proof;
+        no_nonce.nonce = None;
+        assert!(prover.verify(&no_nonce).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Verifying many compressed chunk proofs at once, e.g. when auditing every provider of a file.
+//!
+//! arecibo has no way to merge the checks of several Spartan proofs, so each proof is still
+//! verified on its own, exactly as `CompressedSNARK::verify` would. The only gain over a loop
+//! is that the proofs are verified in parallel on a thread pool; entries reference their
+//! verifier key rather than owning it, but nothing else is shared between them.
+use arecibo::errors::NovaError;
+use arecibo::traits::snark::RelaxedR1CSSNARKTrait;
+use arecibo::traits::Engine;
+use arecibo::{CompressedSNARK, VerifierKey};
+use ff::Field;
+use rayon::prelude::*;
+use std::time::Instant;
+use tracing::info;
+
+use crate::{C1, C2};
+
+/// One compressed proof and the statement it should prove
+pub struct BatchEntry<'a, E1, E2, SS1, SS2>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    pub vk: &'a VerifierKey<E1, E2, C1<E1>, C2<E2>, SS1, SS2>,
+    pub z0_primary: Vec<E1::Scalar>,
+    pub num_steps: usize,
+    pub proof: &'a CompressedSNARK<E1, E2, C1<E1>, C2<E2>, SS1, SS2>,
+}
+
+#[derive(Debug)]
+pub struct BatchReport<F> {
+    /// The final primary output of each entry, in order, or why it failed to verify
+    pub results: Vec<Result<Vec<F>, NovaError>>,
+}
+
+impl<F> BatchReport<F> {
+    pub fn all_verified(&self) -> bool {
+        self.results.iter().all(|r| r.is_ok())
+    }
+
+    /// The indices of the entries which failed to verify
+    pub fn failed(&self) -> Vec<usize> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.is_err())
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// Verify every entry on `pool`. Each entry's result is independent of the others
+pub fn verify_batch<E1, E2, SS1, SS2>(
+    entries: &[BatchEntry<E1, E2, SS1, SS2>],
+    pool: &rayon::ThreadPool,
+) -> BatchReport<E1::Scalar>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    // The secondary circuit is trivial, so every proof starts from the same secondary input
+    let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+    let start = Instant::now();
+    let results: Vec<_> = pool.install(|| {
+        entries
+            .par_iter()
+            .map(|entry| {
+                entry
+                    .proof
+                    .verify(entry.vk, entry.num_steps, &entry.z0_primary, &z0_secondary)
+                    .map(|(zn_primary, _)| zn_primary)
+            })
+            .collect()
+    });
+    let report = BatchReport { results };
+    info!(
+        n_proofs = entries.len(),
+        n_failed = report.failed().len(),
+        elapsed = ?start.elapsed(),
+        "Verified batch"
+    );
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arecibo::provider::ipa_pc;
+    use arecibo::provider::{PallasEngine, VestaEngine};
+    use arecibo::spartan;
+
+    use crate::blake3_circuit::{Blake3CompressPubIO, CircomArtifacts};
+    use crate::blake3_hash::hash_with_path;
+    use crate::{
+        compress_snark, prove_chunk_hashes, setup_params, utils, PASTA_CIRCOM_R1CS_PATH,
+        PASTA_CIRCOM_WASM_PATH,
+    };
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = ipa_pc::EvaluationEngine<E1>;
+    type EE2 = ipa_pc::EvaluationEngine<E2>;
+    type S1 = spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+
+    #[test]
+    fn test_verify_batch() {
+        let artifacts =
+            CircomArtifacts::load(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let compressions_per_step = 4;
+        let params = setup_params::<E1, E2, S1, S2>(&artifacts, compressions_per_step);
+        let (pk, vk) = CompressedSNARK::<_, _, _, _, S1, S2>::setup(&params.pp).unwrap();
+
+        let data = vec![9u8; 1024 * 3 + 17];
+        let hash_proofs: Vec<_> = (0..4)
+            .map(|chunk_idx| hash_with_path(&data, chunk_idx).unwrap().1)
+            .collect();
+        let statements: Vec<_> = hash_proofs
+            .iter()
+            .map(|hash_proof| {
+                let n_blocks = utils::n_blocks_from_bytes(hash_proof.bytes.len());
+                let total_depth = hash_proof.parent_path.len() + 1;
+                let num_steps = (n_blocks + total_depth - 1 + compressions_per_step - 1)
+                    / compressions_per_step;
+                (hash_proof.chunk_idx, n_blocks, total_depth, num_steps)
+            })
+            .collect();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let proofs: Vec<_> = prove_chunk_hashes(&params, hash_proofs, &artifacts, &pool)
+            .into_iter()
+            .map(|r| {
+                compress_snark(&params.pp, &pk, &vk, &r.unwrap().1)
+                    .unwrap()
+                    .0
+            })
+            .collect();
+
+        let entry = |proof_idx: usize, chunk_idx: u64| {
+            let (_, n_blocks, total_depth, num_steps) = statements[proof_idx];
+            BatchEntry {
+                vk: &vk,
+                z0_primary: Blake3CompressPubIO::<<E1 as Engine>::GE>::initial(
+                    chunk_idx,
+                    n_blocks,
+                    total_depth,
+                )
+                .to_vec(),
+                num_steps,
+                proof: &proofs[proof_idx],
+            }
+        };
+        let mut entries: Vec<_> = statements
+            .iter()
+            .enumerate()
+            .map(|(i, statement)| entry(i, statement.0))
+            .collect();
+        // A proof for chunk 0 claimed for chunk 1
+        entries.insert(1, entry(0, 1));
+
+        // Against verifying the proofs one after the other
+        let start = Instant::now();
+        let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+        let sequential: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                entry
+                    .proof
+                    .verify(entry.vk, entry.num_steps, &entry.z0_primary, &z0_secondary)
+                    .is_ok()
+            })
+            .collect();
+        let sequential_elapsed = start.elapsed();
+        let start = Instant::now();
+        let report = verify_batch(&entries, &pool);
+        let batch_elapsed = start.elapsed();
+        info!(
+            ?sequential_elapsed,
+            ?batch_elapsed,
+            "Batch against sequential verification"
+        );
+        assert_eq!(
+            report.results.iter().map(|r| r.is_ok()).collect::<Vec<_>>(),
+            sequential
+        );
+
+        assert!(!report.all_verified());
+        assert_eq!(report.failed(), vec![1]);
+        let outputs: Vec<_> = report.results.into_iter().flatten().collect();
+        // Every chunk is under the same root
+        assert_eq!(outputs.len(), 4);
+        assert!(outputs.iter().all(|zn| zn[2..10] == outputs[0][2..10]));
+    }
+}
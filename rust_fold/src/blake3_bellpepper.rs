@@ -0,0 +1,804 @@
+//! A BLAKE3 step circuit written directly against bellpepper gadgets.
+//!
+//! `Blake3BellpepperCircuit` has the same 16 element public IO and the same step semantics as
+//! `Blake3BlockCompressCircuit` (see `circuits/blake3_nova.circom`), but does not need any
+//! compiled circom artifacts (`.wasm`/`.r1cs`) at runtime.
+use arecibo::errors::NovaError;
+use arecibo::traits::circuit::{StepCircuit, TrivialCircuit};
+use arecibo::traits::snark::RelaxedR1CSSNARKTrait;
+use arecibo::traits::{Engine, Group};
+use arecibo::{PublicParams, RecursiveSNARK};
+use bellpepper::gadgets::multieq::MultiEq;
+use bellpepper::gadgets::uint32::UInt32;
+use bellpepper_core::boolean::{AllocatedBit, Boolean};
+use bellpepper_core::num::AllocatedNum;
+use bellpepper_core::{ConstraintSystem, LinearCombination, SynthesisError, Variable};
+use ff::{Field, PrimeField, PrimeFieldBits};
+use std::time::Instant;
+use tracing::{info, info_span};
+
+use crate::blake3_circuit::{
+    leaf_block_words, parent_block_words, Blake3CompressPubIO, CircuitCursor, PathNode, IO_ARITY,
+    IV, MSG_PERMUTATION,
+};
+use crate::blake3_hash::Blake3HashProof;
+use crate::{utils, C2};
+
+const CHUNK_START: usize = 0;
+const CHUNK_END: usize = 1;
+const PARENT: usize = 2;
+const ROOT: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct Blake3BellpepperCircuit<G: Group> {
+    leaf_bytes: Vec<u8>,
+    pub(crate) n_bytes: usize,
+    pub(crate) total_depth: usize,
+    pub(crate) n_blocks: usize,
+    cursor: CircuitCursor,
+    parent_path: Vec<PathNode>,
+    _p: std::marker::PhantomData<G>,
+}
+
+impl<G: Group> Blake3BellpepperCircuit<G> {
+    pub fn new(bytes: Vec<u8>, parent_path: Vec<PathNode>) -> Blake3BellpepperCircuit<G> {
+        let bytes_len = bytes.len();
+        let n_blocks = utils::n_blocks_from_bytes(bytes_len);
+        let depth = parent_path.len() + 1;
+
+        Blake3BellpepperCircuit {
+            n_bytes: bytes_len,
+            n_blocks,
+            leaf_bytes: bytes,
+            parent_path,
+            total_depth: depth,
+            cursor: CircuitCursor::new(depth),
+            _p: std::marker::PhantomData,
+        }
+    }
+
+    /// One step per block plus one per parent above the leaf
+    pub fn num_steps(&self) -> usize {
+        self.n_blocks + self.total_depth - 1
+    }
+
+    pub fn update_for_step(&mut self) {
+        self.cursor.advance(self.n_blocks, self.total_depth);
+    }
+
+    /// The private message words and byte count for the current step
+    fn message_block(&self) -> (Vec<u32>, u64) {
+        if self.cursor.is_leaf(self.n_blocks) {
+            leaf_block_words(&self.leaf_bytes, self.cursor.block())
+        } else {
+            // We always have b=64 for a parent block
+            (
+                parent_block_words(&self.parent_path[self.cursor.depth()]),
+                64,
+            )
+        }
+    }
+}
+
+/// A linear combination together with its value, if known
+#[derive(Clone)]
+pub(crate) struct Expr<F: PrimeField> {
+    pub(crate) value: Option<F>,
+    pub(crate) lc: LinearCombination<F>,
+}
+
+impl<F: PrimeField> Expr<F> {
+    pub(crate) fn num(num: &AllocatedNum<F>) -> Self {
+        Expr {
+            value: num.get_value(),
+            lc: LinearCombination::zero() + num.get_variable(),
+        }
+    }
+
+    pub(crate) fn constant(one: Variable, c: F) -> Self {
+        Expr {
+            value: Some(c),
+            lc: LinearCombination::zero() + (c, one),
+        }
+    }
+
+    pub(crate) fn bit(one: Variable, bit: &Boolean) -> Self {
+        Expr {
+            value: bit.get_value().map(|b| if b { F::ONE } else { F::ZERO }),
+            lc: bit.lc(one, F::ONE),
+        }
+    }
+
+    pub(crate) fn add(&self, other: &Self) -> Self {
+        Expr {
+            value: self.value.zip(other.value).map(|(a, b)| a + b),
+            lc: self.lc.clone() + &other.lc,
+        }
+    }
+
+    pub(crate) fn sub(&self, other: &Self) -> Self {
+        Expr {
+            value: self.value.zip(other.value).map(|(a, b)| a - b),
+            lc: self.lc.clone() - &other.lc,
+        }
+    }
+}
+
+pub(crate) fn pack_bits<F: PrimeField>(one: Variable, bits: &[Boolean]) -> Expr<F> {
+    let mut packed = Expr::constant(one, F::ZERO);
+    let mut coeff = F::ONE;
+    for bit in bits {
+        let value = packed
+            .value
+            .zip(bit.get_value())
+            .map(|(v, b)| if b { v + coeff } else { v });
+        packed = Expr {
+            value,
+            lc: packed.lc + &bit.lc(one, coeff),
+        };
+        coeff = coeff.double();
+    }
+    packed
+}
+
+/// Allocate a number constrained to equal `e`
+pub(crate) fn alloc_expr<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    e: &Expr<F>,
+) -> Result<AllocatedNum<F>, SynthesisError> {
+    let num = AllocatedNum::alloc(cs.namespace(|| "alloc"), || {
+        e.value.ok_or(SynthesisError::AssignmentMissing)
+    })?;
+    cs.enforce(
+        || "alloc equals expression",
+        |lc| lc + num.get_variable(),
+        |lc| lc + CS::one(),
+        |_| e.lc.clone(),
+    );
+    Ok(num)
+}
+
+/// Little endian decomposition of `e` into `n` bits. This also range checks `e` to `n` bits
+pub(crate) fn to_bits<F: PrimeFieldBits, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    e: &Expr<F>,
+    n: usize,
+) -> Result<Vec<Boolean>, SynthesisError> {
+    let values: Vec<Option<bool>> = match e.value {
+        Some(v) => {
+            let bits = v.to_le_bits();
+            (0..n).map(|i| Some(bits[i])).collect()
+        }
+        None => vec![None; n],
+    };
+    let bits = values
+        .into_iter()
+        .enumerate()
+        .map(|(i, b)| {
+            AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), b).map(Boolean::from)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let packed = pack_bits::<F>(CS::one(), &bits);
+    cs.enforce(
+        || "pack bits",
+        |_| packed.lc,
+        |lc| lc + CS::one(),
+        |_| e.lc.clone(),
+    );
+    Ok(bits)
+}
+
+pub(crate) fn num_to_uint32<F: PrimeFieldBits, CS: ConstraintSystem<F>>(
+    cs: CS,
+    num: &AllocatedNum<F>,
+) -> Result<UInt32, SynthesisError> {
+    Ok(UInt32::from_bits(&to_bits(cs, &Expr::num(num), 32)?))
+}
+
+pub(crate) fn uint32_to_num<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: CS,
+    word: &UInt32,
+) -> Result<AllocatedNum<F>, SynthesisError> {
+    alloc_expr(cs, &pack_bits(CS::one(), &word.clone().into_bits()))
+}
+
+/// 1 iff `e == 0`
+pub(crate) fn is_zero<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    e: &Expr<F>,
+) -> Result<Boolean, SynthesisError> {
+    let out = AllocatedBit::alloc(cs.namespace(|| "out"), e.value.map(|v| v == F::ZERO))?;
+    let inv = AllocatedNum::alloc(cs.namespace(|| "inv"), || {
+        let v = e.value.ok_or(SynthesisError::AssignmentMissing)?;
+        Ok(Option::<F>::from(v.invert()).unwrap_or(F::ZERO))
+    })?;
+    // e * inv = 1 - out
+    cs.enforce(
+        || "e * inv = 1 - out",
+        |_| e.lc.clone(),
+        |lc| lc + inv.get_variable(),
+        |lc| lc + CS::one() - out.get_variable(),
+    );
+    // e * out = 0
+    cs.enforce(
+        || "e * out = 0",
+        |_| e.lc.clone(),
+        |lc| lc + out.get_variable(),
+        |lc| lc,
+    );
+    Ok(Boolean::from(out))
+}
+
+pub(crate) fn is_equal<F: PrimeField, CS: ConstraintSystem<F>>(
+    cs: CS,
+    a: &Expr<F>,
+    b: &Expr<F>,
+) -> Result<Boolean, SynthesisError> {
+    is_zero(cs, &a.sub(b))
+}
+
+/// 1 iff `a < b`, where both are `n` bit numbers. Mirrors circomlib's `LessThan(n)`
+pub(crate) fn less_than<F: PrimeFieldBits, CS: ConstraintSystem<F>>(
+    cs: CS,
+    a: &Expr<F>,
+    b: &Expr<F>,
+    n: usize,
+) -> Result<Boolean, SynthesisError> {
+    let shifted = a.add(&Expr::constant(CS::one(), F::from(1u64 << n))).sub(b);
+    let bits = to_bits(cs, &shifted, n + 1)?;
+    Ok(bits[n].not())
+}
+
+/// 1 iff the path descends to the left child at `depth`, for a path `total_depth` long to the
+/// chunk with index bits `idx_bits` (little endian). The bit which selects the child at a depth
+/// is at `total_depth - depth - 2`, as bit 0 is the leaf's parent. 0 at depths no bit selects
+pub(crate) fn goes_left_at_depth<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    depth: &Expr<F>,
+    total_depth: &Expr<F>,
+    idx_bits: &[Boolean],
+) -> Result<Expr<F>, SynthesisError> {
+    let one = CS::one();
+    let mut bit_at_depth = Expr::constant(one, F::ZERO);
+    for (i, bit) in idx_bits.iter().enumerate() {
+        let at_depth = is_equal(
+            cs.namespace(|| format!("at depth {}", i)),
+            depth,
+            &total_depth.sub(&Expr::constant(one, F::from(i as u64 + 2))),
+        )?;
+        let goes_left = Boolean::and(
+            cs.namespace(|| format!("goes left {}", i)),
+            &bit.not(),
+            &at_depth,
+        )?;
+        bit_at_depth = bit_at_depth.add(&Expr::bit(one, &goes_left));
+    }
+    Ok(bit_at_depth)
+}
+
+/// `cond ? if_true : if_false`
+pub(crate) fn mux<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    cond: &Boolean,
+    if_true: &Expr<F>,
+    if_false: &Expr<F>,
+) -> Result<AllocatedNum<F>, SynthesisError> {
+    let value = match cond.get_value() {
+        Some(true) => if_true.value,
+        Some(false) => if_false.value,
+        None => None,
+    };
+    let out = AllocatedNum::alloc(cs.namespace(|| "mux"), || {
+        value.ok_or(SynthesisError::AssignmentMissing)
+    })?;
+    // cond * (if_true - if_false) = out - if_false
+    cs.enforce(
+        || "mux",
+        |_| cond.lc(CS::one(), F::ONE),
+        |_| if_true.lc.clone() - &if_false.lc,
+        |lc| lc + out.get_variable() - &if_false.lc,
+    );
+    Ok(out)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn mixing_g<F: PrimeField, CS: ConstraintSystem<F>, M>(
+    mut cs: M,
+    v: &mut [UInt32],
+    a: usize,
+    b: usize,
+    c: usize,
+    d: usize,
+    x: &UInt32,
+    y: &UInt32,
+) -> Result<(), SynthesisError>
+where
+    M: ConstraintSystem<F, Root = MultiEq<F, CS>>,
+{
+    v[a] = UInt32::addmany(
+        cs.namespace(|| "mixing step 1"),
+        &[v[a].clone(), v[b].clone(), x.clone()],
+    )?;
+    v[d] = v[d].xor(cs.namespace(|| "mixing step 2"), &v[a])?.rotr(16);
+    v[c] = UInt32::addmany(
+        cs.namespace(|| "mixing step 3"),
+        &[v[c].clone(), v[d].clone()],
+    )?;
+    v[b] = v[b].xor(cs.namespace(|| "mixing step 4"), &v[c])?.rotr(12);
+    v[a] = UInt32::addmany(
+        cs.namespace(|| "mixing step 5"),
+        &[v[a].clone(), v[b].clone(), y.clone()],
+    )?;
+    v[d] = v[d].xor(cs.namespace(|| "mixing step 6"), &v[a])?.rotr(8);
+    v[c] = UInt32::addmany(
+        cs.namespace(|| "mixing step 7"),
+        &[v[c].clone(), v[d].clone()],
+    )?;
+    v[b] = v[b].xor(cs.namespace(|| "mixing step 8"), &v[c])?.rotr(7);
+
+    Ok(())
+}
+
+/// The BLAKE3 compression function, returning the 8 word output chaining value
+pub(crate) fn blake3_compression<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    h: &[UInt32],
+    m: &[UInt32],
+    t: [UInt32; 2],
+    b: UInt32,
+    d: UInt32,
+) -> Result<Vec<UInt32>, SynthesisError> {
+    assert_eq!(h.len(), 8);
+    assert_eq!(m.len(), 16);
+
+    let mut v = Vec::with_capacity(16);
+    v.extend_from_slice(h);
+    v.extend(IV[..4].iter().map(|iv| UInt32::constant(*iv)));
+    let [t0, t1] = t;
+    v.extend([t0, t1, b, d]);
+
+    {
+        let mut cs = MultiEq::new(&mut cs);
+        let mut m = m.to_vec();
+        for round in 0..7 {
+            let mut cs = cs.namespace(|| format!("round {}", round));
+            const GS: [(usize, usize, usize, usize); 8] = [
+                (0, 4, 8, 12),
+                (1, 5, 9, 13),
+                (2, 6, 10, 14),
+                (3, 7, 11, 15),
+                (0, 5, 10, 15),
+                (1, 6, 11, 12),
+                (2, 7, 8, 13),
+                (3, 4, 9, 14),
+            ];
+            for (i, (a, b, c, d)) in GS.iter().enumerate() {
+                mixing_g(
+                    cs.namespace(|| format!("mixing invocation {}", i)),
+                    &mut v,
+                    *a,
+                    *b,
+                    *c,
+                    *d,
+                    &m[2 * i],
+                    &m[2 * i + 1],
+                )?;
+            }
+            // No permutation after the last round
+            if round < 6 {
+                m = MSG_PERMUTATION.iter().map(|j| m[*j].clone()).collect();
+            }
+        }
+    }
+
+    (0..8)
+        .map(|i| v[i].xor(cs.namespace(|| format!("output xor {}", i)), &v[i + 8]))
+        .collect()
+}
+
+impl<G: Group> StepCircuit<G::Scalar> for Blake3BellpepperCircuit<G> {
+    fn arity(&self) -> usize {
+        IO_ARITY
+    }
+
+    fn synthesize<CS: ConstraintSystem<G::Scalar>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<G::Scalar>],
+    ) -> Result<Vec<AllocatedNum<G::Scalar>>, SynthesisError> {
+        self.synthesize_with_message(cs, z).map(|(out, _)| out)
+    }
+}
+
+impl<G: Group> Blake3BellpepperCircuit<G> {
+    /// `synthesize`, also returning the 16 private message words of the step so that a circuit
+    /// built around this one can put constraints on what was hashed
+    pub(crate) fn synthesize_with_message<CS: ConstraintSystem<G::Scalar>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<G::Scalar>],
+    ) -> Result<(Vec<AllocatedNum<G::Scalar>>, Vec<AllocatedNum<G::Scalar>>), SynthesisError> {
+        assert_eq!(z.len(), IO_ARITY);
+        let one = CS::one();
+        // Same layout as `Blake3CompressPubIO::to_vec`
+        let n_blocks = &z[0];
+        let block_count = Expr::num(&z[1]);
+        let h = &z[2..10];
+        let total_depth = Expr::num(&z[10]);
+        let depth = Expr::num(&z[11]);
+        let chunk_idx_low = Expr::num(&z[12]);
+        let chunk_idx_high = Expr::num(&z[13]);
+        let leaf_depth = Expr::num(&z[14]);
+
+        /************************* Private input ***********************/
+        let (m_words, b) = self.message_block();
+        let m = m_words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("m {}", i)), || {
+                    Ok(G::Scalar::from(*w as u64))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let b = UInt32::alloc(cs.namespace(|| "b"), Some(b as u32))?;
+
+        /************************* Get depth ***********************/
+        let is_root = is_equal(
+            cs.namespace(|| "is_root"),
+            &depth,
+            &Expr::constant(one, G::Scalar::ZERO),
+        )?;
+        // Max depth is 64
+        to_bits(cs.namespace(|| "depth range"), &depth, 8)?;
+        to_bits(cs.namespace(|| "leaf_depth range"), &leaf_depth, 8)?;
+        let is_parent = less_than(
+            cs.namespace(|| "is_parent"),
+            &depth,
+            &leaf_depth.sub(&Expr::constant(one, G::Scalar::ONE)),
+            8,
+        )?;
+        let below_leaf = less_than(cs.namespace(|| "exceed_depth"), &depth, &leaf_depth, 8)?;
+        Boolean::enforce_equal(
+            cs.namespace(|| "depth below leaf"),
+            &below_leaf,
+            &Boolean::constant(true),
+        )?;
+        let not_parent = is_parent.not();
+
+        /************************* Get flags ***********************/
+        let is_first = is_equal(
+            cs.namespace(|| "is_first"),
+            &block_count,
+            &Expr::constant(one, G::Scalar::ZERO),
+        )?;
+        let is_last = is_equal(
+            cs.namespace(|| "is_last"),
+            &block_count,
+            &Expr::num(n_blocks).sub(&Expr::constant(one, G::Scalar::ONE)),
+        )?;
+        let mut flags = vec![Boolean::constant(false); 32];
+        flags[CHUNK_START] = Boolean::and(cs.namespace(|| "chunk start"), &is_first, &not_parent)?;
+        // We are the last block of a chunk iff we are a leaf and last block of leaf
+        flags[CHUNK_END] = Boolean::and(cs.namespace(|| "chunk end"), &is_last, &not_parent)?;
+        flags[PARENT] = is_parent.clone();
+        let root_candidate = Boolean::or(cs.namespace(|| "root candidate"), &is_parent, &is_last)?;
+        flags[ROOT] = Boolean::and(cs.namespace(|| "root"), &root_candidate, &is_root)?;
+        let is_last_block = flags[CHUNK_END].clone();
+        let d = UInt32::from_bits(&flags);
+
+        /************************* Get the child side ***********************/
+        let low_bits = to_bits(cs.namespace(|| "chunk_idx_low bits"), &chunk_idx_low, 32)?;
+        let high_bits = to_bits(cs.namespace(|| "chunk_idx_high bits"), &chunk_idx_high, 32)?;
+        let idx_bits: Vec<Boolean> = low_bits.iter().chain(high_bits.iter()).cloned().collect();
+
+        let bit_at_depth = goes_left_at_depth(
+            cs.namespace(|| "goes left"),
+            &depth,
+            &total_depth,
+            &idx_bits,
+        )?;
+        // For leaves this does not matter, so we always go left
+        let down_left_value = match (is_parent.get_value(), bit_at_depth.value) {
+            (Some(false), _) => Some(true),
+            (Some(true), Some(v)) => Some(v == G::Scalar::ONE),
+            _ => None,
+        };
+        let down_left = AllocatedBit::alloc(cs.namespace(|| "down left"), down_left_value)?;
+        // is_parent * bit_at_depth = down_left - not_parent
+        cs.enforce(
+            || "down left",
+            |_| is_parent.lc(one, G::Scalar::ONE),
+            |_| bit_at_depth.lc.clone(),
+            |lc| lc + down_left.get_variable() - &not_parent.lc(one, G::Scalar::ONE),
+        );
+        let down_left = Boolean::from(down_left);
+
+        /************************* Final message and chaining value ***********************/
+        let mut final_m = Vec::with_capacity(16);
+        for i in 0..16 {
+            let mut cs = cs.namespace(|| format!("final m {}", i));
+            // For the parent m[0..8] is the CV of the sibling which is not on the path
+            let (if_left, if_right) = if i < 8 {
+                (Expr::num(&h[i]), Expr::num(&m[i]))
+            } else {
+                (Expr::num(&m[i - 8]), Expr::num(&h[i - 8]))
+            };
+            let m_is_parent = mux(cs.namespace(|| "side"), &down_left, &if_left, &if_right)?;
+            let out = mux(
+                cs.namespace(|| "parent"),
+                &is_parent,
+                &Expr::num(&m_is_parent),
+                &Expr::num(&m[i]),
+            )?;
+            final_m.push(num_to_uint32(cs.namespace(|| "word"), &out)?);
+        }
+
+        let mut h_compression = Vec::with_capacity(8);
+        for i in 0..8 {
+            let mut cs = cs.namespace(|| format!("h compression {}", i));
+            let out = mux(
+                cs.namespace(|| "iv"),
+                &is_parent,
+                &Expr::constant(one, G::Scalar::from(IV[i] as u64)),
+                &Expr::num(&h[i]),
+            )?;
+            h_compression.push(num_to_uint32(cs.namespace(|| "word"), &out)?);
+        }
+
+        // Parents always use a counter of 0
+        let mut t = Vec::with_capacity(2);
+        for (name, bits) in [("t0", &low_bits), ("t1", &high_bits)] {
+            let masked = bits
+                .iter()
+                .enumerate()
+                .map(|(i, bit)| {
+                    Boolean::and(cs.namespace(|| format!("{} {}", name, i)), bit, &not_parent)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            t.push(UInt32::from_bits(&masked));
+        }
+        let t: [UInt32; 2] = t.try_into().unwrap();
+
+        /************************* Compress ***********************/
+        let h_out = blake3_compression(
+            cs.namespace(|| "blake3 compression"),
+            &h_compression,
+            &final_m,
+            t,
+            b,
+            d,
+        )?;
+
+        /************************* Public outputs ***********************/
+        let h_out = h_out
+            .iter()
+            .enumerate()
+            .map(|(i, word)| uint32_to_num(cs.namespace(|| format!("h_out {}", i)), word))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Only update the block count if we are not a parent
+        let block_count_out = alloc_expr(
+            cs.namespace(|| "block_count_out"),
+            &block_count.add(&Expr::bit(one, &not_parent)),
+        )?;
+
+        // Decrement if (chunk end or is parent) and (not root)
+        let decr_candidate = Boolean::or(
+            cs.namespace(|| "decr candidate"),
+            &is_last_block,
+            &is_parent,
+        )?;
+        let decr_depth = Boolean::and(
+            cs.namespace(|| "decr depth"),
+            &decr_candidate,
+            &is_root.not(),
+        )?;
+        let depth_out = alloc_expr(
+            cs.namespace(|| "depth_out"),
+            &depth.sub(&Expr::bit(one, &decr_depth)),
+        )?;
+
+        let mut out = Vec::with_capacity(IO_ARITY);
+        out.push(n_blocks.clone());
+        out.push(block_count_out);
+        out.extend(h_out);
+        out.push(z[10].clone());
+        out.push(depth_out);
+        out.push(z[12].clone());
+        out.push(z[13].clone());
+        out.push(z[14].clone());
+        // The nonce is passed through
+        out.push(z[15].clone());
+        Ok((out, m))
+    }
+}
+
+/// Prove the path from a chunk to the root with `Blake3BellpepperCircuit` as the step circuit,
+/// which needs no compiled circom artifacts. One compression is folded per step.
+pub fn prove_chunk_hash_bellpepper<E1, E2, SS1, SS2>(
+    hash_proof: Blake3HashProof,
+) -> Result<
+    (
+        Vec<u8>,
+        PublicParams<E1, E2, Blake3BellpepperCircuit<<E1 as Engine>::GE>, C2<E2>>,
+        RecursiveSNARK<E1, E2, Blake3BellpepperCircuit<<E1 as Engine>::GE>, C2<E2>>,
+    ),
+    NovaError,
+>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    let _span = info_span!(
+        "prove_chunk_hash_bellpepper",
+        chunk_idx = hash_proof.chunk_idx
+    )
+    .entered();
+    let mut circuit_primary = Blake3BellpepperCircuit::<<E1 as Engine>::GE>::new(
+        hash_proof.bytes,
+        hash_proof.parent_path,
+    );
+    let circuit_secondary = TrivialCircuit::default();
+    let num_steps = circuit_primary.num_steps();
+
+    let start = Instant::now();
+    let pp = PublicParams::<E1, E2, _, C2<E2>>::setup(
+        &circuit_primary,
+        &circuit_secondary,
+        &*SS1::ck_floor(),
+        &*SS2::ck_floor(),
+    );
+    info!(elapsed = ?start.elapsed(), "PublicParams::setup");
+
+    let total_depth = <E1 as Engine>::Scalar::from(circuit_primary.total_depth as u64);
+    let z0_primary = Blake3CompressPubIO::<<E1 as Engine>::GE>::new(
+        hash_proof.chunk_idx,
+        total_depth,
+        <E1 as Engine>::Scalar::from(circuit_primary.n_blocks as u64),
+        IV.iter()
+            .map(|iv| <E1 as Engine>::Scalar::from(*iv as u64))
+            .collect(),
+        total_depth,
+    )
+    .to_vec();
+    let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+
+    let mut recursive_snark = RecursiveSNARK::new(
+        &pp,
+        &circuit_primary,
+        &circuit_secondary,
+        &z0_primary,
+        &z0_secondary,
+    )?;
+    for i in 0..num_steps {
+        let start = Instant::now();
+        recursive_snark.prove_step(&pp, &circuit_primary, &circuit_secondary)?;
+        info!(step = i, num_steps, elapsed = ?start.elapsed(), "RecursiveSNARK::prove_step");
+        circuit_primary.update_for_step();
+    }
+
+    let start = Instant::now();
+    let (zn_primary, _) = recursive_snark.verify(&pp, num_steps, &z0_primary, &z0_secondary)?;
+    info!(elapsed = ?start.elapsed(), "RecursiveSNARK::verify");
+
+    let output_hash = utils::format_scalar_blake_hash::<<E1 as Engine>::GE>(
+        zn_primary[2..10].try_into().unwrap(),
+    );
+    Ok((output_hash, pp, recursive_snark))
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::PallasEngine;
+    use arecibo::traits::Engine;
+    use bellpepper_core::test_cs::TestConstraintSystem;
+    use tracing::debug;
+
+    use super::*;
+    use crate::blake3_circuit::{Blake3BlockCompressCircuit, Blake3CompressPubIO};
+    use crate::blake3_hash::hash_with_path;
+    use crate::{PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_WASM_PATH};
+
+    type G = <PallasEngine as Engine>::GE;
+    type F = <PallasEngine as Engine>::Scalar;
+
+    /// Run one step of `circuit` on `z` in a fresh test constraint system
+    fn run_step<C: StepCircuit<F>>(circuit: &C, z: &[F]) -> (Vec<F>, usize) {
+        let mut cs = TestConstraintSystem::<F>::new();
+        let z = z
+            .iter()
+            .enumerate()
+            .map(|(i, v)| AllocatedNum::alloc(cs.namespace(|| format!("z {}", i)), || Ok(*v)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let out = circuit.synthesize(&mut cs, &z).unwrap();
+        assert!(
+            cs.is_satisfied(),
+            "Unsatisfied constraint: {:?}",
+            cs.which_is_unsatisfied()
+        );
+        let out = out.iter().map(|x| x.get_value().unwrap()).collect();
+        (out, cs.num_constraints())
+    }
+
+    /// Step both circuits through every fold, checking that their outputs agree and that
+    /// the final chaining value is the BLAKE3 hash
+    fn check_equivalent(data: &[u8], chunk_idx: usize) {
+        let (hash, hash_proof) = hash_with_path(data, chunk_idx).unwrap();
+        let mut circom_circuit = Blake3BlockCompressCircuit::<G>::new(
+            hash_proof.bytes.clone(),
+            hash_proof.parent_path.clone(),
+            PASTA_CIRCOM_WASM_PATH.to_string(),
+            PASTA_CIRCOM_R1CS_PATH.to_string(),
+        );
+        let mut native_circuit =
+            Blake3BellpepperCircuit::<G>::new(hash_proof.bytes, hash_proof.parent_path);
+
+        let total_depth = F::from(native_circuit.total_depth as u64);
+        let mut z = Blake3CompressPubIO::<G>::new(
+            hash_proof.chunk_idx,
+            total_depth,
+            F::from(native_circuit.n_blocks as u64),
+            IV.iter().map(|iv| F::from(*iv as u64)).collect(),
+            total_depth,
+        )
+        .to_vec();
+
+        let num_steps = native_circuit.n_blocks + native_circuit.total_depth - 1;
+        for i in 0..num_steps {
+            let (circom_out, circom_constraints) = run_step(&circom_circuit, &z);
+            let (native_out, native_constraints) = run_step(&native_circuit, &z);
+            assert_eq!(native_out, circom_out, "Output mismatch at step {}", i);
+            debug!(
+                step = i,
+                circom_constraints, native_constraints, "Constraints per step"
+            );
+            assert!(native_constraints < circom_constraints);
+
+            z = native_out;
+            circom_circuit.update_for_step();
+            native_circuit.update_for_step();
+        }
+
+        let output_hash = utils::format_scalar_blake_hash::<G>(z[2..10].try_into().unwrap());
+        assert_eq!(output_hash, hash.as_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_bellpepper_single_chunk() {
+        check_equivalent(&[117u8; 17], 0);
+        check_equivalent(&[0u8; 68], 0);
+        check_equivalent(&[0u8; 1024], 0);
+    }
+
+    #[test]
+    fn test_bellpepper_path() {
+        let data = vec![0u8; 1024 * 3 + 5];
+        check_equivalent(&data, 2);
+        check_equivalent(&data, 3);
+    }
+
+    #[test]
+    fn test_prove_bellpepper() {
+        type E1 = PallasEngine;
+        type E2 = arecibo::provider::VestaEngine;
+        type S1 = arecibo::spartan::snark::RelaxedR1CSSNARK<
+            E1,
+            arecibo::provider::ipa_pc::EvaluationEngine<E1>,
+        >;
+        type S2 = arecibo::spartan::snark::RelaxedR1CSSNARK<
+            E2,
+            arecibo::provider::ipa_pc::EvaluationEngine<E2>,
+        >;
+        let data = vec![5u8; 1024 * 3 + 5];
+        for chunk_idx in [0, 3] {
+            let (hash, hash_proof) = hash_with_path(&data, chunk_idx).unwrap();
+            let (output_hash, _, _) =
+                prove_chunk_hash_bellpepper::<E1, E2, S1, S2>(hash_proof).unwrap();
+            assert_eq!(output_hash, hash.as_bytes().to_vec());
+        }
+    }
+}
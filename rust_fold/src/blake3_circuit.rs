@@ -1,23 +1,49 @@
 use arecibo::traits::circuit::StepCircuit;
 use arecibo::traits::Group;
+use bellpepper_core::boolean::Boolean;
 use bellpepper_core::num::AllocatedNum;
-use bellpepper_core::ConstraintSystem;
-use circom_scotia::{calculate_witness, r1cs::CircomConfig};
-use ff::Field;
-use std::str::FromStr;
-use std::{cmp::min, path::PathBuf};
+use bellpepper_core::{ConstraintSystem, SynthesisError};
+use circom_scotia::r1cs::{CircomConfig, R1CS};
+use circom_scotia::{calculate_witness, reader::load_r1cs};
+use ff::{Field, PrimeField};
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{debug, error, trace};
 
+use crate::artifacts::{check_r1cs, ArtifactError};
+use crate::backend::ProverError;
+use crate::blake3_bellpepper::{is_equal, is_zero, Expr};
+use crate::constraint_check;
+use crate::native_witness::{self, low_u64, SymbolMap};
+use crate::step_trace::StepTrace;
 use crate::utils::{self, pad_vector_to_min_length};
 
 const N_KEYS: usize = 8;
 const MAX_BYTES_PER_BLOCK: usize = 64;
 
-const IO_ARITY: usize = 15;
+pub(crate) const IO_ARITY: usize = 16;
+/// The circom circuit's public outputs: every IO element but the nonce, which the step circuit
+/// passes through itself so that the compiled circom artifacts do not depend on it
+pub(crate) const CIRCOM_IO_ARITY: usize = IO_ARITY - 1;
+
+const NONCE_CONTEXT: &str = "blake3_fold 2024-01 nonce to scalar";
 
 pub const IV: [u32; N_KEYS] = [
     0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19,
 ];
 
+/// The message permutation applied between rounds (`Blake3Permute` in `blake3_common.circom`)
+pub(crate) const MSG_PERMUTATION: [usize; 16] =
+    [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+// The Blake3 domain separation flags
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+
 // TODO: we should make this for **private** and **public** inputs
 // so then we have a bit of an easier time later when we move as much as possible (provably)
 // to private inputs
@@ -30,15 +56,17 @@ pub struct Blake3CompressPubIO<G: Group> {
     block_count: G::Scalar,
     h_keys: [G::Scalar; 8],
     leaf_depth: G::Scalar,
+    // The verifier's challenge nonce, carried unchanged through every step (see `with_nonce`)
+    nonce: G::Scalar,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum PathDirection {
     Left,
     Right,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 /// PathDirection here indicates which direction the node descends towards the leaf
 /// (i.e. left or right)
 /// The hash of [u8; 32] is the hash of the other child node which is not descended to
@@ -52,34 +80,244 @@ impl PathNode {
     }
 }
 
+/// How the witness of each compression is computed
+#[derive(Clone)]
+enum WitnessGenerator<F: PrimeField> {
+    /// circom's wasm witness generator, loaded by circom-scotia together with the R1CS
+    Wasm(Arc<CircomConfig<F>>),
+    /// `native_witness`, which only needs the R1CS and the circuit's `.sym` file
+    Native(Arc<R1CS<F>>, Arc<SymbolMap>),
+}
+
+/// The circom artifacts the step circuit synthesizes with. These are loaded once and shared
+/// between every circuit (and thread) proving with them, rather than reloaded on each step.
+#[derive(Clone)]
+pub struct CircomArtifacts<F: PrimeField> {
+    witness: WitnessGenerator<F>,
+    // The circuit's `.sym` file, loaded once for whichever of native witnesses, the trace and
+    // the constraint check need it
+    symbols: Option<Arc<SymbolMap>>,
+    // If set, every compression synthesized with values is written to a trace
+    trace: Option<Arc<StepTrace>>,
+    // If set, every witness is checked against the R1CS, naming the signals of any constraint
+    // it violates
+    check_constraints: bool,
+    // If set, the prover checks the output of every step against the native reference model
+    check_reference: bool,
+}
+
+impl<F: PrimeField> CircomArtifacts<F> {
+    /// Load the R1CS and the wasm witness generator
+    pub fn load(circom_path_wasm: &str, circom_path_r1cs: &str) -> Result<Self, ArtifactError> {
+        // circom-scotia panics on missing files rather than returning its error, and loads an
+        // r1cs over another field or with another IO arity without complaint
+        std::fs::metadata(circom_path_wasm)
+            .map_err(|err| ArtifactError::Io(circom_path_wasm.into(), err))?;
+        check_r1cs::<F>(Path::new(circom_path_r1cs))?;
+        let cfg = CircomConfig::<F>::new(circom_path_wasm, circom_path_r1cs)
+            .map_err(|err| ArtifactError::InvalidWasm(circom_path_wasm.into(), err.to_string()))?;
+        debug!(
+            wasm = circom_path_wasm,
+            r1cs = circom_path_r1cs,
+            "Loaded config for R1CS"
+        );
+        Ok(CircomArtifacts {
+            witness: WitnessGenerator::Wasm(Arc::new(cfg)),
+            symbols: None,
+            trace: None,
+            check_constraints: false,
+            check_reference: false,
+        })
+    }
+
+    /// Load the R1CS alone, and compute witnesses natively from the circom `.sym` file at
+    /// `circom_path_sym` rather than running the wasm witness generator on every step
+    pub fn load_native(
+        circom_path_r1cs: &str,
+        circom_path_sym: &str,
+    ) -> Result<Self, ArtifactError> {
+        let r1cs_path = Path::new(circom_path_r1cs);
+        // `load_r1cs` panics on a file it cannot read or parse, so the header is checked first
+        check_r1cs::<F>(r1cs_path)?;
+        let symbols = Arc::new(
+            SymbolMap::from_file(circom_path_sym)
+                .map_err(|err| ArtifactError::Io(circom_path_sym.into(), err))?,
+        );
+        let r1cs = load_r1cs::<F>(r1cs_path);
+        debug!(
+            r1cs = circom_path_r1cs,
+            sym = circom_path_sym,
+            "Loaded R1CS for native witnesses"
+        );
+        Ok(CircomArtifacts {
+            witness: WitnessGenerator::Native(Arc::new(r1cs), symbols.clone()),
+            symbols: Some(symbols),
+            trace: None,
+            check_constraints: false,
+            check_reference: false,
+        })
+    }
+
+    pub(crate) fn r1cs(&self) -> &R1CS<F> {
+        match &self.witness {
+            WitnessGenerator::Wasm(cfg) => &cfg.r1cs,
+            WitnessGenerator::Native(r1cs, _) => r1cs,
+        }
+    }
+
+    /// The witness of one compression, given its circom inputs
+    fn calculate_witness(&self, input: Vec<(String, Vec<F>)>) -> Result<Vec<F>, SynthesisError> {
+        match &self.witness {
+            WitnessGenerator::Wasm(cfg) => calculate_witness(cfg, input, true).map_err(|err| {
+                error!(%err, "Failed to calculate the circom witness");
+                SynthesisError::AssignmentMissing
+            }),
+            WitnessGenerator::Native(_, symbols) => {
+                native_witness::calculate_witness(symbols, &input).map_err(|err| {
+                    error!(%err, "Failed to calculate the native witness");
+                    SynthesisError::AssignmentMissing
+                })
+            }
+        }
+    }
+
+    /// The circuit's symbols, if they were loaded
+    pub(crate) fn symbols(&self) -> Option<&SymbolMap> {
+        self.symbols.as_deref()
+    }
+
+    /// The symbols these artifacts already have, or else the ones in the circom `.sym` file at
+    /// `circom_path_sym`, which are kept for later
+    fn load_symbols(&mut self, circom_path_sym: &str) -> Result<Arc<SymbolMap>, ArtifactError> {
+        if let Some(symbols) = &self.symbols {
+            return Ok(symbols.clone());
+        }
+        let symbols = Arc::new(
+            SymbolMap::from_file(circom_path_sym)
+                .map_err(|err| ArtifactError::Io(circom_path_sym.into(), err))?,
+        );
+        self.symbols = Some(symbols.clone());
+        Ok(symbols)
+    }
+
+    /// Write every compression proven with these artifacts to `trace_path` as JSON lines (see
+    /// `step_trace`), naming the witness from the circom `.sym` file at `circom_path_sym`
+    /// unless the symbols are already loaded
+    pub fn with_trace(
+        mut self,
+        trace_path: &str,
+        circom_path_sym: &str,
+    ) -> Result<Self, ArtifactError> {
+        let symbols = self.load_symbols(circom_path_sym)?;
+        let trace = StepTrace::create(trace_path, &symbols)
+            .map_err(|err| ArtifactError::Io(trace_path.into(), err))?;
+        self.trace = Some(Arc::new(trace));
+        Ok(self)
+    }
+
+    /// Check the witness of every compression proven with these artifacts against the R1CS,
+    /// and log the constraints it violates with their signals named from the circom `.sym` file
+    /// at `circom_path_sym` unless the symbols are already loaded
+    pub fn with_constraint_check(mut self, circom_path_sym: &str) -> Result<Self, ArtifactError> {
+        self.load_symbols(circom_path_sym)?;
+        self.check_constraints = true;
+        Ok(self)
+    }
+
+    /// Check the output of every step proven with these artifacts against the native reference
+    /// model, failing the proof at the first step that diverges from it. A bug then shows up at
+    /// the block or parent it happens at rather than only as a wrong final hash
+    pub fn with_reference_check(mut self) -> Self {
+        self.check_reference = true;
+        self
+    }
+}
+
+impl<F: PrimeField> std::fmt::Debug for CircomArtifacts<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CircomArtifacts")
+            .field("n_constraints", &self.r1cs().constraints.len())
+            .field(
+                "native_witness",
+                &matches!(self.witness, WitnessGenerator::Native(..)),
+            )
+            .field("trace", &self.trace.is_some())
+            .field("constraint_check", &self.check_constraints)
+            .field("reference_check", &self.check_reference)
+            .finish()
+    }
+}
+
+/// Where a step circuit is in the chunk and path: the leaf blocks are compressed first, then
+/// the parents from the leaf up to the root. Shared by every step circuit, and saved with
+/// checkpoints to resume proving from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CircuitCursor {
+    current_block: usize,
+    current_depth: usize,
+    compressions_done: usize,
+}
+
+impl CircuitCursor {
+    /// At the first block of a leaf `total_depth` deep in the tree
+    pub(crate) fn new(total_depth: usize) -> Self {
+        CircuitCursor {
+            current_block: 0,
+            current_depth: total_depth - 1,
+            compressions_done: 0,
+        }
+    }
+
+    pub(crate) fn block(&self) -> usize {
+        self.current_block
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.current_depth
+    }
+
+    /// Whether the next compression is of a leaf block rather than a parent
+    pub(crate) fn is_leaf(&self, n_blocks: usize) -> bool {
+        self.current_block < n_blocks
+    }
+
+    /// Whether there is still a compression left to do
+    pub(crate) fn is_active(&self, n_blocks: usize, total_depth: usize) -> bool {
+        self.compressions_done < n_blocks + total_depth - 1
+    }
+
+    /// Move past one compression. Does nothing once every compression is done.
+    pub(crate) fn advance(&mut self, n_blocks: usize, total_depth: usize) {
+        if !self.is_active(n_blocks, total_depth) {
+            return;
+        }
+        self.compressions_done += 1;
+        // If we are still absorbing the input
+        if self.current_block < n_blocks {
+            self.current_block += 1;
+        }
+        // We start updating for the parent path when we finish absorbing all the blocks
+        // Note that this can happen right after the last block
+        if self.current_block == n_blocks && self.current_depth > 0 {
+            self.current_depth -= 1;
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Blake3BlockCompressCircuit<G: Group> {
     leaf_bytes: Vec<u8>,
     pub(crate) n_bytes: usize,
     pub(crate) total_depth: usize,
     pub(crate) n_blocks: usize,
-    current_depth: usize,
-    current_block: usize,
+    cursor: CircuitCursor,
+    // The number of Blake3 compressions folded into a single step
+    pub(crate) compressions_per_step: usize,
     parent_path: Vec<PathNode>,
-    circom_path_wasm: String,
-    circom_path_r1cs: String,
-    // TODO:
-    // cfg: CircomConfig<G::Scalar>,
+    artifacts: CircomArtifacts<G::Scalar>,
     _p: std::marker::PhantomData<G>,
 }
 
-fn load_cfg<G: Group>(wtns: &str, r1cs: &str) -> CircomConfig<G::Scalar> {
-    // Load the R1CS
-    // TODO: instead of unwrap, return error?
-    let cfg = CircomConfig::<G::Scalar>::new(
-        PathBuf::from_str(wtns).unwrap(),
-        PathBuf::from_str(r1cs).unwrap(),
-    )
-    .unwrap();
-    println!("Loaded config for R1CS");
-    cfg
-}
-
 impl<G: Group> Blake3CompressPubIO<G> {
     pub(crate) fn new(
         chunk_idx: u64,
@@ -105,9 +343,31 @@ impl<G: Group> Blake3CompressPubIO<G> {
             block_count: G::Scalar::from(0),
             h_keys: h,
             leaf_depth,
+            nonce: G::Scalar::ZERO,
         }
     }
 
+    /// Bind the IO to a verifier's challenge nonce. Every folded instance commits to the
+    /// initial IO, so a proof made for one nonce does not verify for any other and a prover
+    /// cannot answer a fresh challenge with a proof computed ahead of time.
+    pub(crate) fn with_nonce(mut self, nonce: &[u8; 32]) -> Self {
+        self.nonce = nonce_to_scalar(nonce);
+        self
+    }
+
+    /// The IO a chunk's path starts from, with the IV as chaining value at the leaf's depth
+    pub(crate) fn initial(chunk_idx: u64, n_blocks: usize, total_depth: usize) -> Self {
+        let iv = IV.iter().map(|iv| G::Scalar::from(*iv as u64)).collect();
+        let total_depth = G::Scalar::from(total_depth as u64);
+        Self::new(
+            chunk_idx,
+            total_depth,
+            G::Scalar::from(n_blocks as u64),
+            iv,
+            total_depth,
+        )
+    }
+
     pub(crate) fn to_vec(&self) -> Vec<G::Scalar> {
         let mut vec = Vec::new();
         vec.push(self.n_blocks);
@@ -118,6 +378,7 @@ impl<G: Group> Blake3CompressPubIO<G> {
         vec.push(self.chunk_idx_low);
         vec.push(self.chunk_idx_high);
         vec.push(self.leaf_depth);
+        vec.push(self.nonce);
         assert!(vec.len() == IO_ARITY);
         vec
     }
@@ -134,6 +395,7 @@ impl<G: Group> Blake3CompressPubIO<G> {
         let chunk_idx_low = vec[12];
         let chunk_idx_high = vec[13];
         let leaf_depth = vec[14];
+        let nonce = vec[15];
         Blake3CompressPubIO {
             total_depth,
             depth,
@@ -143,6 +405,7 @@ impl<G: Group> Blake3CompressPubIO<G> {
             chunk_idx_low,
             chunk_idx_high,
             leaf_depth,
+            nonce,
         }
     }
 
@@ -157,12 +420,119 @@ impl<G: Group> Blake3CompressPubIO<G> {
     }
 }
 
+/// A 32 byte nonce as a scalar. It is hashed down to 248 bits first so that it fits in any
+/// of the fields we prove over without reduction.
+pub(crate) fn nonce_to_scalar<F: PrimeField>(nonce: &[u8; 32]) -> F {
+    let mut bytes = [0u8; 31];
+    blake3::Hasher::new_derive_key(NONCE_CONTEXT)
+        .update(nonce)
+        .finalize_xof()
+        .fill(&mut bytes);
+    let mut low = [0u8; 16];
+    low.copy_from_slice(&bytes[..16]);
+    let mut high = [0u8; 16];
+    high[..15].copy_from_slice(&bytes[16..]);
+    F::from_u128(u128::from_le_bytes(low))
+        + F::from_u128(u128::from_le_bytes(high)) * F::from_u128(1u128 << 64).square()
+}
+
+/// The 16 little endian message words of block `block` of a chunk, zero padded,
+/// along with the number of bytes in the block
+pub(crate) fn leaf_block_words(leaf_bytes: &[u8], block: usize) -> (Vec<u32>, u64) {
+    // 4 bytes per 32-bit word
+    let start_idx = block * 4 * 16;
+    let end_idx = min(start_idx + 4 * 16, leaf_bytes.len());
+
+    let mut message_bytes = leaf_bytes[start_idx..end_idx].to_vec();
+    // The number of 32 bit words (4 byte) in the message
+    pad_vector_to_min_length(&mut message_bytes, MAX_BYTES_PER_BLOCK, 0);
+    let as_u32 = utils::bytes_to_u32_le(&message_bytes);
+    let n_bytes_per_block = (end_idx - start_idx) as u64;
+    assert!(
+        n_bytes_per_block <= MAX_BYTES_PER_BLOCK as u64,
+        "Too many bytes per block"
+    );
+    (as_u32, n_bytes_per_block)
+}
+
+/// The message words for a parent step: the sibling's chaining value in the first 8 words
+/// and zeros in the last 8. The circuit moves the sibling to the correct side.
+pub(crate) fn parent_block_words(path_node: &PathNode) -> Vec<u32> {
+    // TODO: IDK. Little endian everything...
+    // We need to work with everything in little endian from the beginning
+    let mut as_u32 = utils::bytes_to_u32_le(&path_node.1);
+    assert!(as_u32.len() == 8);
+    as_u32.extend_from_slice(&[0u32; 8]);
+    as_u32
+}
+
+/// The quarter round `G` of the Blake3 compression function
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+/// The Blake3 compression function, returning the new chaining value. This is a plain
+/// software version, sharing nothing with the circuit or its native witness, to check them
+/// against.
+pub(crate) fn compress(
+    cv: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+) -> [u32; 8] {
+    let mut state = [0u32; 16];
+    state[..8].copy_from_slice(cv);
+    state[8..12].copy_from_slice(&IV[..4]);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+    state[14] = block_len;
+    state[15] = flags;
+    let mut m = *block;
+    for round in 0..7 {
+        // The columns, then the diagonals
+        g(&mut state, 0, 4, 8, 12, m[0], m[1]);
+        g(&mut state, 1, 5, 9, 13, m[2], m[3]);
+        g(&mut state, 2, 6, 10, 14, m[4], m[5]);
+        g(&mut state, 3, 7, 11, 15, m[6], m[7]);
+        g(&mut state, 0, 5, 10, 15, m[8], m[9]);
+        g(&mut state, 1, 6, 11, 12, m[10], m[11]);
+        g(&mut state, 2, 7, 8, 13, m[12], m[13]);
+        g(&mut state, 3, 4, 9, 14, m[14], m[15]);
+        if round < 6 {
+            m = std::array::from_fn(|i| m[MSG_PERMUTATION[i]]);
+        }
+    }
+    std::array::from_fn(|i| state[i] ^ state[i + 8])
+}
+
 impl<G: Group> Blake3BlockCompressCircuit<G> {
     pub fn new(
         bytes: Vec<u8>,
         parent_path: Vec<PathNode>,
         circom_path_wasm: String,
         circom_path_r1cs: String,
+    ) -> Blake3BlockCompressCircuit<G> {
+        Self::with_artifacts(
+            bytes,
+            parent_path,
+            CircomArtifacts::load(&circom_path_wasm, &circom_path_r1cs)
+                .expect("Failed to load the circom artifacts"),
+        )
+    }
+
+    /// Build the circuit on top of already loaded circom artifacts
+    pub fn with_artifacts(
+        bytes: Vec<u8>,
+        parent_path: Vec<PathNode>,
+        artifacts: CircomArtifacts<G::Scalar>,
     ) -> Blake3BlockCompressCircuit<G> {
         let bytes_len = bytes.len();
         let n_blocks = utils::n_blocks_from_bytes(bytes_len);
@@ -173,49 +543,207 @@ impl<G: Group> Blake3BlockCompressCircuit<G> {
             n_blocks,
             leaf_bytes: bytes,
             parent_path,
-            current_block: 0,
             total_depth: depth,
-            current_depth: depth - 1,
-            circom_path_r1cs,
-            circom_path_wasm,
+            cursor: CircuitCursor::new(depth),
+            compressions_per_step: 1,
+            artifacts,
             _p: std::marker::PhantomData,
         }
     }
 
-    pub fn update_for_step(&mut self) -> () {
-        // If we are still absorbing the input
-        if self.current_block < self.n_blocks {
-            self.current_block += 1;
+    /// Do `compressions_per_step` Blake3 compressions in every step. Slots left over after
+    /// the final compression are no-ops which pass the state through unchanged.
+    pub fn with_compressions_per_step(mut self, compressions_per_step: usize) -> Self {
+        assert!(compressions_per_step > 0);
+        self.compressions_per_step = compressions_per_step;
+        self
+    }
+
+    /// The total number of compressions: one per block plus one per parent above the leaf
+    pub(crate) fn n_compressions(&self) -> usize {
+        self.n_blocks + self.total_depth - 1
+    }
+
+    /// The number of IVC steps needed to do all the compressions
+    pub fn num_steps(&self) -> usize {
+        (self.n_compressions() + self.compressions_per_step - 1) / self.compressions_per_step
+    }
+
+    /// Whether there is still a compression left to do
+    fn is_active(&self) -> bool {
+        self.cursor.is_active(self.n_blocks, self.total_depth)
+    }
+
+    /// Where the circuit is in the chunk and path, to resume proving from
+    pub(crate) fn cursor(&self) -> CircuitCursor {
+        self.cursor.clone()
+    }
+
+    pub(crate) fn restore_cursor(&mut self, cursor: &CircuitCursor) {
+        self.cursor = cursor.clone();
+    }
+
+    /// A digest of everything the circuit proves over besides its IO: the leaf bytes, the
+    /// parent path and the number of compressions per step
+    pub(crate) fn input_digest(&self) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&(self.compressions_per_step as u64).to_le_bytes());
+        hasher.update(&(self.leaf_bytes.len() as u64).to_le_bytes());
+        hasher.update(&self.leaf_bytes);
+        for PathNode(dir, hash) in &self.parent_path {
+            hasher.update(&[(*dir == PathDirection::Left) as u8]);
+            hasher.update(hash);
         }
-        // We start updating for the parent path when we finish absorbing all the blocks
-        // Note that this can happen after updating self.n_blocks
-        if self.current_block == self.n_blocks && self.current_depth > 0 {
-            self.current_depth -= 1;
+        *hasher.finalize().as_bytes()
+    }
+
+    /// What the current step should output from `io`, worked out natively from the cursor, the
+    /// leaf bytes and the path rather than from the circuit's witness
+    pub(crate) fn reference_step(&self, io: &Blake3CompressPubIO<G>) -> Blake3CompressPubIO<G> {
+        let mut slot = self.clone();
+        let mut io = Blake3CompressPubIO::from_vec(io.to_vec());
+        for _ in 0..self.compressions_per_step {
+            io = slot.reference_compression(io);
+            slot.update_for_compression();
+        }
+        io
+    }
+
+    fn reference_compression(&self, mut io: Blake3CompressPubIO<G>) -> Blake3CompressPubIO<G> {
+        if !self.is_active() {
+            return io;
+        }
+        let h: [u32; 8] = std::array::from_fn(|i| low_u64(&io.h_keys[i]) as u32);
+        let is_root = io.depth == G::Scalar::ZERO;
+        let h_out = if self.cursor.is_leaf(self.n_blocks) {
+            let (m, b) = leaf_block_words(&self.leaf_bytes, self.cursor.block());
+            let chunk_idx = low_u64(&io.chunk_idx_low) | (low_u64(&io.chunk_idx_high) << 32);
+            let is_last = self.cursor.block() == self.n_blocks - 1;
+            let mut flags = 0;
+            if self.cursor.block() == 0 {
+                flags |= CHUNK_START;
+            }
+            if is_last {
+                flags |= CHUNK_END;
+                if is_root {
+                    flags |= ROOT;
+                } else {
+                    io.depth -= G::Scalar::ONE;
+                }
+            }
+            io.block_count += G::Scalar::ONE;
+            compress(&h, &m.try_into().unwrap(), chunk_idx, b as u32, flags)
+        } else {
+            let PathNode(dir, sibling) = &self.parent_path[self.cursor.depth()];
+            let sibling = utils::bytes_to_u32_le(sibling);
+            let m = match dir {
+                PathDirection::Left => [&h[..], &sibling[..]].concat(),
+                PathDirection::Right => [&sibling[..], &h[..]].concat(),
+            };
+            let flags = if is_root { PARENT | ROOT } else { PARENT };
+            if !is_root {
+                io.depth -= G::Scalar::ONE;
+            }
+            compress(&IV, &m.try_into().unwrap(), 0, 64, flags)
+        };
+        io.h_keys = h_out.map(|word| G::Scalar::from(word as u64));
+        io
+    }
+
+    /// Check the witness of every compression the current step makes from `z_i` against the
+    /// R1CS, and log the constraints they violate. Proving a step which failed is explained this
+    /// way, whether or not the artifacts check every step
+    pub(crate) fn report_unsatisfied_step(&self, z_i: &[G::Scalar]) {
+        let mut slot = self.clone();
+        let mut io = Blake3CompressPubIO::<G>::from_vec(z_i.to_vec());
+        for _ in 0..self.compressions_per_step {
+            if !slot.is_active() {
+                break;
+            }
+            let input = slot.format_input_from_io(Blake3CompressPubIO::from_vec(io.to_vec()));
+            // A failure to compute the witness is logged where it happens
+            let Ok(witness) = self.artifacts.calculate_witness(input) else {
+                return;
+            };
+            constraint_check::report_unsatisfied(
+                self.artifacts.r1cs(),
+                &witness,
+                self.artifacts.symbols(),
+                &slot.cursor(),
+            );
+            io = slot.reference_compression(io);
+            slot.update_for_compression();
+        }
+    }
+
+    /// Whether the artifacts ask for every step to be checked with `check_reference_step`
+    pub(crate) fn checks_reference(&self) -> bool {
+        self.artifacts.check_reference
+    }
+
+    /// Fail, naming where the step starts in the chunk and path, if the step from `z_i` to
+    /// `z_next` is not the one `reference_step` expects
+    pub(crate) fn check_reference_step(
+        &self,
+        step: usize,
+        z_i: &[G::Scalar],
+        z_next: &[G::Scalar],
+    ) -> Result<(), ProverError> {
+        let expected = self.reference_step(&Blake3CompressPubIO::from_vec(z_i.to_vec()));
+        let expected = expected.to_vec();
+        if expected == z_next {
+            return Ok(());
+        }
+        let position = if self.cursor.is_leaf(self.n_blocks) {
+            format!("leaf block {}", self.cursor.block())
+        } else {
+            format!("parent at depth {}", self.cursor.depth())
+        };
+        let elements = (0..IO_ARITY)
+            .filter(|&j| expected[j] != z_next[j])
+            .map(|j| (j, format!("{:?}", expected[j]), format!("{:?}", z_next[j])))
+            .collect();
+        Err(ProverError::ReferenceMismatch {
+            step,
+            position,
+            elements,
+        })
+    }
+
+    pub fn update_for_step(&mut self) -> () {
+        for _ in 0..self.compressions_per_step {
+            self.update_for_compression();
         }
     }
 
+    fn update_for_compression(&mut self) {
+        self.cursor.advance(self.n_blocks, self.total_depth);
+    }
+
     fn format_input(
         &self,
         z: &[bellpepper_core::num::AllocatedNum<G::Scalar>],
     ) -> Result<Vec<(String, Vec<G::Scalar>)>, bellpepper_core::SynthesisError> {
         let io_input = Blake3CompressPubIO::<G>::from_alloced_vec(z.to_vec());
+        Ok(self.format_input_from_io(io_input))
+    }
 
-        let not_parent = self.current_block < self.n_blocks;
-        let (message_block_scalar, b) = if not_parent {
-            println!("Doing leaf");
-            // 4 bytes per 32-bit word
-            let start_idx = self.current_block * 4 * 16;
-            let end_idx = min(start_idx + 4 * 16, self.n_bytes);
-
-            let mut message_bytes = self.leaf_bytes[start_idx..end_idx].to_vec();
-            // The number of 32 bit words (4 byte) in the message
-            pad_vector_to_min_length(&mut message_bytes, MAX_BYTES_PER_BLOCK, 0);
-            let as_u32 = utils::bytes_to_u32_le(&message_bytes);
-            let n_bytes_per_block = (end_idx - start_idx) as u64;
-            assert!(
-                n_bytes_per_block <= MAX_BYTES_PER_BLOCK as u64,
-                "Too many bytes per block"
-            );
+    pub(crate) fn format_input_from_vec(&self, z: Vec<G::Scalar>) -> Vec<(String, Vec<G::Scalar>)> {
+        self.format_input_from_io(Blake3CompressPubIO::<G>::from_vec(z))
+    }
+
+    fn format_input_from_io(
+        &self,
+        io_input: Blake3CompressPubIO<G>,
+    ) -> Vec<(String, Vec<G::Scalar>)> {
+        let not_parent = self.cursor.is_leaf(self.n_blocks);
+        let (message_block_scalar, b) = if !self.is_active() {
+            // A no-op slot. Its output is discarded so any message satisfies the circuit
+            (vec![G::Scalar::ZERO; 16], G::Scalar::ZERO)
+        } else if not_parent {
+            trace!(block = self.cursor.block(), "Leaf block");
+            let (as_u32, n_bytes_per_block) =
+                leaf_block_words(&self.leaf_bytes, self.cursor.block());
             // The number of bytes
             let b = G::Scalar::from(n_bytes_per_block);
 
@@ -224,32 +752,27 @@ impl<G: Group> Blake3BlockCompressCircuit<G> {
                 b,
             )
         } else {
-            println!("Doing alternative");
+            trace!(depth = self.cursor.depth(), "Parent block");
             // We always have b=64 for a parent block
             let b = G::Scalar::from(64u64);
             // Note that parent_path.len() = total_depth - 1. As we never access
             // parent_path at the leaf processing, we do not access parent_path[total_depth - 1] (illegal)
-            let path_node = &self.parent_path[self.current_depth];
-            let message_bytes = path_node.1;
-            // TODO: IDK. Little endian everything...
-            // We need to work with everything in little endian from the beginning
-            let as_u32 = utils::bytes_to_u32_le(&message_bytes);
-            assert!(as_u32.len() == 8);
-            let mut m = as_u32
+            let path_node = &self.parent_path[self.cursor.depth()];
+            let m = parent_block_words(path_node)
                 .iter()
                 .map(|x| G::Scalar::from(*x as u64))
                 .collect::<Vec<G::Scalar>>();
-            let empty = vec![G::Scalar::ZERO; 8];
-            m.extend_from_slice(&empty);
-            println!("Sib value {:?} {:?}", message_bytes, path_node.0);
-            println!("chaining value: {:?}", io_input.h_keys);
+            trace!(sibling = ?path_node.1, direction = ?path_node.0, chaining_value = ?io_input.h_keys, "Parent block");
             (m, b)
         };
 
-        println!(
-            "DEPTH {:?}, total depth {:?}. chunk idx  {:?} :: {:?}. LEAF TOTAL DEPTH {:?}",
-            io_input.depth, io_input.total_depth, io_input.chunk_idx_low, io_input.chunk_idx_high,
-            io_input.leaf_depth
+        trace!(
+            depth = ?io_input.depth,
+            total_depth = ?io_input.total_depth,
+            chunk_idx_low = ?io_input.chunk_idx_low,
+            chunk_idx_high = ?io_input.chunk_idx_high,
+            leaf_depth = ?io_input.leaf_depth,
+            "Formatted input"
         );
 
         let b_arg = ("b".to_string(), vec![b]);
@@ -285,10 +808,31 @@ impl<G: Group> Blake3BlockCompressCircuit<G> {
             override_h_to_iv,
             leaf_depth,
         ];
-        Ok(input)
+        input
     }
 }
 
+/// Whether the compression which starts from the IO `z` is the root's: the last leaf block of a
+/// chunk which is the only one in the file, or the parent at depth 0. The state after the root's
+/// compression satisfies this too.
+fn is_root_compression<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    z: &[AllocatedNum<F>],
+) -> Result<Boolean, SynthesisError> {
+    let one = CS::one();
+    let n_blocks = Expr::num(&z[0]);
+    let block_count = Expr::num(&z[1]);
+    let at_root = is_zero(cs.namespace(|| "at root"), &Expr::num(&z[11]))?;
+    let last_block = is_equal(
+        cs.namespace(|| "last block"),
+        &block_count,
+        &n_blocks.sub(&Expr::constant(one, F::ONE)),
+    )?;
+    let is_parent = is_equal(cs.namespace(|| "is parent"), &block_count, &n_blocks)?;
+    let root_candidate = Boolean::or(cs.namespace(|| "root candidate"), &last_block, &is_parent)?;
+    Boolean::and(cs.namespace(|| "is root"), &at_root, &root_candidate)
+}
+
 impl<G: Group> StepCircuit<G::Scalar> for Blake3BlockCompressCircuit<G> {
     fn arity(&self) -> usize {
         IO_ARITY
@@ -300,15 +844,242 @@ impl<G: Group> StepCircuit<G::Scalar> for Blake3BlockCompressCircuit<G> {
         z: &[bellpepper_core::num::AllocatedNum<G::Scalar>],
     ) -> Result<Vec<bellpepper_core::num::AllocatedNum<G::Scalar>>, bellpepper_core::SynthesisError>
     {
-        let cfg = load_cfg::<G>(&self.circom_path_wasm, &self.circom_path_r1cs);
-        let input = self.format_input(z)?;
-        let witness = calculate_witness(&cfg, input, true).expect("msg");
-        utils::synthesize_with_vec::<G::Scalar, _>(
-            &mut cs.namespace(|| "blake3_circom"),
-            cfg.r1cs.clone(),
-            Some(witness),
-            // Return the arity of the input/output for the public ins and outs
-            self.arity(),
-        )
+        let r1cs = self.artifacts.r1cs();
+
+        let values = |z: &[AllocatedNum<G::Scalar>]| -> Option<Vec<G::Scalar>> {
+            z.iter().map(|x| x.get_value()).collect()
+        };
+
+        let mut z_out = z.to_vec();
+        // Each slot works on its own copy of the cursor, so the circuit itself only moves on
+        // in `update_for_step`
+        let mut slot = self.clone();
+        // The verifier fixes the number of steps to just cover every compression, so the first
+        // slot of a step always has a compression to do. Every later slot does one until the
+        // step has done the root's, and leaves the state as is after that.
+        let mut active = Boolean::constant(true);
+        for i in 0..self.compressions_per_step {
+            let mut cs = cs.namespace(|| format!("compression {}", i));
+            debug_assert!(active.get_value().map_or(true, |a| a == slot.is_active()));
+            let z_in = z_out.clone();
+            let input = slot.format_input(&z_in)?;
+            // No-op slots, and the shape synthesized at setup which has no values, are not traced
+            let traced = match &self.artifacts.trace {
+                Some(trace) if slot.is_active() => {
+                    values(&z_in).map(|z_in| (trace, z_in, input.clone()))
+                }
+                _ => None,
+            };
+            let witness = self.artifacts.calculate_witness(input)?;
+            let traced = traced.map(|(trace, z_in, input)| (trace, z_in, input, witness.clone()));
+            // The shape synthesized at setup has no witness to check
+            if self.artifacts.check_constraints && values(&z_in).is_some() {
+                constraint_check::report_unsatisfied(
+                    r1cs,
+                    &witness,
+                    self.artifacts.symbols(),
+                    &slot.cursor(),
+                );
+            }
+            let mut out = utils::synthesize_with_vec::<G::Scalar, _>(
+                &mut cs.namespace(|| "blake3_circom"),
+                r1cs.clone(),
+                Some(witness),
+                // Return the arity of the input/output for the public ins and outs
+                CIRCOM_IO_ARITY,
+            )?;
+            // The nonce is passed through
+            out.push(z_in[IO_ARITY - 1].clone());
+
+            z_out = if i == 0 {
+                out
+            } else {
+                out.iter()
+                    .zip(z_in.iter())
+                    .enumerate()
+                    .map(|(j, (next, prev))| {
+                        utils::conditionally_select(
+                            cs.namespace(|| format!("select {}", j)),
+                            next,
+                            prev,
+                            &active,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            if let Some((trace, z_in, input, witness)) = traced {
+                let z_next = values(&z_out).expect("A traced compression has values");
+                trace.record(&slot.cursor(), &input, &witness, &z_in, &z_next);
+            }
+            if i + 1 < self.compressions_per_step {
+                // The next slot only compresses if this one did and it was not the root's
+                let root = is_root_compression(cs.namespace(|| "is root"), &z_in)?;
+                active = Boolean::and(cs.namespace(|| "next active"), &active, &root.not())?;
+            }
+            slot.update_for_compression();
+        }
+        Ok(z_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::PallasEngine;
+    use arecibo::traits::Engine;
+
+    use super::*;
+    use crate::blake3_hash::hash_with_path;
+    use crate::{PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_SYM_PATH, PASTA_CIRCOM_WASM_PATH};
+
+    type G = <PallasEngine as Engine>::GE;
+
+    #[test]
+    fn test_compress() {
+        // A single block input is compressed once, as the root
+        for data in [vec![], vec![9u8; 17], vec![200u8; 64]] {
+            let (m, b) = leaf_block_words(&data, 0);
+            let flags = CHUNK_START | CHUNK_END | ROOT;
+            let cv = compress(&IV, &m.try_into().unwrap(), 0, b as u32, flags);
+            let bytes: Vec<u8> = cv.iter().flat_map(|word| word.to_le_bytes()).collect();
+            assert_eq!(bytes, blake3::hash(&data).as_bytes().to_vec());
+        }
+    }
+
+    #[test]
+    fn test_reference_step() {
+        let artifacts =
+            CircomArtifacts::load(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let data: Vec<u8> = (0..1024 * 7 + 100).map(|i| (i % 251) as u8).collect();
+        for (chunk_idx, k) in [(0, 1), (2, 3), (7, 1), (7, 4)] {
+            let (hash, hash_proof) = hash_with_path(&data, chunk_idx).unwrap();
+            let mut circuit = Blake3BlockCompressCircuit::<G>::with_artifacts(
+                hash_proof.bytes,
+                hash_proof.parent_path,
+                artifacts.clone(),
+            )
+            .with_compressions_per_step(k);
+            let mut io = Blake3CompressPubIO::<G>::initial(
+                chunk_idx as u64,
+                circuit.n_blocks,
+                circuit.total_depth,
+            );
+            for step in 0..circuit.num_steps() {
+                let z_next = circuit.reference_step(&io).to_vec();
+                assert!(circuit
+                    .check_reference_step(step, &io.to_vec(), &z_next)
+                    .is_ok());
+                // A step with a wrong output word is named with the element it got wrong
+                let mut wrong = z_next.clone();
+                wrong[2] += <G as Group>::Scalar::ONE;
+                assert!(matches!(
+                    circuit.check_reference_step(step, &io.to_vec(), &wrong),
+                    Err(ProverError::ReferenceMismatch { step: s, ref elements, .. })
+                        if s == step && elements.len() == 1 && elements[0].0 == 2
+                ));
+                io = Blake3CompressPubIO::from_vec(z_next);
+                circuit.update_for_step();
+            }
+            assert_eq!(io.depth, <G as Group>::Scalar::ZERO);
+            assert_eq!(
+                utils::format_scalar_blake_hash::<G>(io.h_keys),
+                hash.as_bytes().to_vec()
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_artifacts_errors() {
+        type F = <G as Group>::Scalar;
+        assert!(matches!(
+            CircomArtifacts::<F>::load("does/not/exist.wasm", PASTA_CIRCOM_R1CS_PATH),
+            Err(ArtifactError::Io(..))
+        ));
+        assert!(matches!(
+            CircomArtifacts::<F>::load_native(PASTA_CIRCOM_R1CS_PATH, "does/not/exist.sym"),
+            Err(ArtifactError::Io(..))
+        ));
+        // A wasm file is not an r1cs
+        assert!(matches!(
+            CircomArtifacts::<F>::load_native(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_SYM_PATH),
+            Err(ArtifactError::InvalidR1cs(..))
+        ));
+        // The pasta r1cs for the BN256 cycle
+        assert!(matches!(
+            CircomArtifacts::<halo2curves::bn256::Fr>::load(
+                PASTA_CIRCOM_WASM_PATH,
+                PASTA_CIRCOM_R1CS_PATH
+            ),
+            Err(ArtifactError::WrongPrime { .. })
+        ));
+        let native =
+            CircomArtifacts::<F>::load_native(PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_SYM_PATH)
+                .unwrap();
+        let wasm =
+            CircomArtifacts::<F>::load(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        assert_eq!(
+            native.r1cs().constraints.len(),
+            wasm.r1cs().constraints.len()
+        );
+
+        // The symbols are loaded once and shared
+        assert!(matches!(
+            wasm.with_constraint_check("does/not/exist.sym"),
+            Err(ArtifactError::Io(..))
+        ));
+        let checked = native
+            .clone()
+            .with_constraint_check("does/not/exist.sym")
+            .unwrap();
+        assert!(std::ptr::eq(
+            native.symbols().unwrap(),
+            checked.symbols().unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_noop_slots() {
+        use bellpepper_core::test_cs::TestConstraintSystem;
+        type F = <G as Group>::Scalar;
+
+        let artifacts =
+            CircomArtifacts::load(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let data = vec![8u8; 1024 * 7 + 100];
+        // A single chunk file, where the root is a leaf block, and a path ending in a parent
+        for (data, chunk_idx) in [(&data[..200], 0), (&data[..], 5)] {
+            let (hash, hash_proof) = hash_with_path(data, chunk_idx).unwrap();
+            let mut circuit = Blake3BlockCompressCircuit::<G>::with_artifacts(
+                hash_proof.bytes,
+                hash_proof.parent_path,
+                artifacts.clone(),
+            )
+            .with_compressions_per_step(3);
+            // The last step has no-op slots
+            assert_ne!(circuit.n_compressions() % 3, 0);
+            let mut z = Blake3CompressPubIO::<G>::initial(
+                chunk_idx as u64,
+                circuit.n_blocks,
+                circuit.total_depth,
+            )
+            .to_vec();
+            for _ in 0..circuit.num_steps() {
+                let mut cs = TestConstraintSystem::<F>::new();
+                let z_alloc = z
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| {
+                        AllocatedNum::alloc(cs.namespace(|| format!("z {}", i)), || Ok(*v))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                let out = circuit.synthesize(&mut cs, &z_alloc).unwrap();
+                assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+                z = out.iter().map(|x| x.get_value().unwrap()).collect();
+                circuit.update_for_step();
+            }
+            assert_eq!(
+                utils::format_scalar_blake_hash::<G>(z[2..10].try_into().unwrap()),
+                hash.as_bytes().to_vec()
+            );
+        }
     }
 }
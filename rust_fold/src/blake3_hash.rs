@@ -1,13 +1,17 @@
-use std::{cmp::min, io::Read};
+use std::{
+    cmp::min,
+    io::{Read, Seek},
+};
 
 use blake3::Hash;
+use tracing::{debug, trace};
 
 use crate::{
     blake3_circuit::{PathDirection, PathNode},
     MAX_BYTES_PER_CHUNK,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Blake3HashProof {
     pub(crate) chunk_idx: u64,
     pub(crate) parent_path: Vec<PathNode>,
@@ -43,9 +47,47 @@ pub(crate) fn hash_with_path(
     let mut slice = Vec::new();
     extractor.read_to_end(&mut slice)?;
 
+    Ok((
+        hash,
+        proof_from_slice(&slice, &hash, leaf as u64, slice_len)?,
+    ))
+}
+
+/// `hash_with_path` for a file whose bao outboard (see `bao::encode::outboard`) is already
+/// stored, so only the chunk and the parents above it are read rather than the whole file
+pub(crate) fn path_with_outboard<I: Read + Seek, O: Read + Seek>(
+    input: I,
+    outboard: O,
+    hash: &Hash,
+    file_len: u64,
+    leaf: u64,
+) -> Result<Blake3HashProof, std::io::Error> {
+    // In u64 and checked, so a huge index cannot wrap around into range
+    let slice_start = leaf
+        .checked_mul(MAX_BYTES_PER_CHUNK as u64)
+        .filter(|&slice_start| slice_start < file_len.max(1))
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Chunk index out of range")
+        })?;
+    let slice_len = min(MAX_BYTES_PER_CHUNK as u64, file_len - slice_start);
+    let mut extractor =
+        bao::encode::SliceExtractor::new_outboard(input, outboard, slice_start, slice_len);
+    let mut slice = Vec::new();
+    extractor.read_to_end(&mut slice)?;
+    proof_from_slice(&slice, hash, leaf, slice_len)
+}
+
+/// Check a bao slice of the chunk `leaf` against `hash` and read the path to it
+fn proof_from_slice(
+    slice: &[u8],
+    hash: &Hash,
+    leaf: u64,
+    slice_len: u64,
+) -> Result<Blake3HashProof, std::io::Error> {
+    let slice_start = leaf * MAX_BYTES_PER_CHUNK as u64;
     let mut decoded = Vec::new();
 
-    let mut decoder = bao::decode::SliceDecoder::new(&*slice, &hash, slice_start, slice_len);
+    let mut decoder = bao::decode::SliceDecoder::new(slice, hash, slice_start, slice_len);
 
     decoder.read_to_end(&mut decoded)?;
     // decoder.shared.state;
@@ -64,7 +106,7 @@ pub(crate) fn hash_with_path(
         } else {
             PathDirection::Right
         };
-        println!("Parent CV: {:?}", chunk);
+        trace!(?chunk, "Parent CV");
         let chunk_array = if dir == PathDirection::Left {
             let mut chunk_array = [0u8; 32];
             // Get the right child as we descend left
@@ -81,15 +123,12 @@ pub(crate) fn hash_with_path(
         path_nodes.push(PathNode::new(dir.clone(), chunk_array));
     }
 
-    println!("Path nodes: {:?}", path_nodes);
-    Ok((
-        hash,
-        Blake3HashProof {
-            chunk_idx: leaf as u64,
-            parent_path: path_nodes,
-            bytes: data_slice.to_vec(),
-        },
-    ))
+    debug!(?path_nodes, "Path nodes");
+    Ok(Blake3HashProof {
+        chunk_idx: leaf,
+        parent_path: path_nodes,
+        bytes: data_slice.to_vec(),
+    })
 }
 
 #[cfg(test)]
@@ -107,4 +146,54 @@ mod tests {
         println!("path_nodes: {:?}", path_nodes.parent_path);
         // assert!(path_nodes.len() == 1);
     }
+
+    #[test]
+    fn test_path_with_outboard() {
+        let input: Vec<u8> = (0..1_024 * 5 + 300).map(|i| (i % 251) as u8).collect();
+        let (outboard, hash) = bao::encode::outboard(&input);
+        for leaf in 0..6 {
+            let (expected_hash, expected) = hash_with_path(&input, leaf).unwrap();
+            assert_eq!(hash, expected_hash);
+            let proof = path_with_outboard(
+                std::io::Cursor::new(&input),
+                std::io::Cursor::new(&outboard),
+                &hash,
+                input.len() as u64,
+                leaf as u64,
+            )
+            .unwrap();
+            assert_eq!(proof, expected);
+        }
+
+        // A stale outboard for other content is caught
+        let mut changed = input.clone();
+        changed[0] ^= 1;
+        assert!(path_with_outboard(
+            std::io::Cursor::new(&changed),
+            std::io::Cursor::new(&outboard),
+            &hash,
+            input.len() as u64,
+            0,
+        )
+        .is_err());
+        assert!(path_with_outboard(
+            std::io::Cursor::new(&input),
+            std::io::Cursor::new(&outboard),
+            &hash,
+            input.len() as u64,
+            6,
+        )
+        .is_err());
+        // Indices whose offset overflows are out of range too
+        for leaf in [u64::MAX / MAX_BYTES_PER_CHUNK as u64 + 1, u64::MAX] {
+            assert!(path_with_outboard(
+                std::io::Cursor::new(&input),
+                std::io::Cursor::new(&outboard),
+                &hash,
+                input.len() as u64,
+                leaf,
+            )
+            .is_err());
+        }
+    }
 }
@@ -0,0 +1,500 @@
+//! Proving the paths of many chunks, possibly under different roots, in one IVC chain.
+//!
+//! This does not merge `RecursiveSNARK`s which were already produced: arecibo does not expose
+//! folding two running instances together, so the chain is proven from the chunks' paths
+//! (`Blake3HashProof`s), back to back. Whoever proves it therefore needs the chunks' bytes and
+//! paths, not their proofs, and redoes their compressions. Every time a path reaches its root,
+//! the (root, chunk index) pair is absorbed into a running commitment, which is the public
+//! output of the chain together with the number of pairs absorbed.
+//!
+//! The commitment is a chain of keyed BLAKE3 hashes (see `commit_chunks`), so it costs one
+//! extra compression per chunk and the verifier can recompute it with the `blake3` crate.
+use arecibo::errors::NovaError;
+use arecibo::traits::circuit::{StepCircuit, TrivialCircuit};
+use arecibo::traits::snark::RelaxedR1CSSNARKTrait;
+use arecibo::traits::{Engine, Group};
+use arecibo::{PublicParams, RecursiveSNARK};
+use bellpepper::gadgets::uint32::UInt32;
+use bellpepper_core::boolean::{AllocatedBit, Boolean};
+use bellpepper_core::num::AllocatedNum;
+use bellpepper_core::{ConstraintSystem, SynthesisError};
+use ff::Field;
+use std::fmt;
+use std::time::Instant;
+use tracing::{info, info_span};
+
+use crate::blake3_bellpepper::{
+    alloc_expr, blake3_compression, is_equal, num_to_uint32, uint32_to_num,
+    Blake3BellpepperCircuit, Expr,
+};
+use crate::blake3_circuit::{Blake3CompressPubIO, IO_ARITY, IV};
+use crate::blake3_hash::{hash_with_path, Blake3HashProof};
+use crate::utils;
+
+/// The path IO, then the commitment's 8 words, whether the next step starts a new path
+/// and the number of paths committed to
+pub(crate) const MULTIPATH_ARITY: usize = IO_ARITY + 8 + 2;
+const ACC_IDX: usize = IO_ARITY;
+const FRESH_IDX: usize = IO_ARITY + 8;
+const COUNT_IDX: usize = IO_ARITY + 9;
+
+/// 32 bytes of root followed by the little endian chunk index
+const ENTRY_BYTES: u32 = 40;
+// CHUNK_START | CHUNK_END | ROOT | KEYED_HASH
+const KEYED_HASH_FLAGS: u32 = 1 | 2 | 8 | 16;
+
+/// The commitment to a list of (root, chunk index) pairs: starting from the all zero key,
+/// each pair is hashed with BLAKE3 keyed by the commitment so far
+pub fn commit_chunks(entries: &[([u8; 32], u64)]) -> [u8; 32] {
+    let mut acc = [0u8; 32];
+    for (root, chunk_idx) in entries {
+        let mut entry = root.to_vec();
+        entry.extend_from_slice(&chunk_idx.to_le_bytes());
+        acc = *blake3::keyed_hash(&acc, &entry).as_bytes();
+    }
+    acc
+}
+
+#[derive(Debug)]
+pub enum MultiPathError {
+    /// A chain needs at least one path to prove
+    NoPaths,
+    Nova(NovaError),
+}
+
+impl From<NovaError> for MultiPathError {
+    fn from(err: NovaError) -> Self {
+        MultiPathError::Nova(err)
+    }
+}
+
+impl fmt::Display for MultiPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiPathError::NoPaths => write!(f, "No paths to prove"),
+            MultiPathError::Nova(err) => write!(f, "Nova error: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for MultiPathError {}
+
+#[derive(Debug, Clone)]
+struct PathChunk<G: Group> {
+    circuit: Blake3BellpepperCircuit<G>,
+    // The IO the chunk's path starts from
+    z0: Vec<G::Scalar>,
+    n_compressions: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Blake3MultiPathCircuit<G: Group> {
+    chunks: Vec<PathChunk<G>>,
+    current_chunk: usize,
+    // The number of compressions done so far in the current chunk's path
+    compressions_done: usize,
+}
+
+impl<G: Group> Blake3MultiPathCircuit<G> {
+    pub fn new(hash_proofs: Vec<Blake3HashProof>) -> Result<Self, MultiPathError> {
+        if hash_proofs.is_empty() {
+            return Err(MultiPathError::NoPaths);
+        }
+        let chunks = hash_proofs
+            .into_iter()
+            .map(|hash_proof| {
+                let circuit =
+                    Blake3BellpepperCircuit::<G>::new(hash_proof.bytes, hash_proof.parent_path);
+                let total_depth = G::Scalar::from(circuit.total_depth as u64);
+                let z0 = Blake3CompressPubIO::<G>::new(
+                    hash_proof.chunk_idx,
+                    total_depth,
+                    G::Scalar::from(circuit.n_blocks as u64),
+                    IV.iter().map(|iv| G::Scalar::from(*iv as u64)).collect(),
+                    total_depth,
+                )
+                .to_vec();
+                let n_compressions = circuit.n_blocks + circuit.total_depth - 1;
+                PathChunk {
+                    circuit,
+                    z0,
+                    n_compressions,
+                }
+            })
+            .collect();
+        Ok(Blake3MultiPathCircuit {
+            chunks,
+            current_chunk: 0,
+            compressions_done: 0,
+        })
+    }
+
+    /// One step per compression, over all the chunks' paths
+    pub fn num_steps(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.n_compressions).sum()
+    }
+
+    /// The statement of each chunk, in order, see `multipath_num_steps`
+    pub fn statements(&self) -> Vec<(usize, usize)> {
+        self.chunks
+            .iter()
+            .map(|chunk| (chunk.circuit.n_blocks, chunk.circuit.total_depth))
+            .collect()
+    }
+
+    /// The IO the chain starts from: no commitments yet and about to start a new path
+    pub fn z0(&self) -> Vec<G::Scalar> {
+        let mut z0 = vec![G::Scalar::ZERO; MULTIPATH_ARITY];
+        z0[FRESH_IDX] = G::Scalar::ONE;
+        z0
+    }
+
+    pub fn update_for_step(&mut self) {
+        let chunk = &mut self.chunks[self.current_chunk];
+        chunk.circuit.update_for_step();
+        let n_compressions = chunk.n_compressions;
+        self.compressions_done += 1;
+        // Stay on the last chunk once it is done, there are no more steps to synthesize
+        if self.compressions_done == n_compressions && self.current_chunk + 1 < self.chunks.len() {
+            self.current_chunk += 1;
+            self.compressions_done = 0;
+        }
+    }
+}
+
+impl<G: Group> StepCircuit<G::Scalar> for Blake3MultiPathCircuit<G> {
+    fn arity(&self) -> usize {
+        MULTIPATH_ARITY
+    }
+
+    fn synthesize<CS: ConstraintSystem<G::Scalar>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<G::Scalar>],
+    ) -> Result<Vec<AllocatedNum<G::Scalar>>, SynthesisError> {
+        assert_eq!(z.len(), MULTIPATH_ARITY);
+        let one = CS::one();
+        let chunk = &self.chunks[self.current_chunk];
+
+        let fresh = AllocatedBit::alloc(
+            cs.namespace(|| "fresh"),
+            z[FRESH_IDX].get_value().map(|v| v == G::Scalar::ONE),
+        )?;
+        cs.enforce(
+            || "fresh is bit",
+            |lc| lc + fresh.get_variable(),
+            |lc| lc + one,
+            |lc| lc + z[FRESH_IDX].get_variable(),
+        );
+        let fresh = Boolean::from(fresh);
+
+        /************************* Start a new path ***********************/
+        // Only the parts of the starting IO which depend on the chunk are chosen by the prover
+        let alloc_start = |cs: &mut CS, i: usize| {
+            AllocatedNum::alloc(cs.namespace(|| format!("start {}", i)), || Ok(chunk.z0[i]))
+        };
+        let leaf_depth = alloc_start(cs, 14)?;
+        let mut start = Vec::with_capacity(IO_ARITY);
+        start.push(alloc_start(cs, 0)?);
+        start.push(alloc_expr(
+            cs.namespace(|| "start block_count"),
+            &Expr::constant(one, G::Scalar::ZERO),
+        )?);
+        for (i, iv) in IV.iter().enumerate() {
+            start.push(alloc_expr(
+                cs.namespace(|| format!("start h {}", i)),
+                &Expr::constant(one, G::Scalar::from(*iv as u64)),
+            )?);
+        }
+        start.push(alloc_start(cs, 10)?);
+        start.push(alloc_expr(
+            cs.namespace(|| "start depth"),
+            &Expr::num(&leaf_depth).sub(&Expr::constant(one, G::Scalar::ONE)),
+        )?);
+        start.push(alloc_start(cs, 12)?);
+        start.push(alloc_start(cs, 13)?);
+        start.push(leaf_depth);
+        // Every path carries the nonce the chain started from
+        start.push(z[15].clone());
+
+        let path_in = start
+            .iter()
+            .zip(z[..IO_ARITY].iter())
+            .enumerate()
+            .map(|(i, (start, prev))| {
+                utils::conditionally_select(
+                    cs.namespace(|| format!("path in {}", i)),
+                    start,
+                    prev,
+                    &fresh,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        /************************* Step along the path ***********************/
+        let path_out = chunk
+            .circuit
+            .synthesize(&mut cs.namespace(|| "path step"), &path_in)?;
+
+        // The path is done after the compression at depth 0 which absorbs the last block
+        let at_root = is_equal(
+            cs.namespace(|| "at root"),
+            &Expr::num(&path_in[11]),
+            &Expr::constant(one, G::Scalar::ZERO),
+        )?;
+        let blocks_done = is_equal(
+            cs.namespace(|| "blocks done"),
+            &Expr::num(&path_out[1]),
+            &Expr::num(&path_out[0]),
+        )?;
+        let finished = Boolean::and(cs.namespace(|| "finished"), &at_root, &blocks_done)?;
+
+        /************************* Commit to (root, chunk index) ***********************/
+        let acc = z[ACC_IDX..ACC_IDX + 8]
+            .iter()
+            .enumerate()
+            .map(|(i, word)| num_to_uint32(cs.namespace(|| format!("acc {}", i)), word))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut entry = path_out[2..10]
+            .iter()
+            .enumerate()
+            .map(|(i, word)| num_to_uint32(cs.namespace(|| format!("root {}", i)), word))
+            .collect::<Result<Vec<_>, _>>()?;
+        entry.push(num_to_uint32(
+            cs.namespace(|| "chunk_idx_low"),
+            &path_in[12],
+        )?);
+        entry.push(num_to_uint32(
+            cs.namespace(|| "chunk_idx_high"),
+            &path_in[13],
+        )?);
+        entry.extend((0..6).map(|_| UInt32::constant(0)));
+        let committed = blake3_compression(
+            cs.namespace(|| "keyed hash"),
+            &acc,
+            &entry,
+            [UInt32::constant(0), UInt32::constant(0)],
+            UInt32::constant(ENTRY_BYTES),
+            UInt32::constant(KEYED_HASH_FLAGS),
+        )?;
+
+        let mut out = path_out;
+        for (i, word) in committed.iter().enumerate() {
+            let committed = uint32_to_num(cs.namespace(|| format!("committed {}", i)), word)?;
+            out.push(utils::conditionally_select(
+                cs.namespace(|| format!("acc out {}", i)),
+                &committed,
+                &z[ACC_IDX + i],
+                &finished,
+            )?);
+        }
+        out.push(alloc_expr(
+            cs.namespace(|| "fresh out"),
+            &Expr::bit(one, &finished),
+        )?);
+        out.push(alloc_expr(
+            cs.namespace(|| "count out"),
+            &Expr::num(&z[COUNT_IDX]).add(&Expr::bit(one, &finished)),
+        )?);
+        Ok(out)
+    }
+}
+
+/// The public parameters of the multi-path chain. The paths do not change the shape of the
+/// step, so one set of parameters proves any chunks under any roots.
+pub fn setup_multipath_params<E1, E2, SS1, SS2>() -> PublicParams<
+    E1,
+    E2,
+    Blake3MultiPathCircuit<<E1 as Engine>::GE>,
+    TrivialCircuit<<E2 as Engine>::Scalar>,
+>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    let (_, hash_proof) = hash_with_path(&[0u8], 0).expect("Failed to hash a single byte");
+    let circuit_primary = Blake3MultiPathCircuit::<<E1 as Engine>::GE>::new(vec![hash_proof])
+        .expect("One path is enough");
+    let start = Instant::now();
+    let pp = PublicParams::<
+        E1,
+        E2,
+        Blake3MultiPathCircuit<<E1 as Engine>::GE>,
+        TrivialCircuit<<E2 as Engine>::Scalar>,
+    >::setup(
+        &circuit_primary,
+        &TrivialCircuit::default(),
+        &*SS1::ck_floor(),
+        &*SS2::ck_floor(),
+    );
+    info!(elapsed = ?start.elapsed(), "PublicParams::setup");
+    pp
+}
+
+/// The number of steps of a chain over chunks with these (block count, total depth)
+/// statements, as given for each chunk by `audit::Challenge::expected_statement`
+pub fn multipath_num_steps(statements: &[(usize, usize)]) -> usize {
+    statements
+        .iter()
+        .map(|(n_blocks, total_depth)| n_blocks + total_depth - 1)
+        .sum()
+}
+
+/// Prove the paths of all the chunks in `hash_proofs` in a single chain, redoing all their
+/// compressions. Returns the commitment to their (root, chunk index) pairs, in order, or
+/// `MultiPathError::NoPaths` if there are none
+pub fn prove_multipath<E1, E2, SS1, SS2>(
+    hash_proofs: Vec<Blake3HashProof>,
+) -> Result<
+    (
+        Vec<u8>,
+        PublicParams<
+            E1,
+            E2,
+            Blake3MultiPathCircuit<<E1 as Engine>::GE>,
+            TrivialCircuit<<E2 as Engine>::Scalar>,
+        >,
+        RecursiveSNARK<
+            E1,
+            E2,
+            Blake3MultiPathCircuit<<E1 as Engine>::GE>,
+            TrivialCircuit<<E2 as Engine>::Scalar>,
+        >,
+    ),
+    MultiPathError,
+>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    let _span = info_span!("prove_multipath", n_chunks = hash_proofs.len()).entered();
+    let mut circuit_primary = Blake3MultiPathCircuit::<<E1 as Engine>::GE>::new(hash_proofs)?;
+    let circuit_secondary = TrivialCircuit::default();
+    let num_steps = circuit_primary.num_steps();
+    let pp = setup_multipath_params::<E1, E2, SS1, SS2>();
+
+    let z0_primary = circuit_primary.z0();
+    let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+    let mut recursive_snark = RecursiveSNARK::new(
+        &pp,
+        &circuit_primary,
+        &circuit_secondary,
+        &z0_primary,
+        &z0_secondary,
+    )?;
+    for i in 0..num_steps {
+        let start = Instant::now();
+        let res = recursive_snark.prove_step(&pp, &circuit_primary, &circuit_secondary);
+        circuit_primary.update_for_step();
+        info!(
+            step = i,
+            num_steps,
+            ok = res.is_ok(),
+            elapsed = ?start.elapsed(),
+            "RecursiveSNARK::prove_step"
+        );
+        res?;
+    }
+
+    let (zn_primary, _) = recursive_snark.verify(&pp, num_steps, &z0_primary, &z0_secondary)?;
+    let commitment = utils::format_scalar_blake_hash::<<E1 as Engine>::GE>(
+        zn_primary[ACC_IDX..ACC_IDX + 8].try_into().unwrap(),
+    );
+    Ok((commitment, pp, recursive_snark))
+}
+
+/// Verify a multi-path proof of `num_steps` steps against the (root, chunk index) pairs it
+/// should commit to. `multipath_num_steps` gives `num_steps` from the chunks' statements
+pub fn verify_multipath<E1, E2>(
+    pp: &PublicParams<
+        E1,
+        E2,
+        Blake3MultiPathCircuit<<E1 as Engine>::GE>,
+        TrivialCircuit<<E2 as Engine>::Scalar>,
+    >,
+    recursive_snark: &RecursiveSNARK<
+        E1,
+        E2,
+        Blake3MultiPathCircuit<<E1 as Engine>::GE>,
+        TrivialCircuit<<E2 as Engine>::Scalar>,
+    >,
+    num_steps: usize,
+    entries: &[([u8; 32], u64)],
+) -> Result<bool, NovaError>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    let mut z0_primary = vec![<E1 as Engine>::Scalar::ZERO; MULTIPATH_ARITY];
+    z0_primary[FRESH_IDX] = <E1 as Engine>::Scalar::ONE;
+    let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+    let (zn_primary, _) = recursive_snark.verify(pp, num_steps, &z0_primary, &z0_secondary)?;
+
+    let commitment = utils::format_scalar_blake_hash::<<E1 as Engine>::GE>(
+        zn_primary[ACC_IDX..ACC_IDX + 8].try_into().unwrap(),
+    );
+    // Every path must have been finished
+    Ok(zn_primary[FRESH_IDX] == <E1 as Engine>::Scalar::ONE
+        && zn_primary[COUNT_IDX] == <E1 as Engine>::Scalar::from(entries.len() as u64)
+        && commitment == commit_chunks(entries).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::{PallasEngine, VestaEngine};
+
+    use super::*;
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = arecibo::provider::ipa_pc::EvaluationEngine<E1>;
+    type EE2 = arecibo::provider::ipa_pc::EvaluationEngine<E2>;
+    type S1 = arecibo::spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = arecibo::spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+
+    #[test]
+    fn test_multipath_chunks() {
+        let file_a = vec![1u8; 1024 * 3 + 5];
+        let file_b = vec![2u8; 17];
+        let mut entries = vec![];
+        let mut hash_proofs = vec![];
+        for (data, chunk_idx) in [(&file_a, 0), (&file_b, 0), (&file_a, 3)] {
+            let (hash, hash_proof) = hash_with_path(data, chunk_idx).unwrap();
+            entries.push((*hash.as_bytes(), chunk_idx as u64));
+            hash_proofs.push(hash_proof);
+        }
+        // What the verifier knows of each chunk: its block count and depth
+        let statements: Vec<_> = hash_proofs
+            .iter()
+            .map(|hash_proof| {
+                (
+                    utils::n_blocks_from_bytes(hash_proof.bytes.len()),
+                    hash_proof.parent_path.len() + 1,
+                )
+            })
+            .collect();
+        let num_steps = multipath_num_steps(&statements);
+        let circuit =
+            Blake3MultiPathCircuit::<<E1 as Engine>::GE>::new(hash_proofs.clone()).unwrap();
+        assert_eq!(circuit.statements(), statements);
+        assert_eq!(circuit.num_steps(), num_steps);
+
+        let (commitment, pp, recursive_snark) =
+            prove_multipath::<E1, E2, S1, S2>(hash_proofs).unwrap();
+        assert_eq!(commitment, commit_chunks(&entries).to_vec());
+        assert!(verify_multipath(&pp, &recursive_snark, num_steps, &entries).unwrap());
+
+        // Committing to a different list must not verify
+        entries.swap(0, 2);
+        assert!(!verify_multipath(&pp, &recursive_snark, num_steps, &entries).unwrap());
+
+        assert!(matches!(
+            prove_multipath::<E1, E2, S1, S2>(vec![]),
+            Err(MultiPathError::NoPaths)
+        ));
+    }
+}
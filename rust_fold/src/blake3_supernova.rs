@@ -0,0 +1,632 @@
+//! Non-uniform IVC (SuperNova) over a BLAKE3 path.
+//!
+//! `Blake3BlockCompressCircuit` handles leaf blocks and parent nodes in one circuit, so every
+//! fold pays for the parent muxing and the 64-way path bit lookup as well as the leaf flag logic.
+//! Here leaf blocks and parent nodes get separate step circuits with the same 16 element IO
+//! (see `Blake3CompressPubIO`). The circuit for each step is picked from the same
+//! `CircuitCursor` that `Blake3BlockCompressCircuit` tracks.
+use arecibo::supernova::error::SuperNovaError;
+use arecibo::supernova::{
+    NonUniformCircuit, PublicParams, RecursiveSNARK, StepCircuit, TrivialSecondaryCircuit,
+};
+use arecibo::traits::snark::RelaxedR1CSSNARKTrait;
+use arecibo::traits::{Engine, Group};
+use bellpepper::gadgets::uint32::UInt32;
+use bellpepper_core::boolean::{AllocatedBit, Boolean};
+use bellpepper_core::num::AllocatedNum;
+use bellpepper_core::{ConstraintSystem, SynthesisError};
+use ff::Field;
+use std::time::Instant;
+use tracing::{info, info_span};
+
+use crate::blake3_bellpepper::{
+    alloc_expr, blake3_compression, goes_left_at_depth, is_equal, less_than, mux, num_to_uint32,
+    to_bits, uint32_to_num, Expr,
+};
+use crate::blake3_circuit::{
+    leaf_block_words, parent_block_words, Blake3CompressPubIO, CircuitCursor, PathNode, IO_ARITY,
+    IV,
+};
+use crate::blake3_hash::Blake3HashProof;
+use crate::utils;
+
+pub(crate) const LEAF_CIRCUIT_INDEX: usize = 0;
+pub(crate) const PARENT_CIRCUIT_INDEX: usize = 1;
+
+const CHUNK_START: usize = 0;
+const CHUNK_END: usize = 1;
+const PARENT: usize = 2;
+const ROOT: usize = 3;
+
+/// Compresses one block of the leaf chunk
+#[derive(Debug, Clone)]
+pub struct Blake3LeafCircuit<G: Group> {
+    m: Vec<u32>,
+    b: u64,
+    _p: std::marker::PhantomData<G>,
+}
+
+/// Compresses a parent node from the running chaining value and the sibling's chaining value
+#[derive(Debug, Clone)]
+pub struct Blake3ParentCircuit<G: Group> {
+    sibling: Vec<u32>,
+    _p: std::marker::PhantomData<G>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Blake3NonUniformCircuit<G: Group> {
+    Leaf(Blake3LeafCircuit<G>),
+    Parent(Blake3ParentCircuit<G>),
+}
+
+/// The cursor along the path from the leaf chunk to the root, handing out the step circuit
+/// for every fold
+#[derive(Debug, Clone)]
+pub struct Blake3PathCircuits<G: Group> {
+    leaf_bytes: Vec<u8>,
+    pub(crate) total_depth: usize,
+    pub(crate) n_blocks: usize,
+    cursor: CircuitCursor,
+    parent_path: Vec<PathNode>,
+    _p: std::marker::PhantomData<G>,
+}
+
+impl<G: Group> Blake3PathCircuits<G> {
+    pub fn new(bytes: Vec<u8>, parent_path: Vec<PathNode>) -> Self {
+        let n_blocks = utils::n_blocks_from_bytes(bytes.len());
+        let depth = parent_path.len() + 1;
+        Blake3PathCircuits {
+            leaf_bytes: bytes,
+            total_depth: depth,
+            n_blocks,
+            cursor: CircuitCursor::new(depth),
+            parent_path,
+            _p: std::marker::PhantomData,
+        }
+    }
+
+    /// One step per block plus one per parent above the leaf
+    pub fn num_steps(&self) -> usize {
+        self.n_blocks + self.total_depth - 1
+    }
+
+    pub fn update_for_step(&mut self) {
+        self.cursor.advance(self.n_blocks, self.total_depth);
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.cursor.is_leaf(self.n_blocks)
+    }
+
+    fn leaf_circuit(&self) -> Blake3LeafCircuit<G> {
+        let (m, b) = if self.is_leaf() {
+            leaf_block_words(&self.leaf_bytes, self.cursor.block())
+        } else {
+            // Only used for the circuit's shape
+            (vec![0u32; 16], 0)
+        };
+        Blake3LeafCircuit {
+            m,
+            b,
+            _p: std::marker::PhantomData,
+        }
+    }
+
+    fn parent_circuit(&self) -> Blake3ParentCircuit<G> {
+        let sibling = if self.is_leaf() {
+            // Only used for the circuit's shape
+            vec![0u32; 8]
+        } else {
+            parent_block_words(&self.parent_path[self.cursor.depth()])[..8].to_vec()
+        };
+        Blake3ParentCircuit {
+            sibling,
+            _p: std::marker::PhantomData,
+        }
+    }
+
+    /// The step circuit for the current position
+    pub fn current_circuit(&self) -> Blake3NonUniformCircuit<G> {
+        if self.is_leaf() {
+            Blake3NonUniformCircuit::Leaf(self.leaf_circuit())
+        } else {
+            Blake3NonUniformCircuit::Parent(self.parent_circuit())
+        }
+    }
+}
+
+impl<E1, E2>
+    NonUniformCircuit<
+        E1,
+        E2,
+        Blake3NonUniformCircuit<<E1 as Engine>::GE>,
+        TrivialSecondaryCircuit<<E2 as Engine>::Scalar>,
+    > for Blake3PathCircuits<<E1 as Engine>::GE>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    fn num_circuits(&self) -> usize {
+        2
+    }
+
+    fn primary_circuit(&self, circuit_index: usize) -> Blake3NonUniformCircuit<<E1 as Engine>::GE> {
+        match circuit_index {
+            LEAF_CIRCUIT_INDEX => Blake3NonUniformCircuit::Leaf(self.leaf_circuit()),
+            PARENT_CIRCUIT_INDEX => Blake3NonUniformCircuit::Parent(self.parent_circuit()),
+            _ => panic!("No circuit with index {}", circuit_index),
+        }
+    }
+
+    fn secondary_circuit(&self) -> TrivialSecondaryCircuit<<E2 as Engine>::Scalar> {
+        Default::default()
+    }
+
+    fn initial_circuit_index(&self) -> usize {
+        LEAF_CIRCUIT_INDEX
+    }
+}
+
+impl<G: Group> Blake3LeafCircuit<G> {
+    fn synthesize<CS: ConstraintSystem<G::Scalar>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<G::Scalar>],
+    ) -> Result<
+        (
+            Option<AllocatedNum<G::Scalar>>,
+            Vec<AllocatedNum<G::Scalar>>,
+        ),
+        SynthesisError,
+    > {
+        let one = CS::one();
+        let n_blocks = Expr::num(&z[0]);
+        let block_count = Expr::num(&z[1]);
+        let depth = Expr::num(&z[11]);
+        let leaf_depth = Expr::num(&z[14]);
+
+        // Leaf blocks are always compressed at the leaf's depth
+        cs.enforce(
+            || "depth is leaf depth",
+            |_| depth.lc.clone(),
+            |lc| lc + one,
+            |_| leaf_depth.sub(&Expr::constant(one, G::Scalar::ONE)).lc,
+        );
+        // There are at most 16 blocks in a chunk and we must not be past the last one
+        to_bits(cs.namespace(|| "block_count range"), &block_count, 5)?;
+        to_bits(cs.namespace(|| "n_blocks range"), &n_blocks, 5)?;
+        let in_chunk = less_than(cs.namespace(|| "in chunk"), &block_count, &n_blocks, 5)?;
+        Boolean::enforce_equal(
+            cs.namespace(|| "block in chunk"),
+            &in_chunk,
+            &Boolean::constant(true),
+        )?;
+
+        let zero = Expr::constant(one, G::Scalar::ZERO);
+        let is_root = is_equal(cs.namespace(|| "is_root"), &depth, &zero)?;
+        let is_first = is_equal(cs.namespace(|| "is_first"), &block_count, &zero)?;
+        let is_last = is_equal(
+            cs.namespace(|| "is_last"),
+            &block_count,
+            &n_blocks.sub(&Expr::constant(one, G::Scalar::ONE)),
+        )?;
+        let mut flags = vec![Boolean::constant(false); 32];
+        flags[CHUNK_START] = is_first;
+        flags[CHUNK_END] = is_last.clone();
+        // A chunk is only the root if it is the whole tree
+        flags[ROOT] = Boolean::and(cs.namespace(|| "root"), &is_last, &is_root)?;
+        let d = UInt32::from_bits(&flags);
+
+        let h = z[2..10]
+            .iter()
+            .enumerate()
+            .map(|(i, h)| num_to_uint32(cs.namespace(|| format!("h {}", i)), h))
+            .collect::<Result<Vec<_>, _>>()?;
+        let m = self
+            .m
+            .iter()
+            .enumerate()
+            .map(|(i, w)| UInt32::alloc(cs.namespace(|| format!("m {}", i)), Some(*w)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let b = UInt32::alloc(cs.namespace(|| "b"), Some(self.b as u32))?;
+        let t = [
+            num_to_uint32(cs.namespace(|| "t0"), &z[12])?,
+            num_to_uint32(cs.namespace(|| "t1"), &z[13])?,
+        ];
+
+        let h_out = blake3_compression(cs.namespace(|| "blake3 compression"), &h, &m, t, b, d)?
+            .iter()
+            .enumerate()
+            .map(|(i, word)| uint32_to_num(cs.namespace(|| format!("h_out {}", i)), word))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let block_count_out = alloc_expr(
+            cs.namespace(|| "block_count_out"),
+            &block_count.add(&Expr::constant(one, G::Scalar::ONE)),
+        )?;
+        // Move up to the parents after the last block, unless the chunk is the root
+        let decr_depth = Boolean::and(cs.namespace(|| "decr depth"), &is_last, &is_root.not())?;
+        let depth_out = alloc_expr(
+            cs.namespace(|| "depth_out"),
+            &depth.sub(&Expr::bit(one, &decr_depth)),
+        )?;
+        // The next step is a parent iff this was the last block (PARENT_CIRCUIT_INDEX is 1)
+        let pc_out = alloc_expr(cs.namespace(|| "pc_out"), &Expr::bit(one, &is_last))?;
+
+        let mut out = Vec::with_capacity(IO_ARITY);
+        out.push(z[0].clone());
+        out.push(block_count_out);
+        out.extend(h_out);
+        out.push(z[10].clone());
+        out.push(depth_out);
+        out.extend_from_slice(&z[12..IO_ARITY]);
+        Ok((Some(pc_out), out))
+    }
+}
+
+impl<G: Group> Blake3ParentCircuit<G> {
+    fn synthesize<CS: ConstraintSystem<G::Scalar>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<G::Scalar>],
+    ) -> Result<
+        (
+            Option<AllocatedNum<G::Scalar>>,
+            Vec<AllocatedNum<G::Scalar>>,
+        ),
+        SynthesisError,
+    > {
+        let one = CS::one();
+        let n_blocks = Expr::num(&z[0]);
+        let block_count = Expr::num(&z[1]);
+        let total_depth = Expr::num(&z[10]);
+        let depth = Expr::num(&z[11]);
+        let leaf_depth = Expr::num(&z[14]);
+
+        // All the leaf's blocks have been absorbed
+        cs.enforce(
+            || "leaf absorbed",
+            |_| block_count.lc.clone(),
+            |lc| lc + one,
+            |_| n_blocks.lc.clone(),
+        );
+        // Parents are strictly above the leaf. Max depth is 64
+        to_bits(cs.namespace(|| "depth range"), &depth, 8)?;
+        to_bits(cs.namespace(|| "leaf_depth range"), &leaf_depth, 8)?;
+        let is_parent = less_than(
+            cs.namespace(|| "is_parent"),
+            &depth,
+            &leaf_depth.sub(&Expr::constant(one, G::Scalar::ONE)),
+            8,
+        )?;
+        Boolean::enforce_equal(
+            cs.namespace(|| "above leaf"),
+            &is_parent,
+            &Boolean::constant(true),
+        )?;
+        let is_root = is_equal(
+            cs.namespace(|| "is_root"),
+            &depth,
+            &Expr::constant(one, G::Scalar::ZERO),
+        )?;
+
+        /************************* Get the child side ***********************/
+        let low_bits = to_bits(
+            cs.namespace(|| "chunk_idx_low bits"),
+            &Expr::num(&z[12]),
+            32,
+        )?;
+        let high_bits = to_bits(
+            cs.namespace(|| "chunk_idx_high bits"),
+            &Expr::num(&z[13]),
+            32,
+        )?;
+        let idx_bits: Vec<_> = low_bits.into_iter().chain(high_bits).collect();
+        let bit_at_depth = goes_left_at_depth(
+            cs.namespace(|| "goes left"),
+            &depth,
+            &total_depth,
+            &idx_bits,
+        )?;
+        let down_left = AllocatedBit::alloc(
+            cs.namespace(|| "down left"),
+            bit_at_depth.value.map(|v| v == G::Scalar::ONE),
+        )?;
+        cs.enforce(
+            || "down left is bit at depth",
+            |lc| lc + down_left.get_variable(),
+            |lc| lc + one,
+            |_| bit_at_depth.lc.clone(),
+        );
+        let down_left = Boolean::from(down_left);
+
+        /************************* Order the children ***********************/
+        let sibling = self
+            .sibling
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", i)), || {
+                    Ok(G::Scalar::from(*w as u64))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut m = Vec::with_capacity(16);
+        for i in 0..16 {
+            let mut cs = cs.namespace(|| format!("m {}", i));
+            // If we descend to the left, the running chaining value is the left child
+            let (if_left, if_right) = if i < 8 {
+                (Expr::num(&z[2 + i]), Expr::num(&sibling[i]))
+            } else {
+                (Expr::num(&sibling[i - 8]), Expr::num(&z[2 + i - 8]))
+            };
+            let word = mux(cs.namespace(|| "side"), &down_left, &if_left, &if_right)?;
+            m.push(num_to_uint32(cs.namespace(|| "word"), &word)?);
+        }
+
+        /************************* Compress ***********************/
+        let mut flags = vec![Boolean::constant(false); 32];
+        flags[PARENT] = Boolean::constant(true);
+        flags[ROOT] = is_root.clone();
+        let d = UInt32::from_bits(&flags);
+        let h: Vec<UInt32> = IV.iter().map(|iv| UInt32::constant(*iv)).collect();
+        // Parents always use a counter of 0 and a full block
+        let t = [UInt32::constant(0), UInt32::constant(0)];
+        let b = UInt32::constant(64);
+
+        let h_out = blake3_compression(cs.namespace(|| "blake3 compression"), &h, &m, t, b, d)?
+            .iter()
+            .enumerate()
+            .map(|(i, word)| uint32_to_num(cs.namespace(|| format!("h_out {}", i)), word))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Move up unless we are at the root
+        let depth_out = alloc_expr(
+            cs.namespace(|| "depth_out"),
+            &depth
+                .sub(&Expr::constant(one, G::Scalar::ONE))
+                .add(&Expr::bit(one, &is_root)),
+        )?;
+        // A parent is always followed by a parent
+        let pc_out = alloc_expr(
+            cs.namespace(|| "pc_out"),
+            &Expr::constant(one, G::Scalar::from(PARENT_CIRCUIT_INDEX as u64)),
+        )?;
+
+        let mut out = Vec::with_capacity(IO_ARITY);
+        out.extend_from_slice(&z[0..2]);
+        out.extend(h_out);
+        out.push(z[10].clone());
+        out.push(depth_out);
+        out.extend_from_slice(&z[12..IO_ARITY]);
+        Ok((Some(pc_out), out))
+    }
+}
+
+impl<G: Group> StepCircuit<G::Scalar> for Blake3NonUniformCircuit<G> {
+    fn arity(&self) -> usize {
+        IO_ARITY
+    }
+
+    fn circuit_index(&self) -> usize {
+        match self {
+            Blake3NonUniformCircuit::Leaf(_) => LEAF_CIRCUIT_INDEX,
+            Blake3NonUniformCircuit::Parent(_) => PARENT_CIRCUIT_INDEX,
+        }
+    }
+
+    fn synthesize<CS: ConstraintSystem<G::Scalar>>(
+        &self,
+        cs: &mut CS,
+        _pc: Option<&AllocatedNum<G::Scalar>>,
+        z: &[AllocatedNum<G::Scalar>],
+    ) -> Result<
+        (
+            Option<AllocatedNum<G::Scalar>>,
+            Vec<AllocatedNum<G::Scalar>>,
+        ),
+        SynthesisError,
+    > {
+        assert_eq!(z.len(), IO_ARITY);
+        // SuperNova already checks that the program counter selects this circuit
+        match self {
+            Blake3NonUniformCircuit::Leaf(c) => c.synthesize(cs, z),
+            Blake3NonUniformCircuit::Parent(c) => c.synthesize(cs, z),
+        }
+    }
+}
+
+/// Prove the path from a chunk to the root with non-uniform IVC, using a leaf circuit
+/// for the chunk's blocks and a parent circuit for every node above it.
+/// If there is a `nonce` it is bound into the initial IO, and the proof only verifies for it.
+pub fn prove_chunk_hash_supernova<E1, E2, SS1, SS2>(
+    hash_proof: Blake3HashProof,
+    nonce: Option<&[u8; 32]>,
+) -> Result<
+    (
+        Vec<u8>,
+        PublicParams<
+            E1,
+            E2,
+            Blake3NonUniformCircuit<<E1 as Engine>::GE>,
+            TrivialSecondaryCircuit<<E2 as Engine>::Scalar>,
+        >,
+        RecursiveSNARK<E1, E2>,
+    ),
+    SuperNovaError,
+>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    let _span = info_span!(
+        "prove_chunk_hash_supernova",
+        chunk_idx = hash_proof.chunk_idx
+    )
+    .entered();
+    let mut circuits =
+        Blake3PathCircuits::<<E1 as Engine>::GE>::new(hash_proof.bytes, hash_proof.parent_path);
+    let num_steps = circuits.num_steps();
+
+    let start = Instant::now();
+    let pp = PublicParams::<
+        E1,
+        E2,
+        Blake3NonUniformCircuit<<E1 as Engine>::GE>,
+        TrivialSecondaryCircuit<<E2 as Engine>::Scalar>,
+    >::setup(&circuits, &*SS1::ck_floor(), &*SS2::ck_floor());
+    info!(elapsed = ?start.elapsed(), "PublicParams::setup");
+
+    let total_depth = <E1 as Engine>::Scalar::from(circuits.total_depth as u64);
+    let mut z0_primary = Blake3CompressPubIO::<<E1 as Engine>::GE>::new(
+        hash_proof.chunk_idx,
+        total_depth,
+        <E1 as Engine>::Scalar::from(circuits.n_blocks as u64),
+        IV.iter()
+            .map(|iv| <E1 as Engine>::Scalar::from(*iv as u64))
+            .collect(),
+        total_depth,
+    );
+    if let Some(nonce) = nonce {
+        z0_primary = z0_primary.with_nonce(nonce);
+    }
+    let z0_primary = z0_primary.to_vec();
+    let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+    let circuit_secondary = TrivialSecondaryCircuit::default();
+
+    let mut recursive_snark: Option<RecursiveSNARK<E1, E2>> = None;
+    for i in 0..num_steps {
+        let start = Instant::now();
+        let circuit_primary = circuits.current_circuit();
+        let mut snark = match recursive_snark {
+            Some(snark) => snark,
+            None => RecursiveSNARK::new(
+                &pp,
+                &circuits,
+                &circuit_primary,
+                &circuit_secondary,
+                &z0_primary,
+                &z0_secondary,
+            )?,
+        };
+        snark.prove_step(&pp, &circuit_primary, &circuit_secondary)?;
+        info!(
+            step = i,
+            num_steps,
+            circuit_index = circuit_primary.circuit_index(),
+            elapsed = ?start.elapsed(),
+            "RecursiveSNARK::prove_step"
+        );
+        circuits.update_for_step();
+        recursive_snark = Some(snark);
+    }
+    let recursive_snark = recursive_snark.expect("At least one step is always proven");
+
+    let start = Instant::now();
+    let (zn_primary, _) = recursive_snark.verify(&pp, &z0_primary, &z0_secondary)?;
+    info!(elapsed = ?start.elapsed(), "RecursiveSNARK::verify");
+
+    let output_hash = utils::format_scalar_blake_hash::<<E1 as Engine>::GE>(
+        zn_primary[2..10].try_into().unwrap(),
+    );
+    Ok((output_hash, pp, recursive_snark))
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::{PallasEngine, VestaEngine};
+    use arecibo::traits::circuit::StepCircuit as UniformStepCircuit;
+    use bellpepper_core::test_cs::TestConstraintSystem;
+
+    use super::*;
+    use crate::blake3_bellpepper::Blake3BellpepperCircuit;
+    use crate::blake3_hash::hash_with_path;
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = arecibo::provider::ipa_pc::EvaluationEngine<E1>;
+    type EE2 = arecibo::provider::ipa_pc::EvaluationEngine<E2>;
+    type S1 = arecibo::spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = arecibo::spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+    type G = <E1 as Engine>::GE;
+    type F = <E1 as Engine>::Scalar;
+
+    fn alloc_z(cs: &mut TestConstraintSystem<F>, z: &[F]) -> Vec<AllocatedNum<F>> {
+        z.iter()
+            .enumerate()
+            .map(|(i, v)| AllocatedNum::alloc(cs.namespace(|| format!("z {}", i)), || Ok(*v)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    /// Step the non-uniform circuits alongside the uniform bellpepper circuit, checking the
+    /// outputs agree and that each non-uniform step is smaller
+    fn check_against_uniform(data: &[u8], chunk_idx: usize) {
+        let (hash, hash_proof) = hash_with_path(data, chunk_idx).unwrap();
+        let mut circuits =
+            Blake3PathCircuits::<G>::new(hash_proof.bytes.clone(), hash_proof.parent_path.clone());
+        let mut uniform =
+            Blake3BellpepperCircuit::<G>::new(hash_proof.bytes, hash_proof.parent_path);
+
+        let total_depth = F::from(circuits.total_depth as u64);
+        let mut z = Blake3CompressPubIO::<G>::new(
+            hash_proof.chunk_idx,
+            total_depth,
+            F::from(circuits.n_blocks as u64),
+            IV.iter().map(|iv| F::from(*iv as u64)).collect(),
+            total_depth,
+        )
+        .to_vec();
+
+        for i in 0..circuits.num_steps() {
+            let mut cs = TestConstraintSystem::<F>::new();
+            let z_alloc = alloc_z(&mut cs, &z);
+            let (pc, out) = circuits
+                .current_circuit()
+                .synthesize(&mut cs, None, &z_alloc)
+                .unwrap();
+            assert!(cs.is_satisfied(), "{:?}", cs.which_is_unsatisfied());
+
+            let mut uniform_cs = TestConstraintSystem::<F>::new();
+            let uniform_z = alloc_z(&mut uniform_cs, &z);
+            let uniform_out = uniform.synthesize(&mut uniform_cs, &uniform_z).unwrap();
+
+            let out: Vec<F> = out.iter().map(|x| x.get_value().unwrap()).collect();
+            let uniform_out: Vec<F> = uniform_out.iter().map(|x| x.get_value().unwrap()).collect();
+            assert_eq!(out, uniform_out, "Output mismatch at step {}", i);
+            assert!(cs.num_constraints() < uniform_cs.num_constraints());
+
+            circuits.update_for_step();
+            uniform.update_for_step();
+            if i + 1 < circuits.num_steps() {
+                let expected_pc = circuits.current_circuit().circuit_index() as u64;
+                assert_eq!(pc.unwrap().get_value().unwrap(), F::from(expected_pc));
+            }
+            z = out;
+        }
+
+        let output_hash = utils::format_scalar_blake_hash::<G>(z[2..10].try_into().unwrap());
+        assert_eq!(output_hash, hash.as_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_non_uniform_steps() {
+        check_against_uniform(&[117u8; 17], 0);
+        let data = vec![0u8; 1024 * 3 + 5];
+        check_against_uniform(&data, 1);
+        check_against_uniform(&data, 3);
+    }
+
+    #[test]
+    fn test_prove_supernova() {
+        let data = vec![9u8; 1024 * 3 + 5];
+        for chunk_idx in [0, 2, 3] {
+            let (hash, hash_proof) = hash_with_path(&data, chunk_idx).unwrap();
+            let ret = prove_chunk_hash_supernova::<E1, E2, S1, S2>(hash_proof, None);
+            assert!(ret.is_ok());
+            assert_eq!(ret.unwrap().0, hash.as_bytes().to_vec());
+        }
+    }
+}
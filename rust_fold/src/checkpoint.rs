@@ -0,0 +1,253 @@
+//! Saving an in-progress `RecursiveSNARK` every few steps, so a crash in a long proof (a deep
+//! tree, or many compressions per step) resumes from the last checkpoint instead of step 0.
+//!
+//! A chunk has a single checkpoint file per input (the file name holds the circuit's input
+//! digest, so proofs of the same chunk index in different files never overwrite each other),
+//! overwritten as the proof goes on and removed once it is done. Checkpointing is best effort: failing to write one, or finding one for other inputs
+//! or parameters, is logged and proving carries on.
+use arecibo::traits::Engine;
+use arecibo::RecursiveSNARK;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use tracing::{debug, info, warn};
+
+use crate::blake3_circuit::CircuitCursor;
+use crate::codec::{self, Kind};
+use crate::setup_digest::{self, SetupDigest};
+use crate::{C1, C2};
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub(crate) struct Checkpoint<'a, E1, E2>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    pub(crate) chunk_idx: u64,
+    /// `Blake3BlockCompressCircuit::input_digest` of the circuit being proven
+    pub(crate) input_digest: [u8; 32],
+    pub(crate) params_digest: SetupDigest,
+    /// The nonce the proof is bound to, if any
+    pub(crate) nonce: Option<[u8; 32]>,
+    pub(crate) steps_done: usize,
+    pub(crate) cursor: CircuitCursor,
+    /// Borrowed when saving, so the proof is not copied just to be written out
+    pub(crate) recursive_snark: Cow<'a, RecursiveSNARK<E1, E2, C1<E1>, C2<E2>>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Checkpointer {
+    dir: PathBuf,
+    every_n_steps: usize,
+}
+
+impl Checkpointer {
+    /// Checkpoint every `every_n_steps` steps into files in `dir`
+    pub fn new(dir: impl Into<PathBuf>, every_n_steps: usize) -> Self {
+        assert!(every_n_steps > 0);
+        Checkpointer {
+            dir: dir.into(),
+            every_n_steps,
+        }
+    }
+
+    fn path(&self, chunk_idx: u64, input_digest: &[u8; 32]) -> PathBuf {
+        self.dir.join(format!(
+            "chunk_{}_{}.checkpoint",
+            chunk_idx,
+            setup_digest::to_hex(input_digest)
+        ))
+    }
+
+    /// Whether to checkpoint after `steps_done` steps. There is no point once all are done
+    pub(crate) fn is_due(&self, steps_done: usize, num_steps: usize) -> bool {
+        steps_done % self.every_n_steps == 0 && steps_done < num_steps
+    }
+
+    pub(crate) fn save<E1, E2>(&self, checkpoint: &Checkpoint<E1, E2>)
+    where
+        E1: Engine<Base = <E2 as Engine>::Scalar>,
+        E2: Engine<Base = <E1 as Engine>::Scalar>,
+    {
+        let path = self.path(checkpoint.chunk_idx, &checkpoint.input_digest);
+        // Written next to the checkpoint and renamed, so a crash while writing keeps the last one
+        let tmp = path.with_extension("checkpoint.tmp");
+        let res = std::fs::create_dir_all(&self.dir)
+            .map_err(codec::CodecError::from)
+            .and_then(|_| codec::write_file(&tmp, Kind::Checkpoint, checkpoint))
+            .and_then(|_| Ok(std::fs::rename(&tmp, &path)?));
+        match res {
+            Ok(()) => debug!(
+                steps_done = checkpoint.steps_done,
+                ?path,
+                "Saved checkpoint"
+            ),
+            Err(err) => warn!(%err, ?path, "Failed to save checkpoint"),
+        }
+    }
+
+    /// The latest checkpoint for the chunk, if there is one for these inputs, parameters and nonce
+    pub(crate) fn load<E1, E2>(
+        &self,
+        chunk_idx: u64,
+        input_digest: &[u8; 32],
+        params_digest: &SetupDigest,
+        nonce: Option<&[u8; 32]>,
+    ) -> Option<Checkpoint<'static, E1, E2>>
+    where
+        E1: Engine<Base = <E2 as Engine>::Scalar>,
+        E2: Engine<Base = <E1 as Engine>::Scalar>,
+    {
+        let path = self.path(chunk_idx, input_digest);
+        if !path.exists() {
+            return None;
+        }
+        let checkpoint: Checkpoint<'static, E1, E2> =
+            match codec::read_file(&path, Kind::Checkpoint) {
+                Ok(checkpoint) => checkpoint,
+                Err(err) => {
+                    warn!(%err, ?path, "Ignoring unreadable checkpoint");
+                    return None;
+                }
+            };
+        if checkpoint.chunk_idx != chunk_idx
+            || &checkpoint.input_digest != input_digest
+            || &checkpoint.params_digest != params_digest
+            || checkpoint.nonce.as_ref() != nonce
+        {
+            warn!(?path, "Ignoring checkpoint for other inputs or parameters");
+            return None;
+        }
+        info!(
+            steps_done = checkpoint.steps_done,
+            ?path,
+            "Resuming from checkpoint"
+        );
+        Some(checkpoint)
+    }
+
+    /// Remove the chunk's checkpoint once its proof is done
+    pub(crate) fn clear(&self, chunk_idx: u64, input_digest: &[u8; 32]) {
+        let path = self.path(chunk_idx, input_digest);
+        if path.exists() {
+            if let Err(err) = std::fs::remove_file(&path) {
+                warn!(%err, ?path, "Failed to remove checkpoint");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arecibo::provider::{PallasEngine, VestaEngine};
+    use arecibo::spartan;
+    use arecibo::traits::circuit::TrivialCircuit;
+    use ff::Field;
+
+    use crate::blake3_circuit::{Blake3BlockCompressCircuit, Blake3CompressPubIO, CircomArtifacts};
+    use crate::blake3_hash::hash_with_path;
+    use crate::utils::TempDir;
+    use crate::{
+        prove_chunk_hash_checkpointed, setup_digest, setup_params, PASTA_CIRCOM_R1CS_PATH,
+        PASTA_CIRCOM_WASM_PATH,
+    };
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = arecibo::provider::ipa_pc::EvaluationEngine<E1>;
+    type EE2 = arecibo::provider::ipa_pc::EvaluationEngine<E2>;
+    type S1 = spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+
+    #[test]
+    fn test_resume() {
+        let artifacts =
+            CircomArtifacts::load(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let params = setup_params::<E1, E2, S1, S2>(&artifacts, 1);
+        let pp = &params.pp;
+        let dir = TempDir::new("checkpoint_resume");
+        let checkpointer = Checkpointer::new(dir.path(), 2);
+
+        let data = vec![7u8; 1024 * 2 + 3];
+        let (hash, hash_proof) = hash_with_path(&data, 1).unwrap();
+
+        // Prove the first few steps by hand, as if the prover crashed after checkpointing
+        let mut circuit = Blake3BlockCompressCircuit::with_artifacts(
+            hash_proof.bytes.clone(),
+            hash_proof.parent_path.clone(),
+            artifacts.clone(),
+        );
+        let num_steps = circuit.num_steps();
+        let z0_primary = Blake3CompressPubIO::<<E1 as Engine>::GE>::initial(
+            1,
+            circuit.n_blocks,
+            circuit.total_depth,
+        )
+        .to_vec();
+        let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+        let mut recursive_snark = RecursiveSNARK::new(
+            pp,
+            &circuit,
+            &TrivialCircuit::default(),
+            &z0_primary,
+            &z0_secondary,
+        )
+        .unwrap();
+        let steps_done = 4;
+        for _ in 0..steps_done {
+            recursive_snark
+                .prove_step(pp, &circuit, &TrivialCircuit::default())
+                .unwrap();
+            circuit.update_for_step();
+        }
+        checkpointer.save(&Checkpoint {
+            chunk_idx: 1,
+            input_digest: circuit.input_digest(),
+            params_digest: setup_digest::params_digest(pp),
+            nonce: None,
+            steps_done,
+            cursor: circuit.cursor(),
+            recursive_snark: Cow::Borrowed(&recursive_snark),
+        });
+
+        // A checkpoint for another chunk's data is not picked up
+        let (_, other) = hash_with_path(&vec![8u8; 1024 * 2 + 3], 1).unwrap();
+        let other_circuit = Blake3BlockCompressCircuit::<<E1 as Engine>::GE>::with_artifacts(
+            other.bytes,
+            other.parent_path,
+            artifacts.clone(),
+        );
+        assert!(checkpointer
+            .load::<E1, E2>(
+                1,
+                &other_circuit.input_digest(),
+                &setup_digest::params_digest(pp),
+                None
+            )
+            .is_none());
+        // Nor is one for another nonce
+        assert!(checkpointer
+            .load::<E1, E2>(
+                1,
+                &circuit.input_digest(),
+                &setup_digest::params_digest(pp),
+                Some(&[1u8; 32])
+            )
+            .is_none());
+
+        let (output_hash, _, stats) = prove_chunk_hash_checkpointed(
+            &params,
+            hash_proof,
+            artifacts,
+            None,
+            Some(&checkpointer),
+        )
+        .unwrap();
+        assert_eq!(output_hash, hash.as_bytes().to_vec());
+        assert_eq!(stats.step_ms.len(), num_steps - steps_done);
+        // Done, so the checkpoint is gone
+        assert!(!checkpointer.path(1, &circuit.input_digest()).exists());
+    }
+}
@@ -0,0 +1,225 @@
+//! A compact binary encoding for proofs, keys and proof metadata, to store and send instead of
+//! JSON, which spells every field element out as a hex string.
+//!
+//! The payload is bincode, behind a small header saying what it holds, so that a verifier key
+//! is not mistaken for a proof and files written by an incompatible version are rejected.
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"B3FP";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    RecursiveSnark,
+    CompressedSnark,
+    VerifierKey,
+    ChunkProof,
+    Checkpoint,
+    Response,
+    Challenge,
+    EpochChain,
+}
+
+impl Kind {
+    fn tag(&self) -> u8 {
+        match self {
+            Kind::RecursiveSnark => 1,
+            Kind::CompressedSnark => 2,
+            Kind::VerifierKey => 3,
+            Kind::ChunkProof => 4,
+            Kind::Checkpoint => 5,
+            Kind::Response => 6,
+            Kind::Challenge => 7,
+            Kind::EpochChain => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Io(std::io::Error),
+    Bincode(bincode::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    WrongKind { expected: Kind, found: u8 },
+}
+
+impl From<std::io::Error> for CodecError {
+    fn from(err: std::io::Error) -> Self {
+        CodecError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for CodecError {
+    fn from(err: bincode::Error) -> Self {
+        CodecError::Bincode(err)
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Io(err) => write!(f, "IO error: {}", err),
+            CodecError::Bincode(err) => write!(f, "Failed to decode the payload: {}", err),
+            CodecError::BadMagic => write!(f, "Not an encoded proof or key"),
+            CodecError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported encoding version {}", version)
+            }
+            CodecError::WrongKind { expected, found } => {
+                write!(f, "Expected a {:?}, found kind {}", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+pub fn encode<T: Serialize>(kind: Kind, value: &T) -> Vec<u8> {
+    let payload = bincode::serialize(value).expect("Failed to serialize");
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.push(kind.tag());
+    bytes.extend(payload);
+    bytes
+}
+
+pub fn decode<T: DeserializeOwned>(kind: Kind, bytes: &[u8]) -> Result<T, CodecError> {
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(CodecError::BadMagic);
+    }
+    let (version, tag) = (bytes[MAGIC.len()], bytes[MAGIC.len() + 1]);
+    if version != VERSION {
+        return Err(CodecError::UnsupportedVersion(version));
+    }
+    if tag != kind.tag() {
+        return Err(CodecError::WrongKind {
+            expected: kind,
+            found: tag,
+        });
+    }
+    Ok(bincode::deserialize(&bytes[HEADER_LEN..])?)
+}
+
+pub fn write_file<T: Serialize>(
+    path: impl AsRef<Path>,
+    kind: Kind,
+    value: &T,
+) -> Result<(), CodecError> {
+    Ok(std::fs::write(path, encode(kind, value))?)
+}
+
+pub fn read_file<T: DeserializeOwned>(path: impl AsRef<Path>, kind: Kind) -> Result<T, CodecError> {
+    decode(kind, &std::fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arecibo::provider::ipa_pc;
+    use arecibo::provider::{PallasEngine, VestaEngine};
+    use arecibo::traits::Engine;
+    use arecibo::{spartan, CompressedSNARK, RecursiveSNARK, VerifierKey};
+    use ff::Field;
+
+    use crate::blake3_circuit::Blake3CompressPubIO;
+    use crate::blake3_hash::hash_with_path;
+    use crate::{
+        compress_snark, prove_chunk_hash, utils, C1, C2, PASTA_CIRCOM_R1CS_PATH,
+        PASTA_CIRCOM_WASM_PATH,
+    };
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = ipa_pc::EvaluationEngine<E1>;
+    type EE2 = ipa_pc::EvaluationEngine<E2>;
+    type S1 = spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+
+    #[test]
+    fn test_header() {
+        let bytes = encode(Kind::ChunkProof, &vec![1u64, 2, 3]);
+        assert_eq!(
+            decode::<Vec<u64>>(Kind::ChunkProof, &bytes).unwrap(),
+            vec![1, 2, 3]
+        );
+        assert!(matches!(
+            decode::<Vec<u64>>(Kind::VerifierKey, &bytes),
+            Err(CodecError::WrongKind { found: 4, .. })
+        ));
+        assert!(matches!(
+            decode::<Vec<u64>>(Kind::ChunkProof, &bytes[..3]),
+            Err(CodecError::BadMagic)
+        ));
+        let mut future = bytes.clone();
+        future[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            decode::<Vec<u64>>(Kind::ChunkProof, &future),
+            Err(CodecError::UnsupportedVersion(_))
+        ));
+        assert!(decode::<Vec<u64>>(Kind::ChunkProof, &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_smaller_than_json() {
+        let data = vec![3u8; 1024 * 2 + 100];
+        let (_, hash_proof) = hash_with_path(&data, 1).unwrap();
+        let z0_primary = Blake3CompressPubIO::<<E1 as Engine>::GE>::initial(
+            1,
+            utils::n_blocks_from_bytes(hash_proof.bytes.len()),
+            hash_proof.parent_path.len() + 1,
+        )
+        .to_vec();
+        let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+        let (_, pp, recursive_snark, stats) = prove_chunk_hash::<E1, E2, S1, S2>(
+            hash_proof,
+            Some(PASTA_CIRCOM_WASM_PATH.to_string()),
+            Some(PASTA_CIRCOM_R1CS_PATH.to_string()),
+            None,
+        )
+        .unwrap();
+        let (pk, vk) = CompressedSNARK::<_, _, _, _, S1, S2>::setup(&pp).unwrap();
+        let (compressed_snark, _) = compress_snark(&pp, &pk, &vk, &recursive_snark).unwrap();
+
+        let recursive = encode(Kind::RecursiveSnark, &recursive_snark);
+        let compressed = encode(Kind::CompressedSnark, &compressed_snark);
+        let key = encode(Kind::VerifierKey, &vk);
+        for (name, binary, json) in [
+            (
+                "recursive snark",
+                recursive.len(),
+                serde_json::to_vec(&recursive_snark).unwrap().len(),
+            ),
+            (
+                "compressed snark",
+                compressed.len(),
+                serde_json::to_vec(&compressed_snark).unwrap().len(),
+            ),
+            (
+                "verifier key",
+                key.len(),
+                serde_json::to_vec(&vk).unwrap().len(),
+            ),
+        ] {
+            assert!(binary < json, "{}: {} >= {} bytes", name, binary, json);
+        }
+
+        // The decoded proofs still verify
+        let recursive_snark: RecursiveSNARK<E1, E2, C1<E1>, C2<E2>> =
+            decode(Kind::RecursiveSnark, &recursive).unwrap();
+        assert!(recursive_snark
+            .verify(&pp, stats.num_steps, &z0_primary, &z0_secondary)
+            .is_ok());
+        let compressed_snark: CompressedSNARK<E1, E2, C1<E1>, C2<E2>, S1, S2> =
+            decode(Kind::CompressedSnark, &compressed).unwrap();
+        let vk: VerifierKey<E1, E2, C1<E1>, C2<E2>, S1, S2> =
+            decode(Kind::VerifierKey, &key).unwrap();
+        assert!(compressed_snark
+            .verify(&vk, stats.num_steps, &z0_primary, &z0_secondary)
+            .is_ok());
+    }
+}
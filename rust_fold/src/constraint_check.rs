@@ -0,0 +1,138 @@
+//! Finding which constraints of the circom R1CS a witness violates, and naming the signals in
+//! them from the circuit's `.sym` file.
+//!
+//! Nova does not check the step circuit while folding, so a bad witness only shows up as a proof
+//! which fails to verify. Checking each compression's witness against the R1CS
+//! `synthesize_with_vec` enforces instead points at the template and signals which went wrong.
+use circom_scotia::r1cs::R1CS;
+use ff::PrimeField;
+use std::collections::BTreeSet;
+use tracing::error;
+
+use crate::blake3_circuit::CircuitCursor;
+use crate::native_witness::SymbolMap;
+
+/// At most this many violated constraints are logged for one compression
+const MAX_REPORTED: usize = 10;
+
+fn eval_lc<F: PrimeField>(lc: &[(usize, F)], witness: &[F]) -> F {
+    // Wire 0 is the constant 1, which the witness holds too
+    lc.iter().map(|(idx, coeff)| *coeff * witness[*idx]).sum()
+}
+
+/// The indices of the constraints `A * B = C` of `r1cs` which `witness` does not satisfy
+pub fn unsatisfied_constraints<F: PrimeField>(r1cs: &R1CS<F>, witness: &[F]) -> Vec<usize> {
+    r1cs.constraints
+        .iter()
+        .enumerate()
+        .filter(|(_, (a, b, c))| eval_lc(a, witness) * eval_lc(b, witness) != eval_lc(c, witness))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// The constraint `constraint` of `r1cs`, evaluated at `witness`, with every signal in it
+/// named (from `SymbolMap::names`) and valued. Wires without a name (e.g. those circom made
+/// for intermediate values) are listed by index.
+pub fn describe_constraint<F: PrimeField>(
+    r1cs: &R1CS<F>,
+    witness: &[F],
+    names: &[Option<&str>],
+    constraint: usize,
+) -> String {
+    let (a, b, c) = &r1cs.constraints[constraint];
+    let wires: BTreeSet<usize> = a
+        .iter()
+        .chain(b)
+        .chain(c)
+        .map(|(idx, _)| *idx)
+        .filter(|idx| *idx > 0)
+        .collect();
+    let signals: Vec<String> = wires
+        .into_iter()
+        .map(|idx| match names.get(idx).copied().flatten() {
+            Some(name) => format!("{} = {:?}", name, witness[idx]),
+            None => format!("wire {} = {:?}", idx, witness[idx]),
+        })
+        .collect();
+    format!(
+        "constraint {}: A = {:?}, B = {:?}, C = {:?} (A * B != C) over {}",
+        constraint,
+        eval_lc(a, witness),
+        eval_lc(b, witness),
+        eval_lc(c, witness),
+        signals.join(", ")
+    )
+}
+
+/// Log every constraint the witness of the compression at `cursor` violates, with its signals
+/// named if there are `symbols`
+pub(crate) fn report_unsatisfied<F: PrimeField>(
+    r1cs: &R1CS<F>,
+    witness: &[F],
+    symbols: Option<&SymbolMap>,
+    cursor: &CircuitCursor,
+) {
+    let unsatisfied = unsatisfied_constraints(r1cs, witness);
+    if unsatisfied.is_empty() {
+        return;
+    }
+    error!(
+        ?cursor,
+        n_unsatisfied = unsatisfied.len(),
+        "Witness does not satisfy the circom R1CS"
+    );
+    let names = symbols.map(SymbolMap::names).unwrap_or_default();
+    for &constraint in unsatisfied.iter().take(MAX_REPORTED) {
+        error!("{}", describe_constraint(r1cs, witness, &names, constraint));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::PallasEngine;
+    use arecibo::traits::Engine;
+    use circom_scotia::calculate_witness;
+    use circom_scotia::r1cs::CircomConfig;
+    use ff::Field;
+
+    use super::*;
+    use crate::blake3_circuit::{Blake3BlockCompressCircuit, Blake3CompressPubIO};
+    use crate::blake3_hash::hash_with_path;
+    use crate::{PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_SYM_PATH, PASTA_CIRCOM_WASM_PATH};
+
+    type G = <PallasEngine as Engine>::GE;
+    type F = <PallasEngine as Engine>::Scalar;
+
+    #[test]
+    fn test_unsatisfied_constraints() {
+        let cfg = CircomConfig::<F>::new(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let symbols = SymbolMap::from_file(PASTA_CIRCOM_SYM_PATH).unwrap();
+
+        let data = vec![3u8; 1024 * 2];
+        let (_, hash_proof) = hash_with_path(&data, 1).unwrap();
+        let circuit = Blake3BlockCompressCircuit::<G>::new(
+            hash_proof.bytes,
+            hash_proof.parent_path,
+            PASTA_CIRCOM_WASM_PATH.to_string(),
+            PASTA_CIRCOM_R1CS_PATH.to_string(),
+        );
+        let z0 = Blake3CompressPubIO::<G>::initial(1, circuit.n_blocks, circuit.total_depth);
+        let input = circuit.format_input_from_vec(z0.to_vec());
+        let mut witness = calculate_witness(&cfg, input, true).unwrap();
+        assert!(unsatisfied_constraints(&cfg.r1cs, &witness).is_empty());
+
+        // Claiming the first leaf block is at the root breaks the depth check
+        let is_root = symbols.get("main.check_depth.is_root").unwrap();
+        witness[is_root] = F::ONE - witness[is_root];
+        let unsatisfied = unsatisfied_constraints(&cfg.r1cs, &witness);
+        assert!(!unsatisfied.is_empty());
+        let names = symbols.names();
+        let descriptions: Vec<String> = unsatisfied
+            .iter()
+            .map(|&constraint| describe_constraint(&cfg.r1cs, &witness, &names, constraint))
+            .collect();
+        assert!(descriptions
+            .iter()
+            .any(|description| description.contains("is_root")));
+    }
+}
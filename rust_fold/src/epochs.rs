@@ -0,0 +1,348 @@
+//! Proof of spacetime: one proof that a file was held over a run of audit epochs.
+//!
+//! Each epoch proves the paths of a few chunks of the file. They are drawn the way
+//! `audit::ChunkSelection::Seed` draws them, seeded by the chain's commitment to the pairs of
+//! the earlier epochs and keyed by the epoch's public beacon. The commitment is computed from
+//! public data only (the root and the chunk indices drawn so far), so anyone can replay the
+//! draws; the only thing keeping an epoch's chunks unknown ahead of time is its beacon.
+//!
+//! This alone does not show the file was held over the whole run: a prover which kept nothing
+//! but can get the file back later can prove every epoch at once, after the last beacon. The
+//! timing has to come from outside, e.g. by requiring a proof of the chain up to each epoch to
+//! be published before the next beacon is released. Publishing the commitment is not enough,
+//! since it is computed from public data too.
+//!
+//! The epochs are folded onto a single `blake3_multipath` chain, one after the other, and the
+//! chain's output is its commitment to every (root, chunk index) pair proven so far. A verifier
+//! replays the selection from the beacons to know which pairs the proof must commit to.
+use arecibo::errors::NovaError;
+use arecibo::traits::circuit::TrivialCircuit;
+use arecibo::traits::Engine;
+use arecibo::{PublicParams, RecursiveSNARK};
+use ff::Field;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Instant;
+use tracing::{info, info_span};
+
+use crate::audit::{AuditError, Challenge, ChunkSelection};
+use crate::blake3_hash::Blake3HashProof;
+use crate::blake3_multipath::{
+    commit_chunks, multipath_num_steps, verify_multipath, Blake3MultiPathCircuit, MultiPathError,
+};
+use crate::codec::{self, CodecError, Kind};
+use crate::C2;
+
+type M1<E1> = Blake3MultiPathCircuit<<E1 as Engine>::GE>;
+
+#[derive(Debug)]
+pub enum EpochError {
+    Codec(CodecError),
+    /// The chunks given for the epoch are not the ones it draws
+    Audit(AuditError),
+    /// The epoch draws no chunks, i.e. `chunks_per_epoch` is 0
+    NoChunks,
+    Nova(NovaError),
+}
+
+impl From<CodecError> for EpochError {
+    fn from(err: CodecError) -> Self {
+        EpochError::Codec(err)
+    }
+}
+
+impl From<AuditError> for EpochError {
+    fn from(err: AuditError) -> Self {
+        EpochError::Audit(err)
+    }
+}
+
+impl From<NovaError> for EpochError {
+    fn from(err: NovaError) -> Self {
+        EpochError::Nova(err)
+    }
+}
+
+impl From<MultiPathError> for EpochError {
+    fn from(err: MultiPathError) -> Self {
+        match err {
+            MultiPathError::NoPaths => EpochError::NoChunks,
+            MultiPathError::Nova(err) => EpochError::Nova(err),
+        }
+    }
+}
+
+impl fmt::Display for EpochError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpochError::Codec(err) => write!(f, "{}", err),
+            EpochError::Audit(err) => write!(f, "{}", err),
+            EpochError::NoChunks => write!(f, "The epoch draws no chunks"),
+            EpochError::Nova(err) => write!(f, "Nova error: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for EpochError {}
+
+/// The chunks an epoch draws after the chain committed to `commitment`
+fn epoch_challenge(
+    root: &[u8; 32],
+    file_len: u64,
+    chunks_per_epoch: u32,
+    commitment: [u8; 32],
+    beacon: &[u8; 32],
+) -> Challenge {
+    Challenge {
+        root: *root,
+        file_len,
+        nonce: *beacon,
+        selection: ChunkSelection::Seed {
+            seed: commitment,
+            count: chunks_per_epoch,
+        },
+        // Epochs are paced by their beacons rather than deadlines
+        deadline_unix_ms: u64::MAX,
+    }
+}
+
+/// The chain of epochs proven so far for one file, to be saved between epochs
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct EpochChain<E1, E2>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    root: [u8; 32],
+    file_len: u64,
+    chunks_per_epoch: u32,
+    beacons: Vec<[u8; 32]>,
+    /// Every (root, chunk index) pair proven, in order
+    entries: Vec<([u8; 32], u64)>,
+    num_steps: usize,
+    recursive_snark: Option<RecursiveSNARK<E1, E2, M1<E1>, C2<E2>>>,
+}
+
+impl<E1, E2> EpochChain<E1, E2>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    pub fn new(root: [u8; 32], file_len: u64, chunks_per_epoch: u32) -> Self {
+        assert!(chunks_per_epoch > 0);
+        EpochChain {
+            root,
+            file_len,
+            chunks_per_epoch,
+            beacons: vec![],
+            entries: vec![],
+            num_steps: 0,
+            recursive_snark: None,
+        }
+    }
+
+    pub fn n_epochs(&self) -> usize {
+        self.beacons.len()
+    }
+
+    pub fn recursive_snark(&self) -> Option<&RecursiveSNARK<E1, E2, M1<E1>, C2<E2>>> {
+        self.recursive_snark.as_ref()
+    }
+
+    /// The chain's output so far: its commitment to every pair proven
+    pub fn commitment(&self) -> [u8; 32] {
+        commit_chunks(&self.entries)
+    }
+
+    /// The chunks the next epoch must prove, in order, if its beacon is `beacon`
+    pub fn next_indices(&self, beacon: &[u8; 32]) -> Vec<u64> {
+        epoch_challenge(
+            &self.root,
+            self.file_len,
+            self.chunks_per_epoch,
+            self.commitment(),
+            beacon,
+        )
+        .chunk_indices()
+    }
+
+    /// Fold the next epoch, with the paths of the chunks `next_indices(beacon)` returns, onto
+    /// the chain. Returns the chain's new commitment.
+    ///
+    /// The paths are checked against the chunks drawn, but not against the root: a path under
+    /// another root makes the chain commit to the wrong pairs, which `verify_epochs` turns down.
+    pub fn prove_epoch(
+        &mut self,
+        pp: &PublicParams<E1, E2, M1<E1>, C2<E2>>,
+        beacon: &[u8; 32],
+        hash_proofs: Vec<Blake3HashProof>,
+    ) -> Result<[u8; 32], EpochError> {
+        let _span = info_span!("prove_epoch", epoch = self.beacons.len()).entered();
+        let challenge = epoch_challenge(
+            &self.root,
+            self.file_len,
+            self.chunks_per_epoch,
+            self.commitment(),
+            beacon,
+        );
+        let indices = challenge.chunk_indices();
+        if hash_proofs.len() != indices.len() {
+            return Err(AuditError::WrongCount {
+                expected: indices.len(),
+                found: hash_proofs.len(),
+            }
+            .into());
+        }
+        for (&chunk_idx, hash_proof) in indices.iter().zip(&hash_proofs) {
+            if hash_proof.chunk_idx != chunk_idx {
+                return Err(AuditError::WrongChunk {
+                    expected: chunk_idx,
+                    found: hash_proof.chunk_idx,
+                }
+                .into());
+            }
+            challenge.check_hash_proof(hash_proof)?;
+        }
+
+        let mut circuit_primary = Blake3MultiPathCircuit::<<E1 as Engine>::GE>::new(hash_proofs)?;
+        let circuit_secondary = TrivialCircuit::default();
+        let num_steps = circuit_primary.num_steps();
+        // The epoch is proven on a copy, so on an error the chain is left as it was
+        let mut recursive_snark = match &self.recursive_snark {
+            Some(recursive_snark) => recursive_snark.clone(),
+            None => RecursiveSNARK::new(
+                pp,
+                &circuit_primary,
+                &circuit_secondary,
+                &circuit_primary.z0(),
+                &[<E2 as Engine>::Scalar::ZERO],
+            )?,
+        };
+        let start = Instant::now();
+        for _ in 0..num_steps {
+            recursive_snark.prove_step(pp, &circuit_primary, &circuit_secondary)?;
+            circuit_primary.update_for_step();
+        }
+        info!(num_steps, elapsed = ?start.elapsed(), "Proved epoch");
+
+        self.recursive_snark = Some(recursive_snark);
+        self.num_steps += num_steps;
+        self.beacons.push(*beacon);
+        self.entries
+            .extend(indices.into_iter().map(|chunk_idx| (self.root, chunk_idx)));
+        Ok(self.commitment())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        codec::encode(Kind::EpochChain, self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CodecError> {
+        codec::decode(Kind::EpochChain, bytes)
+    }
+}
+
+/// Verify that `recursive_snark` proves every epoch of `beacons`, in order, for the file with
+/// `root` and `file_len`
+pub fn verify_epochs<E1, E2>(
+    pp: &PublicParams<E1, E2, M1<E1>, C2<E2>>,
+    recursive_snark: &RecursiveSNARK<E1, E2, M1<E1>, C2<E2>>,
+    root: &[u8; 32],
+    file_len: u64,
+    chunks_per_epoch: u32,
+    beacons: &[[u8; 32]],
+) -> Result<bool, EpochError>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    // Replay the selection, which also gives the number of steps each path takes
+    let mut entries = vec![];
+    let mut statements = vec![];
+    for beacon in beacons {
+        let challenge = epoch_challenge(
+            root,
+            file_len,
+            chunks_per_epoch,
+            commit_chunks(&entries),
+            beacon,
+        );
+        for chunk_idx in challenge.chunk_indices() {
+            statements.push(challenge.expected_statement(chunk_idx)?);
+            entries.push((*root, chunk_idx));
+        }
+    }
+    let num_steps = multipath_num_steps(&statements);
+    Ok(verify_multipath(pp, recursive_snark, num_steps, &entries)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::{PallasEngine, VestaEngine};
+
+    use super::*;
+    use crate::blake3_hash::hash_with_path;
+    use crate::blake3_multipath::setup_multipath_params;
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = arecibo::provider::ipa_pc::EvaluationEngine<E1>;
+    type EE2 = arecibo::provider::ipa_pc::EvaluationEngine<E2>;
+    type S1 = arecibo::spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = arecibo::spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+
+    #[test]
+    fn test_epochs() {
+        let pp = setup_multipath_params::<E1, E2, S1, S2>();
+        let data = vec![3u8; 1024 * 3 + 10];
+        let root = *blake3::hash(&data).as_bytes();
+        let beacons = [[1u8; 32], [2u8; 32], [3u8; 32]];
+
+        let mut chain = EpochChain::<E1, E2>::new(root, data.len() as u64, 1);
+        for (epoch, beacon) in beacons.iter().enumerate() {
+            let indices = chain.next_indices(beacon);
+            let hash_proofs: Vec<_> = indices
+                .iter()
+                .map(|&chunk_idx| hash_with_path(&data, chunk_idx as usize).unwrap().1)
+                .collect();
+
+            if epoch == 1 {
+                // Another chunk than the one drawn is turned down, and the chain is unchanged
+                let other = (indices[0] + 1) % 4;
+                let wrong = vec![hash_with_path(&data, other as usize).unwrap().1];
+                assert!(matches!(
+                    chain.prove_epoch(&pp, beacon, wrong),
+                    Err(EpochError::Audit(AuditError::WrongChunk { .. }))
+                ));
+                assert_eq!(chain.n_epochs(), 1);
+
+                // The chain survives being saved between epochs
+                chain = EpochChain::from_bytes(&chain.to_bytes()).unwrap();
+            }
+            chain.prove_epoch(&pp, beacon, hash_proofs).unwrap();
+        }
+
+        let recursive_snark = chain.recursive_snark().unwrap();
+        let verify = |beacons: &[[u8; 32]]| {
+            // A wrong number of steps fails verification outright rather than returning false
+            matches!(
+                verify_epochs(&pp, recursive_snark, &root, data.len() as u64, 1, beacons),
+                Ok(true)
+            )
+        };
+        assert!(verify(&beacons));
+        // Not for other beacons, a prefix of the epochs or a reordering of them
+        assert!(!verify(&[[1u8; 32], [2u8; 32], [4u8; 32]]));
+        assert!(!verify(&beacons[..2]));
+        assert!(!verify(&[[2u8; 32], [1u8; 32], [3u8; 32]]));
+
+        // An epoch drawing nothing is turned down rather than proven
+        let mut empty = EpochChain::<E1, E2>::new(root, data.len() as u64, 0);
+        assert!(matches!(
+            empty.prove_epoch(&pp, &beacons[0], vec![]),
+            Err(EpochError::NoChunks)
+        ));
+        assert_eq!(empty.n_epochs(), 0);
+    }
+}
@@ -1,4 +1,3 @@
-use arecibo::errors::NovaError;
 use arecibo::provider::non_hiding_zeromorph::ZMPCS;
 use arecibo::provider::{
     self, Bn256Engine, Bn256EngineZM, GrumpkinEngine, PallasEngine, VestaEngine,
@@ -11,14 +10,21 @@ use arecibo::{spartan, PublicParams};
 use arecibo::{CompressedSNARK, ProverKey, VerifierKey};
 use bellpepper_core::ConstraintSystem;
 use blake3_circuit::PathNode;
+use checkpoint::Checkpointer;
 use ff::Field;
 use halo2curves::bn256::Bn256;
 use num_traits::ops::bytes;
+use proof_stats::ProofStats;
+use rayon::prelude::*;
+use std::borrow::Cow;
 use std::fs;
 use std::marker::PhantomData;
 use std::time::Instant;
+use tracing::{debug, error, info, info_span};
+use tracing_subscriber::EnvFilter;
 
-use crate::blake3_circuit::{Blake3BlockCompressCircuit, Blake3CompressPubIO, IV};
+use crate::backend::ProverError;
+use crate::blake3_circuit::{Blake3BlockCompressCircuit, Blake3CompressPubIO, CircomArtifacts, IV};
 use crate::blake3_hash::hash_with_path;
 
 const N_MESSAGE_WORDS_BLOCK: usize = 16;
@@ -28,37 +34,71 @@ const MAX_BYTES_PER_CHUNK: usize = MAX_BLOCKS_PER_CHUNK * MAX_BYTES_PER_BLOCK;
 
 const DEFAULT_CIRCOM_WASM_PATH: &str = "../build/blake3_nova_js/blake3_nova.wasm";
 const DEFAULT_CIRCOM_R1CS_PATH: &str = "../build/blake3_nova.r1cs";
-
+const PASTA_CIRCOM_WASM_PATH: &str = "../build/blake3_nova_pasta_js/blake3_nova_pasta.wasm";
+const PASTA_CIRCOM_R1CS_PATH: &str = "../build/blake3_nova_pasta.r1cs";
+const PASTA_CIRCOM_SYM_PATH: &str = "../build/blake3_nova_pasta.sym";
+
+mod artifacts;
+mod audit;
+mod backend;
+mod batch_verify;
+mod blake3_bellpepper;
 mod blake3_circuit;
 mod blake3_hash;
+mod blake3_multipath;
+mod blake3_supernova;
+mod checkpoint;
+mod codec;
+mod constraint_check;
+mod epochs;
+mod manifest;
+mod native_witness;
+mod proof_stats;
+mod provider;
+mod service;
+mod setup_digest;
+#[cfg(test)]
+mod sparse_tree;
+mod step_trace;
 mod utils;
 
+type C1<E1> = Blake3BlockCompressCircuit<<E1 as Engine>::GE>;
+type C2<E2> = TrivialCircuit<<E2 as Engine>::Scalar>;
+
+/// Public parameters, with the number of compressions per step they were set up for. Chunks
+/// are always proven with that number, so they cannot be folded with a step of another size.
+pub struct FoldParams<E1, E2>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    pub pp: PublicParams<E1, E2, C1<E1>, C2<E2>>,
+    pub compressions_per_step: usize,
+}
+
 /// A PathNode contain whether or not the node is a left or right child
 /// and the hash bytes
 
 /// Using folding to prove that the prover knows all the preimages of blocks in a file
 /// and that they chain together correctly.
+///
+/// Also returns the `ProofStats` of proving, including the setup.
+///
+/// `compressions_per_step` (default 1) sets how many Blake3 compressions are folded in each step.
+/// Larger values make each step bigger but need fewer folds.
 pub fn prove_chunk_hash<E1, E2, SS1, SS2>(
     hash_proof: blake3_hash::Blake3HashProof,
     circom_wasm_path: Option<String>,
     circom_r1cs_path: Option<String>,
+    compressions_per_step: Option<usize>,
 ) -> Result<
     (
         Vec<u8>,
-        PublicParams<
-            E1,
-            E2,
-            Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
-            TrivialCircuit<<E2 as Engine>::Scalar>,
-        >,
-        RecursiveSNARK<
-            E1,
-            E2,
-            Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
-            TrivialCircuit<<E2 as Engine>::Scalar>,
-        >,
+        PublicParams<E1, E2, C1<E1>, C2<E2>>,
+        RecursiveSNARK<E1, E2, C1<E1>, C2<E2>>,
+        ProofStats,
     ),
-    NovaError,
+    ProverError,
 >
 where
     E1: Engine<Base = <E2 as Engine>::Scalar>,
@@ -66,197 +106,304 @@ where
     SS1: RelaxedR1CSSNARKTrait<E1>,
     SS2: RelaxedR1CSSNARKTrait<E2>,
 {
-    println!("Nova-based Blake3 Chunk Compression");
-    println!("=========================================================");
-    let leaf_depth = hash_proof.parent_path.len() as u64 + 1;
-    let bytes = hash_proof.bytes;
-    let chunk_idx = hash_proof.chunk_idx;
-    let parent_path = hash_proof.parent_path;
-
-    assert!(bytes.len() <= MAX_BYTES_PER_CHUNK);
+    let artifacts = CircomArtifacts::load(
+        &circom_wasm_path.unwrap_or(DEFAULT_CIRCOM_WASM_PATH.into()),
+        &circom_r1cs_path.unwrap_or(DEFAULT_CIRCOM_R1CS_PATH.into()),
+    )?;
+    let compressions_per_step = compressions_per_step.unwrap_or(1);
+    let start = Instant::now();
+    let params = setup_params::<E1, E2, SS1, SS2>(&artifacts, compressions_per_step);
+    let setup_ms = proof_stats::millis(start.elapsed());
+    let (output_hash, recursive_snark, mut stats) =
+        prove_chunk_hash_with_params(&params, hash_proof, artifacts)?;
+    stats.setup_ms = setup_ms;
+    Ok((output_hash, params.pp, recursive_snark, stats))
+}
 
-    // number of iterations of MinRoot per Nova's recursive step
-    let mut circuit_primary = Blake3BlockCompressCircuit::new(
-        bytes,
-        parent_path,
-        circom_wasm_path.unwrap_or(DEFAULT_CIRCOM_WASM_PATH.into()),
-        circom_r1cs_path.unwrap_or(DEFAULT_CIRCOM_WASM_PATH.into()),
-    );
+/// Produce the public parameters for folding `compressions_per_step` compressions per step.
+/// They only depend on the shape of the circuit, so every chunk can be proven with the same ones.
+pub fn setup_params<E1, E2, SS1, SS2>(
+    artifacts: &CircomArtifacts<<E1 as Engine>::Scalar>,
+    compressions_per_step: usize,
+) -> FoldParams<E1, E2>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    // The input data does not change the shape of the circuit
+    let circuit_primary =
+        Blake3BlockCompressCircuit::with_artifacts(vec![0u8; 1], vec![], artifacts.clone())
+            .with_compressions_per_step(compressions_per_step);
     let circuit_secondary = TrivialCircuit::default();
-    println!(
-        "Proving {} bytes of Blake3Compress per step",
-        circuit_primary.n_bytes
-    );
-
-    // Round up to include all the blocks
-    let n_blocks = circuit_primary.n_blocks;
-    // We need an additional (total_depth - 1) round here (to account for all parents above the leaf)
-    let num_steps = n_blocks + circuit_primary.total_depth - 1;
 
     // produce public parameters
+    let _span = info_span!("setup", compressions_per_step).entered();
     let start = Instant::now();
-    println!("Producing public parameters...");
-    let pp = PublicParams::<
-        E1,
-        E2,
-        Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
-        TrivialCircuit<<E2 as Engine>::Scalar>,
-    >::setup(
+    let pp = PublicParams::<E1, E2, C1<E1>, C2<E2>>::setup(
         &circuit_primary,
         &circuit_secondary,
         &*SS1::ck_floor(),
         &*SS2::ck_floor(),
     );
-    println!("PublicParams::setup, took {:?} ", start.elapsed());
-
-    println!(
-        "Number of constraints per step (primary circuit): {}",
-        pp.num_constraints().0
-    );
-    println!(
-        "Number of constraints per step (secondary circuit): {}",
-        pp.num_constraints().1
+    info!(elapsed = ?start.elapsed(), "PublicParams::setup");
+    info!(
+        primary_constraints = pp.num_constraints().0,
+        secondary_constraints = pp.num_constraints().1,
+        primary_variables = pp.num_variables().0,
+        secondary_variables = pp.num_variables().1,
+        "Circuit size per step"
     );
+    FoldParams {
+        pp,
+        compressions_per_step,
+    }
+}
 
-    println!(
-        "Number of variables per step (primary circuit): {}",
-        pp.num_variables().0
-    );
-    println!(
-        "Number of variables per step (secondary circuit): {}",
-        pp.num_variables().1
+/// Fold and verify the path from a chunk to the root using existing public parameters.
+/// The returned stats leave `setup_ms` at 0 as the parameters were made elsewhere.
+pub fn prove_chunk_hash_with_params<E1, E2>(
+    params: &FoldParams<E1, E2>,
+    hash_proof: blake3_hash::Blake3HashProof,
+    artifacts: CircomArtifacts<<E1 as Engine>::Scalar>,
+) -> Result<(Vec<u8>, RecursiveSNARK<E1, E2, C1<E1>, C2<E2>>, ProofStats), ProverError>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    prove_chunk_hash_checkpointed(params, hash_proof, artifacts, None, None)
+}
+
+/// `prove_chunk_hash_with_params`, resuming from and saving checkpoints with `checkpointer`.
+/// When resuming, the returned stats only time the steps proven in this call.
+///
+/// If there is a `nonce` it is bound into the initial IO, and the proof only verifies for it.
+pub fn prove_chunk_hash_checkpointed<E1, E2>(
+    params: &FoldParams<E1, E2>,
+    hash_proof: blake3_hash::Blake3HashProof,
+    artifacts: CircomArtifacts<<E1 as Engine>::Scalar>,
+    nonce: Option<&[u8; 32]>,
+    checkpointer: Option<&Checkpointer>,
+) -> Result<(Vec<u8>, RecursiveSNARK<E1, E2, C1<E1>, C2<E2>>, ProofStats), ProverError>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    let pp = &params.pp;
+    let compressions_per_step = params.compressions_per_step;
+    let leaf_depth = hash_proof.parent_path.len() as u64 + 1;
+    let bytes = hash_proof.bytes;
+    let chunk_idx = hash_proof.chunk_idx;
+    let parent_path = hash_proof.parent_path;
+
+    if bytes.len() > MAX_BYTES_PER_CHUNK {
+        return Err(ProverError::ChunkTooLarge(bytes.len()));
+    }
+    let _span = info_span!("prove_chunk_hash", chunk_idx).entered();
+
+    let mut circuit_primary =
+        Blake3BlockCompressCircuit::with_artifacts(bytes, parent_path, artifacts)
+            .with_compressions_per_step(compressions_per_step);
+    let circuit_secondary = TrivialCircuit::default();
+    debug!(
+        n_bytes = circuit_primary.n_bytes,
+        compressions_per_step = circuit_primary.compressions_per_step,
+        "Proving Blake3Compress"
     );
 
+    // Round up to include all the blocks
+    let n_blocks = circuit_primary.n_blocks;
+    // We need an additional (total_depth - 1) compressions (to account for all parents above the leaf)
+    let num_steps = circuit_primary.num_steps();
+    let mut stats = ProofStats {
+        num_steps,
+        ..Default::default()
+    };
+    stats.record_params(pp);
+
     let scalar_iv: Vec<<E1 as Engine>::Scalar> = IV
         .iter()
         .map(|iv| <E1 as Engine>::Scalar::from(*iv as u64))
         .collect();
     // TODO: I think we should move this into the blake3_circuit file
-    let z0_primary = Blake3CompressPubIO::<<E1 as Engine>::GE>::new(
+    let mut z0_primary = Blake3CompressPubIO::<<E1 as Engine>::GE>::new(
         chunk_idx,
         <E1 as Engine>::Scalar::from(circuit_primary.total_depth as u64),
         <E1 as Engine>::Scalar::from(n_blocks as u64),
         scalar_iv,
         <E1 as Engine>::Scalar::from(leaf_depth),
-    )
-    .to_vec();
-    println!("z0_primary len: {}", z0_primary.len());
+    );
+    if let Some(nonce) = nonce {
+        z0_primary = z0_primary.with_nonce(nonce);
+    }
+    let z0_primary = z0_primary.to_vec();
+    debug!(len = z0_primary.len(), "z0_primary");
 
     let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
 
-    type C1<E1> = Blake3BlockCompressCircuit<<E1 as Engine>::GE>;
-    type C2<E2> = TrivialCircuit<<E2 as Engine>::Scalar>;
-    // produce a recursive SNARK
-    println!("Generating a RecursiveSNARK...");
-    let mut recursive_snark: RecursiveSNARK<E1, E2, C1<E1>, C2<E2>> =
-        RecursiveSNARK::<E1, E2, C1<E1>, C2<E2>>::new(
-            &pp,
-            &circuit_primary,
-            &circuit_secondary,
-            &z0_primary,
-            &z0_secondary,
-        )
-        .map_err(|err| {
-            println!("Error: {:?}", err);
-            err
-        })
-        .unwrap();
+    let input_digest = circuit_primary.input_digest();
+    let params_digest = setup_digest::params_digest(pp);
+    let resumed = checkpointer
+        .and_then(|c| c.load::<E1, E2>(chunk_idx, &input_digest, &params_digest, nonce));
+
+    // produce a recursive SNARK, or pick up the checkpointed one
+    let (mut recursive_snark, steps_done) = match resumed {
+        Some(checkpoint) => {
+            circuit_primary.restore_cursor(&checkpoint.cursor);
+            (
+                checkpoint.recursive_snark.into_owned(),
+                checkpoint.steps_done,
+            )
+        }
+        None => {
+            let recursive_snark = RecursiveSNARK::<E1, E2, C1<E1>, C2<E2>>::new(
+                pp,
+                &circuit_primary,
+                &circuit_secondary,
+                &z0_primary,
+                &z0_secondary,
+            )
+            .map_err(|err| {
+                error!(?err, "RecursiveSNARK::new");
+                circuit_primary.report_unsatisfied_step(&z0_primary);
+                err
+            })?;
+            (recursive_snark, 0)
+        }
+    };
+
+    // With `CircomArtifacts::with_reference_check` every step is checked against the native
+    // reference model, so a bug shows up at the block or parent it happens at
+    let mut z_i = if steps_done == 0 {
+        z0_primary.clone()
+    } else {
+        recursive_snark.outputs().0.to_vec()
+    };
 
     // We need to do the ceiling
-    for i in 0..num_steps {
+    for i in steps_done..num_steps {
         let start = Instant::now();
-        let res = recursive_snark.prove_step(&pp, &circuit_primary, &circuit_secondary);
+        let res = recursive_snark.prove_step(pp, &circuit_primary, &circuit_secondary);
+        if res.is_err() {
+            // The outputs are still the ones the failed step started from
+            circuit_primary.report_unsatisfied_step(recursive_snark.outputs().0);
+        }
+        if circuit_primary.checks_reference() && res.is_ok() {
+            let z_next = recursive_snark.outputs().0.to_vec();
+            circuit_primary.check_reference_step(i, &z_i, &z_next)?;
+            z_i = z_next;
+        }
         // Increase internal data necessary for witness generation
         circuit_primary.update_for_step();
 
-        assert!(res.is_ok());
-        println!(
-            "RecursiveSNARK::prove_step {}: {:?}, took {:?} ",
-            i,
-            res.is_ok(),
-            start.elapsed()
+        info!(
+            step = i,
+            num_steps,
+            ok = res.is_ok(),
+            elapsed = ?start.elapsed(),
+            "RecursiveSNARK::prove_step"
         );
+        stats.step_ms.push(proof_stats::millis(start.elapsed()));
+        res?;
+
+        if let Some(checkpointer) = checkpointer.filter(|c| c.is_due(i + 1, num_steps)) {
+            checkpointer.save(&checkpoint::Checkpoint {
+                chunk_idx,
+                input_digest,
+                params_digest,
+                nonce: nonce.copied(),
+                steps_done: i + 1,
+                cursor: circuit_primary.cursor(),
+                recursive_snark: Cow::Borrowed(&recursive_snark),
+            });
+        }
     }
 
     // verify the recursive SNARK
-    println!("Verifying a RecursiveSNARK...");
     let start = Instant::now();
-    let res = recursive_snark.verify(&pp, num_steps, &z0_primary, &z0_secondary);
-    println!(
-        "RecursiveSNARK::verify: {:?}, took {:?}",
-        res.is_ok(),
-        start.elapsed()
-    );
-
-    println!("Snark Output: {:?}", res);
-    assert!(res.is_ok());
-    let res_un = res.unwrap().0;
+    let res = recursive_snark.verify(pp, num_steps, &z0_primary, &z0_secondary);
+    stats.verify_ms = proof_stats::millis(start.elapsed());
+    info!(ok = res.is_ok(), elapsed = ?start.elapsed(), "RecursiveSNARK::verify");
+    debug!(output = ?res, "RecursiveSNARK::verify");
+    let res_un = res?.0;
     // TODO: using formatting!!
     let _n_blocks = res_un[0].clone();
     let _counted_to = res_un[1].clone();
     let output_words = res_un[2..10].to_vec();
     let output_hash =
         utils::format_scalar_blake_hash::<<E1 as Engine>::GE>(output_words.try_into().unwrap());
-    println!("Output hash: {:?}", utils::format_bytes(&output_hash));
+    debug!(hash = ?utils::format_bytes(&output_hash), "Output hash");
 
-    Ok((output_hash, pp, recursive_snark))
+    if let Some(checkpointer) = checkpointer {
+        checkpointer.clear(chunk_idx, &input_digest);
+    }
+
+    stats.proof_size_bytes = bincode::serialized_size(&recursive_snark)?;
+    stats.peak_memory_bytes = proof_stats::peak_memory_bytes();
+    Ok((output_hash, recursive_snark, stats))
 }
 
-fn get_compressed_snark_keys<E1, E2, SS1, SS2>() -> (
-    arecibo::ProverKey<
-        E1,
-        E2,
-        Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
-        TrivialCircuit<<E2 as Engine>::Scalar>,
-        SS1,
-        SS2,
-    >,
-    arecibo::VerifierKey<
-        E1,
-        E2,
-        Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
-        TrivialCircuit<<E2 as Engine>::Scalar>,
-        SS1,
-        SS2,
-    >,
-)
+/// Prove many chunks concurrently on `pool`. The public parameters and circom artifacts are
+/// shared between all the chunks.
+///
+/// Returns one result per chunk, in the same order as `hash_proofs`.
+pub fn prove_chunk_hashes<E1, E2>(
+    params: &FoldParams<E1, E2>,
+    hash_proofs: Vec<blake3_hash::Blake3HashProof>,
+    artifacts: &CircomArtifacts<<E1 as Engine>::Scalar>,
+    pool: &rayon::ThreadPool,
+) -> Vec<Result<(Vec<u8>, RecursiveSNARK<E1, E2, C1<E1>, C2<E2>>, ProofStats), ProverError>>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    pool.install(|| {
+        hash_proofs
+            .into_par_iter()
+            .map(|hash_proof| prove_chunk_hash_with_params(params, hash_proof, artifacts.clone()))
+            .collect()
+    })
+}
+
+fn get_compressed_snark_keys<E1, E2, SS1, SS2>(
+    compressions_per_step: usize,
+) -> Result<
+    (
+        arecibo::ProverKey<
+            E1,
+            E2,
+            Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
+            TrivialCircuit<<E2 as Engine>::Scalar>,
+            SS1,
+            SS2,
+        >,
+        arecibo::VerifierKey<
+            E1,
+            E2,
+            Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
+            TrivialCircuit<<E2 as Engine>::Scalar>,
+            SS1,
+            SS2,
+        >,
+    ),
+    ProverError,
+>
 where
     E1: Engine<Base = <E2 as Engine>::Scalar>,
     E2: Engine<Base = <E1 as Engine>::Scalar>,
     SS1: RelaxedR1CSSNARKTrait<E1>,
     SS2: RelaxedR1CSSNARKTrait<E2>,
 {
-    // These input params do not influence the verifier key
-    // TODO: verify ^^
-    // TODO: DO IT MATTER?
-    let circuit_primary = Blake3BlockCompressCircuit::new(
-        vec![0u8; 1],
-        vec![],
-        DEFAULT_CIRCOM_WASM_PATH.to_string(),
-        DEFAULT_CIRCOM_R1CS_PATH.to_string(),
-    );
-    let circuit_secondary = TrivialCircuit::default();
-    println!(
-        "Proving {} bytes of Blake3Compress per step",
-        circuit_primary.n_bytes
-    );
-
-    // produce public parameters
-    let pp = PublicParams::<
-        E1,
-        E2,
-        Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
-        TrivialCircuit<<E2 as Engine>::Scalar>,
-    >::setup(
-        &circuit_primary,
-        &circuit_secondary,
-        &*SS1::ck_floor(),
-        &*SS2::ck_floor(),
-    );
-    let (pk, vk) = CompressedSNARK::<_, _, _, _, SS1, SS2>::setup(&pp).unwrap();
-    (pk, vk)
+    // The dummy input setup_params uses does not influence the keys, see setup_digest's tests
+    let artifacts = CircomArtifacts::load(DEFAULT_CIRCOM_WASM_PATH, DEFAULT_CIRCOM_R1CS_PATH)?;
+    let params = setup_params::<E1, E2, SS1, SS2>(&artifacts, compressions_per_step);
+    let (pk, vk) = CompressedSNARK::<_, _, _, _, SS1, SS2>::setup(&params.pp)?;
+    Ok((pk, vk))
 }
 
+/// Compress a recursive proof. The returned stats only cover the compression, combine them
+/// with the prover's using `ProofStats::with_compression`
 pub fn compress_snark<E1, E2, SS1, SS2>(
     pp: &PublicParams<
         E1,
@@ -286,13 +433,19 @@ pub fn compress_snark<E1, E2, SS1, SS2>(
         Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
         TrivialCircuit<<E2 as Engine>::Scalar>,
     >,
-) -> CompressedSNARK<
-    E1,
-    E2,
-    Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
-    TrivialCircuit<<E2 as Engine>::Scalar>,
-    SS1,
-    SS2,
+) -> Result<
+    (
+        CompressedSNARK<
+            E1,
+            E2,
+            Blake3BlockCompressCircuit<<E1 as Engine>::GE>,
+            TrivialCircuit<<E2 as Engine>::Scalar>,
+            SS1,
+            SS2,
+        >,
+        ProofStats,
+    ),
+    ProverError,
 >
 where
     E1: Engine<Base = <E2 as Engine>::Scalar>,
@@ -304,17 +457,41 @@ where
     let start = Instant::now();
 
     let res = CompressedSNARK::<_, _, _, _, SS1, SS2>::prove(&pp, &pk, &recursive_snark);
-    println!(
-        "CompressedSNARK::prove: {:?}, took {:?}",
-        res.is_ok(),
-        start.elapsed()
-    );
-    assert!(res.is_ok());
-    let compressed_snark = res.unwrap();
-    compressed_snark
+    info!(ok = res.is_ok(), elapsed = ?start.elapsed(), "CompressedSNARK::prove");
+    let mut stats = ProofStats {
+        compress_ms: Some(proof_stats::millis(start.elapsed())),
+        ..Default::default()
+    };
+    let compressed_snark = res?;
+    stats.record_params(pp);
+    stats.proof_size_bytes = bincode::serialized_size(&compressed_snark)?;
+    stats.peak_memory_bytes = proof_stats::peak_memory_bytes();
+    Ok((compressed_snark, stats))
 }
 
 pub fn main() {
+    // Progress is reported at the info level unless RUST_LOG says otherwise
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let args: Vec<String> = std::env::args().collect();
+    let command: Option<fn(&[String]) -> Result<(), String>> = match args.get(1).map(String::as_str)
+    {
+        Some("serve") => Some(service::run),
+        Some("provide") => Some(provider::run),
+        _ => None,
+    };
+    if let Some(command) = command {
+        if let Err(err) = command(&args[2..]) {
+            error!(%err, "{} failed", args[1]);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // type NE = Engine<GE = E1::G1, Scalar = E1::Fr>;
     type E1 = Bn256EngineZM;
     type E2 = GrumpkinEngine;
@@ -330,39 +507,66 @@ pub fn main() {
     // type SS1 = arecibo::spartan::ppsnark::RelaxedR1CSSNARK<E1, EE1>;
     type SS2 = SPrime<E2, EE2>; //arecibo::spartan::ppsnark::RelaxedR1CSSNARK<E2, EE2>;
 
-    let (pk, vk) = get_compressed_snark_keys::<E1, E2, SS1, SS2>();
+    let (pk, vk) = match get_compressed_snark_keys::<E1, E2, SS1, SS2>(1) {
+        Ok(keys) => keys,
+        Err(err) => {
+            error!(%err, "Failed to set up the compressed SNARK keys");
+            std::process::exit(1);
+        }
+    };
+    info!(
+        digest = setup_digest::to_hex(&setup_digest::verifier_key_digest(&vk)),
+        "Verifier key"
+    );
     let s = serde_json::to_string(&vk).unwrap();
     let s_pk = serde_json::to_string(&pk).unwrap();
     // TODO: arg for path...
     fs::write("../../solidity-verifier/vk_zm.json", s).expect("Unable to write file");
+    codec::write_file(
+        "../../solidity-verifier/vk_zm.bin",
+        codec::Kind::VerifierKey,
+        &vk,
+    )
+    .expect("Unable to write file");
     let hash_proof = hash_with_path(&vec![0u8], 0).unwrap();
-    let (_, pp, rec_s) = prove_chunk_hash::<E1, E2, SS1, SS2>(hash_proof.1, None, None).unwrap();
-    println!("Compressing");
-    let compr_snark = compress_snark(&pp, &pk, &vk, &rec_s);
+    let (_, pp, rec_s, stats) =
+        prove_chunk_hash::<E1, E2, SS1, SS2>(hash_proof.1, None, None, None).unwrap();
+    info!("Compressing");
+    let (compr_snark, compress_stats) = compress_snark(&pp, &pk, &vk, &rec_s).unwrap();
+    info!(
+        stats = stats.with_compression(&compress_stats).to_json(),
+        "Proof stats"
+    );
     fs::write(
         "../../solidity-verifier/proof.json",
         serde_json::to_string(&compr_snark).unwrap(),
     )
     .expect("Unable to write file");
+    codec::write_file(
+        "../../solidity-verifier/proof.bin",
+        codec::Kind::CompressedSnark,
+        &compr_snark,
+    )
+    .expect("Unable to write file");
 
     // fs::write("../../solidity-verifier/pk.json", s_pk).expect("Unable to write file");
 }
 
 #[cfg(test)]
 mod tests {
-    use std::cmp::min;
     use arecibo::provider::{PallasEngine, VestaEngine};
     use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+    use std::cmp::min;
 
     use crate::{
+        backend::ProverError,
+        blake3_circuit::CircomArtifacts,
         blake3_hash::hash_with_path,
-        prove_chunk_hash,
+        prove_chunk_hash, prove_chunk_hashes, setup_params,
         utils::{self},
-        MAX_BYTES_PER_CHUNK,
+        MAX_BYTES_PER_CHUNK, PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_WASM_PATH,
     };
 
-    const PASTA_CIRCOM_WASM_PATH: &str = "../build/blake3_nova_pasta_js/blake3_nova_pasta.wasm";
-    const PASTA_CIRCOM_R1CS_PATH: &str = "../build/blake3_nova_pasta.r1cs";
     type E1 = PallasEngine;
     type E2 = VestaEngine;
     type EE1 = arecibo::provider::ipa_pc::EvaluationEngine<E1>;
@@ -386,6 +590,7 @@ mod tests {
             hash_proof,
             Some(PASTA_CIRCOM_WASM_PATH.to_string()),
             Some(PASTA_CIRCOM_R1CS_PATH.to_string()),
+            None,
         );
         assert!(ret.is_ok());
         let bytes = ret.unwrap().0;
@@ -404,6 +609,7 @@ mod tests {
             rr.1,
             Some(PASTA_CIRCOM_WASM_PATH.to_string()),
             Some(PASTA_CIRCOM_R1CS_PATH.to_string()),
+            None,
         );
         assert!(r.is_ok());
         let bytes = r.unwrap().0;
@@ -432,6 +638,7 @@ mod tests {
                 hash_proof,
                 Some(PASTA_CIRCOM_WASM_PATH.to_string()),
                 Some(PASTA_CIRCOM_R1CS_PATH.to_string()),
+                None,
             );
             assert!(ret.is_ok());
             println!("Successfully did the folding");
@@ -468,6 +675,7 @@ mod tests {
                 hash_proof,
                 Some(PASTA_CIRCOM_WASM_PATH.to_string()),
                 Some(PASTA_CIRCOM_R1CS_PATH.to_string()),
+                None,
             );
             assert!(ret.is_ok());
             let bytes = ret.unwrap().0;
@@ -538,6 +746,62 @@ mod tests {
         test_prove_chunk_hash(small_block);
     }
 
+    #[test]
+    fn test_multiple_compressions_per_step() {
+        let data = vec![7 as u8; 1024 * 3 + 5];
+        let (hash, _) = hash_with_path(&data, 0).unwrap();
+        // 16 blocks + 2 parents for chunk 1, 1 block + 2 parents for chunk 3
+        for (chunk_idx, k) in [(1, 4), (1, 18), (3, 2), (3, 5)] {
+            let (_, hash_proof) = hash_with_path(&data, chunk_idx).unwrap();
+            let ret = prove_chunk_hash::<E1, E2, S1, S2>(
+                hash_proof,
+                Some(PASTA_CIRCOM_WASM_PATH.to_string()),
+                Some(PASTA_CIRCOM_R1CS_PATH.to_string()),
+                Some(k),
+            );
+            assert!(ret.is_ok());
+            let (bytes, _, _, stats) = ret.unwrap();
+            assert_eq!(bytes, hash.as_bytes().to_vec());
+            // The stats count the folds, not the compressions
+            let n_compressions = if chunk_idx == 1 { 18 } else { 3 };
+            assert_eq!(stats.num_steps, (n_compressions + k - 1) / k);
+            assert_eq!(stats.step_ms.len(), stats.num_steps);
+            assert!(stats.proof_size_bytes > 0);
+        }
+    }
+
+    #[test]
+    fn test_prove_chunk_hashes_parallel() {
+        let data = vec![3 as u8; 1024 * 3 + 5];
+        let (hash, _) = hash_with_path(&data, 0).unwrap();
+        // Every step is also checked against the reference model
+        let artifacts = CircomArtifacts::load(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH)
+            .unwrap()
+            .with_reference_check();
+        let params = setup_params::<E1, E2, S1, S2>(&artifacts, 2);
+        let mut hash_proofs: Vec<_> = (0..4)
+            .map(|chunk_idx| hash_with_path(&data, chunk_idx).unwrap().1)
+            .collect();
+        // One oversized chunk fails on its own
+        let mut oversized = hash_proofs[1].clone();
+        oversized.bytes = vec![0u8; MAX_BYTES_PER_CHUNK + 1];
+        hash_proofs.push(oversized);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let results = prove_chunk_hashes::<E1, E2>(&params, hash_proofs, &artifacts, &pool);
+        assert_eq!(results.len(), 5);
+        for ret in &results[..4] {
+            assert_eq!(ret.as_ref().unwrap().0, hash.as_bytes().to_vec());
+        }
+        assert!(matches!(
+            results[4],
+            Err(ProverError::ChunkTooLarge(len)) if len == MAX_BYTES_PER_CHUNK + 1
+        ));
+    }
+
     #[test]
     fn test_compress() {
         // TODO: test compress v nice
@@ -0,0 +1,615 @@
+//! Manifests of many files, so a verifier can audit a whole dataset while only tracking one root.
+//!
+//! A manifest lists the BLAKE3 root and length of every file, sorted by root. Each entry fills
+//! one 64 byte BLAKE3 block (the root, the little endian length, then zeros), so entry `i` is
+//! block `i % 16` of chunk `i / 16` of the manifest, and the manifest root is the BLAKE3 hash of
+//! the entries.
+//!
+//! A manifest proof is a single IVC chain of two paths. It first proves the path from a chunk to
+//! its file's root, then the path from the manifest chunk holding the file's entry to the
+//! manifest root, with the entry's block constrained to hold that file's root and length. The
+//! claimed file root and length are part of the chain's IO, so the verifier learns them from the
+//! proof, and uses the length to know which chunk statement to expect.
+use arecibo::errors::NovaError;
+use arecibo::traits::circuit::{StepCircuit, TrivialCircuit};
+use arecibo::traits::snark::RelaxedR1CSSNARKTrait;
+use arecibo::traits::{Engine, Group};
+use arecibo::{PublicParams, RecursiveSNARK};
+use bellpepper_core::boolean::{AllocatedBit, Boolean};
+use bellpepper_core::num::AllocatedNum;
+use bellpepper_core::{ConstraintSystem, SynthesisError};
+use ff::Field;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+use tracing::{info, info_span};
+
+use crate::audit::{AuditError, Challenge, ChunkSelection};
+use crate::blake3_bellpepper::{alloc_expr, is_equal, Blake3BellpepperCircuit, Expr};
+use crate::blake3_circuit::{Blake3CompressPubIO, IO_ARITY};
+use crate::blake3_hash::{hash_with_path, Blake3HashProof};
+use crate::{utils, MAX_BYTES_PER_CHUNK};
+
+/// The bytes of one manifest entry: a single BLAKE3 block
+const ENTRY_BYTES: usize = 64;
+const ENTRIES_PER_CHUNK: usize = MAX_BYTES_PER_CHUNK / ENTRY_BYTES;
+
+/// The current path IO, the IO the manifest path starts from, the claimed file root's 8 words,
+/// its length's low and high words, the entry's block in its manifest chunk and whether the
+/// file path is done
+pub(crate) const MANIFEST_ARITY: usize = 2 * IO_ARITY + 8 + 2 + 2;
+const MANIFEST_START_IDX: usize = IO_ARITY;
+const ROOT_IDX: usize = 2 * IO_ARITY;
+const LEN_IDX: usize = ROOT_IDX + 8;
+const ENTRY_BLOCK_IDX: usize = LEN_IDX + 2;
+const PHASE_IDX: usize = ENTRY_BLOCK_IDX + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub root: [u8; 32],
+    pub len: u64,
+}
+
+impl ManifestEntry {
+    fn to_bytes(&self) -> [u8; ENTRY_BYTES] {
+        let mut bytes = [0u8; ENTRY_BYTES];
+        bytes[..32].copy_from_slice(&self.root);
+        bytes[32..40].copy_from_slice(&self.len.to_le_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// The manifest of `entries`, sorted and with duplicates (files with the same contents)
+    /// listed once
+    pub fn new(mut entries: Vec<ManifestEntry>) -> Self {
+        entries.sort();
+        entries.dedup();
+        Manifest { entries }
+    }
+
+    /// The manifest of every file under `dir`
+    pub fn from_dir(dir: &Path) -> io::Result<Self> {
+        let mut entries = vec![];
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    dirs.push(entry.path());
+                } else if file_type.is_file() {
+                    let mut hasher = blake3::Hasher::new();
+                    let len = io::copy(&mut std::fs::File::open(entry.path())?, &mut hasher)?;
+                    entries.push(ManifestEntry {
+                        root: *hasher.finalize().as_bytes(),
+                        len,
+                    });
+                }
+            }
+        }
+        Ok(Self::new(entries))
+    }
+
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    /// The index of the entry for the file with `root`
+    pub fn position(&self, root: &[u8; 32]) -> Option<usize> {
+        self.entries
+            .binary_search_by(|entry| entry.root.cmp(root))
+            .ok()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.entries
+            .iter()
+            .flat_map(|entry| entry.to_bytes())
+            .collect()
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        *blake3::hash(&self.to_bytes()).as_bytes()
+    }
+
+    fn entry_hash_proof(&self, entry_idx: usize) -> io::Result<Blake3HashProof> {
+        Ok(hash_with_path(&self.to_bytes(), entry_idx / ENTRIES_PER_CHUNK)?.1)
+    }
+}
+
+/// A challenge for the file `entry` describes, to check chunk statements with
+fn file_challenge(entry: &ManifestEntry) -> Challenge {
+    Challenge {
+        root: entry.root,
+        file_len: entry.len,
+        nonce: [0u8; 32],
+        selection: ChunkSelection::Indices(vec![]),
+        deadline_unix_ms: u64::MAX,
+    }
+}
+
+/// What a manifest proof shows: chunk `chunk_idx` is in the file of entry `entry_idx` of the
+/// manifest with `manifest_root`, and that entry is `entry`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestClaim {
+    pub manifest_root: [u8; 32],
+    pub n_entries: u64,
+    pub entry_idx: u64,
+    pub entry: ManifestEntry,
+    pub chunk_idx: u64,
+}
+
+impl ManifestClaim {
+    /// The statements of the two paths, and the number of steps the chain takes
+    fn statements(&self) -> Result<((usize, usize), (usize, usize), usize), AuditError> {
+        let file = file_challenge(&self.entry).expected_statement(self.chunk_idx)?;
+        let manifest = file_challenge(&ManifestEntry {
+            root: self.manifest_root,
+            len: self.n_entries * ENTRY_BYTES as u64,
+        })
+        .expected_statement(self.entry_idx / ENTRIES_PER_CHUNK as u64)?;
+        let num_steps = (file.0 + file.1 - 1) + (manifest.0 + manifest.1 - 1);
+        Ok((file, manifest, num_steps))
+    }
+
+    fn z0<G: Group>(&self) -> Result<Vec<G::Scalar>, AuditError> {
+        let ((file_blocks, file_depth), (manifest_blocks, manifest_depth), _) =
+            self.statements()?;
+        let mut z0 =
+            Blake3CompressPubIO::<G>::initial(self.chunk_idx, file_blocks, file_depth).to_vec();
+        z0.extend(
+            Blake3CompressPubIO::<G>::initial(
+                self.entry_idx / ENTRIES_PER_CHUNK as u64,
+                manifest_blocks,
+                manifest_depth,
+            )
+            .to_vec(),
+        );
+        z0.extend(
+            utils::bytes_to_u32_le(&self.entry.root)
+                .iter()
+                .map(|word| G::Scalar::from(*word as u64)),
+        );
+        z0.push(G::Scalar::from(self.entry.len & 0xffff_ffff));
+        z0.push(G::Scalar::from(self.entry.len >> 32));
+        z0.push(G::Scalar::from(self.entry_idx % ENTRIES_PER_CHUNK as u64));
+        z0.push(G::Scalar::ZERO);
+        Ok(z0)
+    }
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    UnknownEntry(usize),
+    Io(io::Error),
+    /// The chunk is not in the file the entry describes
+    Audit(AuditError),
+    Nova(NovaError),
+}
+
+impl From<io::Error> for ManifestError {
+    fn from(err: io::Error) -> Self {
+        ManifestError::Io(err)
+    }
+}
+
+impl From<AuditError> for ManifestError {
+    fn from(err: AuditError) -> Self {
+        ManifestError::Audit(err)
+    }
+}
+
+impl From<NovaError> for ManifestError {
+    fn from(err: NovaError) -> Self {
+        ManifestError::Nova(err)
+    }
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::UnknownEntry(entry_idx) => {
+                write!(f, "The manifest has no entry {}", entry_idx)
+            }
+            ManifestError::Io(err) => write!(f, "IO error: {}", err),
+            ManifestError::Audit(err) => write!(f, "{}", err),
+            ManifestError::Nova(err) => write!(f, "Nova error: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+#[derive(Debug, Clone)]
+pub struct Blake3ManifestCircuit<G: Group> {
+    file: Blake3BellpepperCircuit<G>,
+    manifest: Blake3BellpepperCircuit<G>,
+    // The number of compressions in the file path
+    file_steps: usize,
+    steps_done: usize,
+}
+
+impl<G: Group> Blake3ManifestCircuit<G> {
+    pub fn new(file: Blake3HashProof, manifest: Blake3HashProof) -> Self {
+        let file = Blake3BellpepperCircuit::new(file.bytes, file.parent_path);
+        let manifest = Blake3BellpepperCircuit::new(manifest.bytes, manifest.parent_path);
+        Blake3ManifestCircuit {
+            file_steps: file.n_blocks + file.total_depth - 1,
+            file,
+            manifest,
+            steps_done: 0,
+        }
+    }
+
+    pub fn num_steps(&self) -> usize {
+        self.file_steps + self.manifest.n_blocks + self.manifest.total_depth - 1
+    }
+
+    pub fn update_for_step(&mut self) {
+        if self.steps_done < self.file_steps {
+            self.file.update_for_step();
+        } else {
+            self.manifest.update_for_step();
+        }
+        self.steps_done += 1;
+    }
+}
+
+impl<G: Group> StepCircuit<G::Scalar> for Blake3ManifestCircuit<G> {
+    fn arity(&self) -> usize {
+        MANIFEST_ARITY
+    }
+
+    fn synthesize<CS: ConstraintSystem<G::Scalar>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<G::Scalar>],
+    ) -> Result<Vec<AllocatedNum<G::Scalar>>, SynthesisError> {
+        assert_eq!(z.len(), MANIFEST_ARITY);
+        let one = CS::one();
+        let path = if self.steps_done < self.file_steps {
+            &self.file
+        } else {
+            &self.manifest
+        };
+
+        let in_manifest = AllocatedBit::alloc(
+            cs.namespace(|| "in manifest"),
+            z[PHASE_IDX].get_value().map(|v| v == G::Scalar::ONE),
+        )?;
+        cs.enforce(
+            || "in manifest is bit",
+            |lc| lc + in_manifest.get_variable(),
+            |lc| lc + one,
+            |lc| lc + z[PHASE_IDX].get_variable(),
+        );
+        let in_manifest = Boolean::from(in_manifest);
+
+        /************************* Step along the path ***********************/
+        let (path_out, m) =
+            path.synthesize_with_message(&mut cs.namespace(|| "path step"), &z[..IO_ARITY])?;
+
+        /************************* Bind the file root to the entry ***********************/
+        // The file path is done after the compression at depth 0 which absorbs its last block,
+        // and must have reached the claimed root
+        let at_root = is_equal(
+            cs.namespace(|| "at root"),
+            &Expr::num(&z[11]),
+            &Expr::constant(one, G::Scalar::ZERO),
+        )?;
+        let blocks_done = is_equal(
+            cs.namespace(|| "blocks done"),
+            &Expr::num(&path_out[1]),
+            &Expr::num(&path_out[0]),
+        )?;
+        let finished = Boolean::and(cs.namespace(|| "finished"), &at_root, &blocks_done)?;
+        let file_done = Boolean::and(cs.namespace(|| "file done"), &finished, &in_manifest.not())?;
+        for i in 0..8 {
+            cs.enforce(
+                || format!("file root {}", i),
+                |_| file_done.lc(one, G::Scalar::ONE),
+                |lc| lc + path_out[2 + i].get_variable() - z[ROOT_IDX + i].get_variable(),
+                |lc| lc,
+            );
+        }
+
+        // The entry's block starts with the root's words then the length's
+        let at_entry = is_equal(
+            cs.namespace(|| "at entry"),
+            &Expr::num(&z[1]),
+            &Expr::num(&z[ENTRY_BLOCK_IDX]),
+        )?;
+        let check_entry = Boolean::and(cs.namespace(|| "check entry"), &in_manifest, &at_entry)?;
+        for (i, expected) in z[ROOT_IDX..ENTRY_BLOCK_IDX].iter().enumerate() {
+            cs.enforce(
+                || format!("entry word {}", i),
+                |_| check_entry.lc(one, G::Scalar::ONE),
+                |lc| lc + m[i].get_variable() - expected.get_variable(),
+                |lc| lc,
+            );
+        }
+
+        /************************* Move on to the manifest path ***********************/
+        let mut out = path_out
+            .iter()
+            .zip(z[MANIFEST_START_IDX..ROOT_IDX].iter())
+            .enumerate()
+            .map(|(i, (next, start))| {
+                utils::conditionally_select(
+                    cs.namespace(|| format!("path out {}", i)),
+                    start,
+                    next,
+                    &file_done,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        out.extend_from_slice(&z[MANIFEST_START_IDX..PHASE_IDX]);
+        // Only one of the two can be set
+        out.push(alloc_expr(
+            cs.namespace(|| "in manifest out"),
+            &Expr::bit(one, &in_manifest).add(&Expr::bit(one, &file_done)),
+        )?);
+        Ok(out)
+    }
+}
+
+/// The public parameters of manifest proofs. The chain has the same shape for any file and
+/// manifest, so one set proves chunks of any dataset.
+pub fn setup_manifest_params<E1, E2, SS1, SS2>() -> PublicParams<
+    E1,
+    E2,
+    Blake3ManifestCircuit<<E1 as Engine>::GE>,
+    TrivialCircuit<<E2 as Engine>::Scalar>,
+>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    SS1: RelaxedR1CSSNARKTrait<E1>,
+    SS2: RelaxedR1CSSNARKTrait<E2>,
+{
+    let (_, hash_proof) = hash_with_path(&[0u8], 0).expect("Failed to hash a single byte");
+    let circuit_primary =
+        Blake3ManifestCircuit::<<E1 as Engine>::GE>::new(hash_proof.clone(), hash_proof);
+    let start = Instant::now();
+    let pp = PublicParams::<
+        E1,
+        E2,
+        Blake3ManifestCircuit<<E1 as Engine>::GE>,
+        TrivialCircuit<<E2 as Engine>::Scalar>,
+    >::setup(
+        &circuit_primary,
+        &TrivialCircuit::default(),
+        &*SS1::ck_floor(),
+        &*SS2::ck_floor(),
+    );
+    info!(elapsed = ?start.elapsed(), "PublicParams::setup");
+    pp
+}
+
+/// Prove that the chunk of `hash_proof` is in the file of entry `entry_idx` of `manifest`
+pub fn prove_manifest_chunk<E1, E2>(
+    pp: &PublicParams<
+        E1,
+        E2,
+        Blake3ManifestCircuit<<E1 as Engine>::GE>,
+        TrivialCircuit<<E2 as Engine>::Scalar>,
+    >,
+    manifest: &Manifest,
+    entry_idx: usize,
+    hash_proof: Blake3HashProof,
+) -> Result<
+    (
+        ManifestClaim,
+        RecursiveSNARK<
+            E1,
+            E2,
+            Blake3ManifestCircuit<<E1 as Engine>::GE>,
+            TrivialCircuit<<E2 as Engine>::Scalar>,
+        >,
+    ),
+    ManifestError,
+>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    let entry = *manifest
+        .entries()
+        .get(entry_idx)
+        .ok_or(ManifestError::UnknownEntry(entry_idx))?;
+    let _span = info_span!(
+        "prove_manifest_chunk",
+        entry_idx,
+        chunk_idx = hash_proof.chunk_idx
+    )
+    .entered();
+    file_challenge(&entry).check_hash_proof(&hash_proof)?;
+    let claim = ManifestClaim {
+        manifest_root: manifest.root(),
+        n_entries: manifest.entries().len() as u64,
+        entry_idx: entry_idx as u64,
+        entry,
+        chunk_idx: hash_proof.chunk_idx,
+    };
+    let z0_primary = claim.z0::<<E1 as Engine>::GE>()?;
+
+    let mut circuit_primary = Blake3ManifestCircuit::<<E1 as Engine>::GE>::new(
+        hash_proof,
+        manifest.entry_hash_proof(entry_idx)?,
+    );
+    let circuit_secondary = TrivialCircuit::default();
+    let num_steps = circuit_primary.num_steps();
+    let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+    let mut recursive_snark = RecursiveSNARK::new(
+        pp,
+        &circuit_primary,
+        &circuit_secondary,
+        &z0_primary,
+        &z0_secondary,
+    )?;
+    for i in 0..num_steps {
+        let start = Instant::now();
+        let res = recursive_snark.prove_step(pp, &circuit_primary, &circuit_secondary);
+        circuit_primary.update_for_step();
+        info!(
+            step = i,
+            num_steps,
+            ok = res.is_ok(),
+            elapsed = ?start.elapsed(),
+            "RecursiveSNARK::prove_step"
+        );
+        res?;
+    }
+    Ok((claim, recursive_snark))
+}
+
+/// Verify a manifest proof of `claim`
+pub fn verify_manifest_chunk<E1, E2>(
+    pp: &PublicParams<
+        E1,
+        E2,
+        Blake3ManifestCircuit<<E1 as Engine>::GE>,
+        TrivialCircuit<<E2 as Engine>::Scalar>,
+    >,
+    recursive_snark: &RecursiveSNARK<
+        E1,
+        E2,
+        Blake3ManifestCircuit<<E1 as Engine>::GE>,
+        TrivialCircuit<<E2 as Engine>::Scalar>,
+    >,
+    claim: &ManifestClaim,
+) -> Result<bool, ManifestError>
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+{
+    if claim.entry_idx >= claim.n_entries {
+        return Err(ManifestError::UnknownEntry(claim.entry_idx as usize));
+    }
+    let (_, _, num_steps) = claim.statements()?;
+    let z0_primary = claim.z0::<<E1 as Engine>::GE>()?;
+    let z0_secondary = vec![<E2 as Engine>::Scalar::ZERO];
+    let (zn_primary, _) = recursive_snark.verify(pp, num_steps, &z0_primary, &z0_secondary)?;
+
+    let manifest_root = utils::format_scalar_blake_hash::<<E1 as Engine>::GE>(
+        zn_primary[2..10].try_into().unwrap(),
+    );
+    // Both paths must have been finished
+    Ok(zn_primary[PHASE_IDX] == <E1 as Engine>::Scalar::ONE
+        && zn_primary[11] == <E1 as Engine>::Scalar::ZERO
+        && zn_primary[1] == zn_primary[0]
+        && manifest_root == claim.manifest_root.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::{PallasEngine, VestaEngine};
+
+    use super::*;
+    use crate::utils::TempDir;
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = arecibo::provider::ipa_pc::EvaluationEngine<E1>;
+    type EE2 = arecibo::provider::ipa_pc::EvaluationEngine<E2>;
+    type S1 = arecibo::spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = arecibo::spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+
+    fn files() -> Vec<Vec<u8>> {
+        // Enough files for the manifest to take two chunks
+        (0..20u8).map(|i| vec![i; 100 * i as usize + 1]).collect()
+    }
+
+    #[test]
+    fn test_manifest() {
+        let tmp = TempDir::new("manifest");
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        let files = files();
+        for (i, data) in files.iter().enumerate() {
+            let sub = if i % 2 == 0 { "nested" } else { "" };
+            std::fs::write(dir.join(sub).join(format!("{}.bin", i)), data).unwrap();
+        }
+        // A copy is listed once
+        std::fs::write(dir.join("copy.bin"), &files[3]).unwrap();
+
+        let manifest = Manifest::from_dir(&dir).unwrap();
+        assert_eq!(manifest.entries().len(), files.len());
+        assert!(manifest.entries().windows(2).all(|w| w[0].root < w[1].root));
+        let bytes = manifest.to_bytes();
+        assert_eq!(bytes.len(), files.len() * ENTRY_BYTES);
+        assert_eq!(manifest.root(), *blake3::hash(&bytes).as_bytes());
+
+        let root = blake3::hash(&files[7]);
+        let idx = manifest.position(root.as_bytes()).unwrap();
+        let block = &bytes[idx * ENTRY_BYTES..(idx + 1) * ENTRY_BYTES];
+        assert_eq!(&block[..32], root.as_bytes());
+        assert_eq!(block[32..40], (files[7].len() as u64).to_le_bytes());
+    }
+
+    #[test]
+    fn test_prove_manifest_chunk() {
+        let pp = setup_manifest_params::<E1, E2, S1, S2>();
+        let mut files = files();
+        files.push(vec![42u8; 1024 * 2 + 5]);
+        let manifest = Manifest::new(
+            files
+                .iter()
+                .map(|data| ManifestEntry {
+                    root: *blake3::hash(data).as_bytes(),
+                    len: data.len() as u64,
+                })
+                .collect(),
+        );
+        let data = files.last().unwrap();
+        let entry_idx = manifest.position(blake3::hash(data).as_bytes()).unwrap();
+        let (_, hash_proof) = hash_with_path(data, 1).unwrap();
+
+        let (claim, recursive_snark) =
+            prove_manifest_chunk::<E1, E2>(&pp, &manifest, entry_idx, hash_proof.clone()).unwrap();
+        assert_eq!(claim.manifest_root, manifest.root());
+        let verify = |claim: &ManifestClaim| {
+            matches!(
+                verify_manifest_chunk(&pp, &recursive_snark, claim),
+                Ok(true)
+            )
+        };
+        assert!(verify(&claim));
+
+        // Not for another entry, length, chunk or manifest
+        let mut other = claim.clone();
+        other.entry_idx = (entry_idx as u64 + 1) % claim.n_entries;
+        assert!(!verify(&other));
+        let mut other = claim.clone();
+        other.entry.len += 1;
+        assert!(!verify(&other));
+        let mut other = claim.clone();
+        other.chunk_idx = 0;
+        assert!(!verify(&other));
+        let mut other = claim.clone();
+        other.manifest_root = [0u8; 32];
+        assert!(!verify(&other));
+
+        // A chunk of another file cannot be passed off as this entry's, whether it has another
+        // shape or the same one
+        let (_, other_shape) = hash_with_path(&files[5], 0).unwrap();
+        assert!(matches!(
+            prove_manifest_chunk::<E1, E2>(&pp, &manifest, entry_idx, other_shape),
+            Err(ManifestError::Audit(AuditError::WrongStatement { .. }))
+        ));
+        let (_, same_shape) = hash_with_path(&vec![43u8; data.len()], 1).unwrap();
+        let res = prove_manifest_chunk::<E1, E2>(&pp, &manifest, entry_idx, same_shape);
+        assert!(res.map_or(true, |(claim, recursive_snark)| !matches!(
+            verify_manifest_chunk(&pp, &recursive_snark, &claim),
+            Ok(true)
+        )));
+    }
+}
@@ -0,0 +1,950 @@
+//! Native witness generation for the `Blake3Nova` circom circuit.
+//!
+//! Every template used by `circuits/blake3_nova.circom` (including the circomlib comparators
+//! and gates) is re-implemented here signal by signal. Each signal is written under its fully
+//! qualified circom name (e.g. `main.check_depth.is_root`) and placed into the witness vector
+//! using the index listed in the circuit's `.sym` file. Signals which circom optimized away
+//! are listed in the `.sym` file without an index and are skipped. Writing a signal the `.sym`
+//! file does not list, or leaving a wire unwritten, is an error rather than a wrong witness.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+
+use ff::{PrimeField, PrimeFieldBits};
+
+use crate::blake3_circuit::{IV, MSG_PERMUTATION};
+
+/// The `D_FLAGS` template parameter used by `circuits/main/blake3_nova*.circom`
+const D_FLAGS: u64 = 0;
+
+/// Maps fully qualified circom signal names to their index in the witness vector
+#[derive(Debug, Clone)]
+pub struct SymbolMap {
+    indices: HashMap<String, usize>,
+    /// Signals which circom removed during simplification
+    removed: HashSet<String>,
+    n_wires: usize,
+}
+
+impl SymbolMap {
+    pub fn from_file(path: &str) -> Result<Self, std::io::Error> {
+        let contents = fs::read_to_string(path)?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Each line of a `.sym` file is `symbol_idx,witness_idx,component_idx,name`.
+    /// A witness index of -1 marks a signal which was removed during simplification.
+    pub fn parse(contents: &str) -> Self {
+        let mut indices = HashMap::new();
+        let mut removed = HashSet::new();
+        // Wire 0 is always the constant 1
+        let mut n_wires = 1;
+        for line in contents.lines() {
+            let mut parts = line.splitn(4, ',');
+            let (Some(_), Some(witness_idx), Some(_), Some(name)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            let Ok(witness_idx) = witness_idx.trim().parse::<i64>() else {
+                continue;
+            };
+            if witness_idx < 0 {
+                removed.insert(name.trim().to_string());
+                continue;
+            }
+            let witness_idx = witness_idx as usize;
+            n_wires = n_wires.max(witness_idx + 1);
+            indices.insert(name.trim().to_string(), witness_idx);
+        }
+        SymbolMap {
+            indices,
+            removed,
+            n_wires,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).copied()
+    }
+
+    /// Whether `name` is a signal of the circuit which circom removed
+    pub fn is_removed(&self, name: &str) -> bool {
+        self.removed.contains(name)
+    }
+
+    pub fn n_wires(&self) -> usize {
+        self.n_wires
+    }
+
+    /// The name of each wire, indexed by its position in the witness. A wire shared by several
+    /// signals (e.g. a template's input and the signal wired into it) takes the shortest name.
+    pub fn names(&self) -> Vec<Option<&str>> {
+        let mut names: Vec<Option<&str>> = vec![None; self.n_wires];
+        for (name, &idx) in &self.indices {
+            if names[idx].map_or(true, |other| name.len() < other.len()) {
+                names[idx] = Some(name);
+            }
+        }
+        names
+    }
+}
+
+/// Why the native witness could not be computed for a circuit's symbols. Either means the
+/// `.sym` file is not from the circuit re-implemented here
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitnessError {
+    /// A signal written here which the `.sym` file does not list
+    UnknownSignal(String),
+    /// A wire of the `.sym` file which no signal written here maps to
+    UnsetWire { index: usize, name: Option<String> },
+}
+
+impl fmt::Display for WitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WitnessError::UnknownSignal(name) => {
+                write!(f, "Signal `{}` is not in the symbol map", name)
+            }
+            WitnessError::UnsetWire { index, name } => write!(
+                f,
+                "Wire {} (`{}`) was not set by the native witness",
+                index,
+                name.as_deref().unwrap_or("unnamed")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WitnessError {}
+
+struct Witness<'a, F: PrimeField> {
+    symbols: &'a SymbolMap,
+    values: Vec<F>,
+    written: Vec<bool>,
+    /// The first signal written which the symbols do not list
+    unknown: Option<String>,
+}
+
+impl<'a, F: PrimeField + PrimeFieldBits> Witness<'a, F> {
+    fn new(symbols: &'a SymbolMap) -> Self {
+        let mut values = vec![F::ZERO; symbols.n_wires()];
+        let mut written = vec![false; symbols.n_wires()];
+        values[0] = F::ONE;
+        written[0] = true;
+        Witness {
+            symbols,
+            values,
+            written,
+            unknown: None,
+        }
+    }
+
+    fn set(&mut self, name: String, value: F) {
+        if let Some(idx) = self.symbols.get(&name) {
+            self.values[idx] = value;
+            self.written[idx] = true;
+        } else if self.unknown.is_none() && !self.symbols.is_removed(&name) {
+            self.unknown = Some(name);
+        }
+    }
+
+    /// The witness, if every signal written was known and every wire was written
+    fn finish(self) -> Result<Vec<F>, WitnessError> {
+        if let Some(name) = self.unknown {
+            return Err(WitnessError::UnknownSignal(name));
+        }
+        if let Some(index) = self.written.iter().position(|written| !written) {
+            let name = self.symbols.names()[index].map(str::to_string);
+            return Err(WitnessError::UnsetWire { index, name });
+        }
+        Ok(self.values)
+    }
+
+    fn set_word(&mut self, name: String, value: u32) {
+        self.set(name, F::from(value as u64));
+    }
+
+    fn set_all(&mut self, name: &str, values: &[F]) {
+        for (i, v) in values.iter().enumerate() {
+            self.set(format!("{name}[{i}]"), *v);
+        }
+    }
+
+    fn set_all_words(&mut self, name: &str, values: &[u32]) {
+        for (i, v) in values.iter().enumerate() {
+            self.set_word(format!("{name}[{i}]"), *v);
+        }
+    }
+}
+
+fn from_bool<F: PrimeField>(b: bool) -> F {
+    if b {
+        F::ONE
+    } else {
+        F::ZERO
+    }
+}
+
+/// The low 64 bits of the canonical representative of `f`
+pub(crate) fn low_u64<F: PrimeFieldBits>(f: &F) -> u64 {
+    f.to_le_bits()
+        .iter()
+        .take(64)
+        .enumerate()
+        .fold(0u64, |acc, (i, b)| acc | ((*b as u64) << i))
+}
+
+/*************************** circomlib ***************************/
+
+/// `Num2Bits(n)`. Also used for `ToBits(n)` which only differs in the input's name
+fn num2bits<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    input_name: &str,
+    n: usize,
+    input: F,
+) -> Vec<bool> {
+    w.set(format!("{prefix}.{input_name}"), input);
+    let bits = input.to_le_bits();
+    let out: Vec<bool> = (0..n).map(|i| bits[i]).collect();
+    for (i, b) in out.iter().enumerate() {
+        w.set(format!("{prefix}.out[{i}]"), from_bool(*b));
+    }
+    out
+}
+
+fn is_zero<F: PrimeField + PrimeFieldBits>(w: &mut Witness<F>, prefix: &str, input: F) -> F {
+    let inv = Option::<F>::from(input.invert()).unwrap_or(F::ZERO);
+    let out = F::ONE - input * inv;
+    w.set(format!("{prefix}.in"), input);
+    w.set(format!("{prefix}.inv"), inv);
+    w.set(format!("{prefix}.out"), out);
+    out
+}
+
+fn is_equal<F: PrimeField + PrimeFieldBits>(w: &mut Witness<F>, prefix: &str, a: F, b: F) -> F {
+    w.set(format!("{prefix}.in[0]"), a);
+    w.set(format!("{prefix}.in[1]"), b);
+    let out = is_zero(w, &format!("{prefix}.isz"), b - a);
+    w.set(format!("{prefix}.out"), out);
+    out
+}
+
+fn less_than<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    n: usize,
+    a: F,
+    b: F,
+) -> F {
+    w.set(format!("{prefix}.in[0]"), a);
+    w.set(format!("{prefix}.in[1]"), b);
+    let shifted = a + F::from(1u64 << n) - b;
+    let bits = num2bits(w, &format!("{prefix}.n2b"), "in", n + 1, shifted);
+    let out = F::ONE - from_bool::<F>(bits[n]);
+    w.set(format!("{prefix}.out"), out);
+    out
+}
+
+fn greater_eq_than<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    n: usize,
+    a: F,
+    b: F,
+) -> F {
+    w.set(format!("{prefix}.in[0]"), a);
+    w.set(format!("{prefix}.in[1]"), b);
+    let out = less_than(w, &format!("{prefix}.lt"), n, b, a + F::ONE);
+    w.set(format!("{prefix}.out"), out);
+    out
+}
+
+fn and<F: PrimeField + PrimeFieldBits>(w: &mut Witness<F>, prefix: &str, a: F, b: F) -> F {
+    let out = a * b;
+    w.set(format!("{prefix}.a"), a);
+    w.set(format!("{prefix}.b"), b);
+    w.set(format!("{prefix}.out"), out);
+    out
+}
+
+fn or<F: PrimeField + PrimeFieldBits>(w: &mut Witness<F>, prefix: &str, a: F, b: F) -> F {
+    let out = a + b - a * b;
+    w.set(format!("{prefix}.a"), a);
+    w.set(format!("{prefix}.b"), b);
+    w.set(format!("{prefix}.out"), out);
+    out
+}
+
+fn not<F: PrimeField + PrimeFieldBits>(w: &mut Witness<F>, prefix: &str, input: F) -> F {
+    let out = F::ONE - input;
+    w.set(format!("{prefix}.in"), input);
+    w.set(format!("{prefix}.out"), out);
+    out
+}
+
+/*************************** blake3_common.circom ***************************/
+
+/// `Bits33()` and `Bits34()`: split a sum of words into the low 32 bits and `n_carry` carry bits
+fn bits_with_carry<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    n_carry: usize,
+    input: u64,
+) -> u32 {
+    w.set(format!("{prefix}.inp"), F::from(input));
+    for i in 0..32 {
+        w.set(
+            format!("{prefix}.out_bits[{i}]"),
+            from_bool((input >> i) & 1 == 1),
+        );
+    }
+    w.set(format!("{prefix}.u"), from_bool((input >> 32) & 1 == 1));
+    if n_carry == 2 {
+        w.set(format!("{prefix}.v"), from_bool((input >> 33) & 1 == 1));
+    }
+    let out_word = input as u32;
+    w.set_word(format!("{prefix}.out_word"), out_word);
+    out_word
+}
+
+fn xor_word2<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    x: u32,
+    y: u32,
+) -> u32 {
+    w.set_word(format!("{prefix}.x"), x);
+    w.set_word(format!("{prefix}.y"), y);
+    num2bits(w, &format!("{prefix}.tb_x"), "inp", 32, F::from(x as u64));
+    num2bits(w, &format!("{prefix}.tb_y"), "inp", 32, F::from(y as u64));
+    let out = x ^ y;
+    for i in 0..32 {
+        let (x_bit, y_bit, out_bit) = ((x >> i) & 1, (y >> i) & 1, (out >> i) & 1);
+        w.set_word(format!("{prefix}.xor[{i}].x"), x_bit);
+        w.set_word(format!("{prefix}.xor[{i}].y"), y_bit);
+        w.set_word(format!("{prefix}.xor[{i}].out"), out_bit);
+        w.set_word(format!("{prefix}.out_bits[{i}]"), out_bit);
+    }
+    w.set_word(format!("{prefix}.out_word"), out);
+    out
+}
+
+/*************************** blake3_compression.circom ***************************/
+
+fn iv<F: PrimeField + PrimeFieldBits>(w: &mut Witness<F>, prefix: &str) -> [u32; 8] {
+    w.set_all_words(&format!("{prefix}.out"), &IV);
+    IV
+}
+
+/// `RotXorWordBits(R)`: `(word ^ bits) >>> R`
+fn rot_xor_word_bits<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    r: u32,
+    word: u32,
+    bits: u32,
+) -> u32 {
+    w.set_word(format!("{prefix}.inp1_word"), word);
+    num2bits(w, &format!("{prefix}.tb"), "inp", 32, F::from(word as u64));
+
+    let aux = word ^ bits;
+    let out = aux.rotate_right(r);
+    for i in 0..32 {
+        let (word_bit, bits_bit) = ((word >> i) & 1, (bits >> i) & 1);
+        w.set_word(format!("{prefix}.inp2_bits[{i}]"), bits_bit);
+        w.set_word(format!("{prefix}.rx.inp1_bits[{i}]"), word_bit);
+        w.set_word(format!("{prefix}.rx.inp2_bits[{i}]"), bits_bit);
+        w.set_word(format!("{prefix}.rx.aux[{i}]"), (aux >> i) & 1);
+        w.set_word(format!("{prefix}.rx.out_bits[{i}]"), (out >> i) & 1);
+        w.set_word(format!("{prefix}.out_bits[{i}]"), (out >> i) & 1);
+    }
+    w.set_word(format!("{prefix}.rx.out_word"), out);
+    w.set_word(format!("{prefix}.out_word"), out);
+    out
+}
+
+fn half_fun_g<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    (a, b, c, d): (usize, usize, usize, usize),
+    (r1, r2): (u32, u32),
+    v: &[u32; 16],
+    xy: u32,
+) -> [u32; 16] {
+    w.set_all_words(&format!("{prefix}.v"), v);
+    w.set_word(format!("{prefix}.xy"), xy);
+
+    let mut out = *v;
+    let sum_a = v[a] as u64 + v[b] as u64 + xy as u64;
+    out[a] = bits_with_carry(w, &format!("{prefix}.add1"), 2, sum_a);
+    out[d] = rot_xor_word_bits(w, &format!("{prefix}.rxor2"), r1, v[d], out[a]);
+    let sum_c = v[c] as u64 + out[d] as u64;
+    out[c] = bits_with_carry(w, &format!("{prefix}.add3"), 1, sum_c);
+    out[b] = rot_xor_word_bits(w, &format!("{prefix}.rxor4"), r2, v[b], out[c]);
+
+    w.set_all_words(&format!("{prefix}.out"), &out);
+    out
+}
+
+fn mix_fun_g<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    idxs: (usize, usize, usize, usize),
+    inp: &[u32; 16],
+    x: u32,
+    y: u32,
+) -> [u32; 16] {
+    w.set_all_words(&format!("{prefix}.inp"), inp);
+    w.set_word(format!("{prefix}.x"), x);
+    w.set_word(format!("{prefix}.y"), y);
+    let half = half_fun_g(w, &format!("{prefix}.half1"), idxs, (16, 12), inp, x);
+    let out = half_fun_g(w, &format!("{prefix}.half2"), idxs, (8, 7), &half, y);
+    w.set_all_words(&format!("{prefix}.out"), &out);
+    out
+}
+
+fn single_round<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    inp: &[u32; 16],
+    msg: &[u32; 16],
+) -> [u32; 16] {
+    const GS: [(usize, usize, usize, usize); 8] = [
+        (0, 4, 8, 12),
+        (1, 5, 9, 13),
+        (2, 6, 10, 14),
+        (3, 7, 11, 15),
+        (0, 5, 10, 15),
+        (1, 6, 11, 12),
+        (2, 7, 8, 13),
+        (3, 4, 9, 14),
+    ];
+    w.set_all_words(&format!("{prefix}.inp"), inp);
+    w.set_all_words(&format!("{prefix}.msg"), msg);
+
+    let mut vs = *inp;
+    w.set_all_words(&format!("{prefix}.vs[0]"), &vs);
+    for (i, idxs) in GS.iter().enumerate() {
+        vs = mix_fun_g(
+            w,
+            &format!("{prefix}.GS[{i}]"),
+            *idxs,
+            &vs,
+            msg[2 * i],
+            msg[2 * i + 1],
+        );
+        w.set_all_words(&format!("{prefix}.vs[{}]", i + 1), &vs);
+    }
+    w.set_all_words(&format!("{prefix}.out"), &vs);
+    vs
+}
+
+fn permute<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    inp: &[u32; 16],
+) -> [u32; 16] {
+    let out: [u32; 16] = std::array::from_fn(|j| inp[MSG_PERMUTATION[j]]);
+    w.set_all_words(&format!("{prefix}.inp"), inp);
+    w.set_all_words(&format!("{prefix}.out"), &out);
+    out
+}
+
+/// `Blake3Compression()`. `b` and `d` are taken as words as they are always small.
+fn blake3_compression<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    h: &[u32; 8],
+    m: &[u32; 16],
+    t: [u32; 2],
+    b: u32,
+    d: u32,
+) -> [u32; 16] {
+    w.set_all_words(&format!("{prefix}.h"), h);
+    w.set_all_words(&format!("{prefix}.m"), m);
+    w.set_all_words(&format!("{prefix}.t"), &t);
+    w.set_word(format!("{prefix}.b"), b);
+    w.set_word(format!("{prefix}.d"), d);
+
+    let iv = iv(w, &format!("{prefix}.iv"));
+    let mut init = [0u32; 16];
+    init[..8].copy_from_slice(h);
+    init[8..12].copy_from_slice(&iv[..4]);
+    init[12] = t[0];
+    init[13] = t[1];
+    init[14] = b;
+    init[15] = d;
+    w.set_all_words(&format!("{prefix}.init"), &init);
+
+    let mut state = single_round(w, &format!("{prefix}.rounds[0]"), &init, m);
+    let mut msg = *m;
+    for i in 0..6 {
+        msg = permute(w, &format!("{prefix}.permuters[{i}]"), &msg);
+        state = single_round(w, &format!("{prefix}.rounds[{}]", i + 1), &state, &msg);
+    }
+
+    let mut out = [0u32; 16];
+    for i in 0..8 {
+        out[i] = xor_word2(w, &format!("{prefix}.outXor[{i}]"), state[i], state[i + 8]);
+    }
+    for i in 8..16 {
+        out[i] = xor_word2(w, &format!("{prefix}.outXor[{i}]"), state[i], h[i - 8]);
+    }
+    w.set_all_words(&format!("{prefix}.out"), &out);
+    out
+}
+
+/*************************** blake3_nova.circom ***************************/
+
+/// Returns `(is_root, is_parent)`
+fn check_depth<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    depth: F,
+    leaf_depth: F,
+) -> (F, F) {
+    w.set(format!("{prefix}.depth"), depth);
+    w.set(format!("{prefix}.leaf_depth"), leaf_depth);
+
+    let is_root = is_equal(w, &format!("{prefix}.check_root"), depth, F::ZERO);
+    num2bits(w, &format!("{prefix}.n2b_depth"), "in", 8, depth);
+    num2bits(w, &format!("{prefix}.n2b_leaf_depth"), "in", 8, leaf_depth);
+    let is_parent = less_than(
+        w,
+        &format!("{prefix}.check_parent"),
+        8,
+        depth,
+        leaf_depth - F::ONE,
+    );
+    greater_eq_than(w, &format!("{prefix}.exceed_depth"), 8, depth, leaf_depth);
+
+    w.set(format!("{prefix}.is_root"), is_root);
+    w.set(format!("{prefix}.is_parent"), is_parent);
+    (is_root, is_parent)
+}
+
+fn get_down_left_path<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    depth: F,
+    leaf_idx: F,
+    is_parent: F,
+    total_depth: F,
+) -> F {
+    w.set(format!("{prefix}.depth"), depth);
+    w.set(format!("{prefix}.leaf_idx"), leaf_idx);
+    w.set(format!("{prefix}.is_parent"), is_parent);
+    w.set(format!("{prefix}.total_depth"), total_depth);
+
+    let bits = num2bits(w, &format!("{prefix}.n2b"), "in", 65, leaf_idx);
+    let mut bit_at_depth = F::ZERO;
+    for (i, bit) in bits.iter().enumerate().take(64) {
+        let eq = is_equal(
+            w,
+            &format!("{prefix}.eqs[{i}]"),
+            depth,
+            total_depth - F::from(i as u64 + 2),
+        );
+        bit_at_depth += (F::ONE - from_bool::<F>(*bit)) * eq;
+        w.set(format!("{prefix}.bit_at_depth[{i}]"), bit_at_depth);
+    }
+
+    let out = (F::ONE - is_parent) + is_parent * bit_at_depth;
+    w.set(format!("{prefix}.out"), out);
+    out
+}
+
+#[allow(clippy::too_many_arguments)]
+fn get_final_m<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    h: &[F; 8],
+    m: &[F; 16],
+    is_parent: F,
+    depth: F,
+    total_depth: F,
+    chunk_idx: F,
+) -> [F; 16] {
+    w.set_all(&format!("{prefix}.h"), h);
+    w.set_all(&format!("{prefix}.m"), m);
+    w.set(format!("{prefix}.is_parent"), is_parent);
+    w.set(format!("{prefix}.depth"), depth);
+    w.set(format!("{prefix}.total_depth"), total_depth);
+    w.set(format!("{prefix}.chunk_idx"), chunk_idx);
+
+    let down = get_down_left_path(
+        w,
+        &format!("{prefix}.down_left_path"),
+        depth,
+        chunk_idx,
+        is_parent,
+        total_depth,
+    );
+
+    let mut out_m = [F::ZERO; 16];
+    for i in 0..16 {
+        let (tmp_down, m_is_parent) = if i < 8 {
+            let tmp_down = h[i] * down;
+            (tmp_down, m[i] * (F::ONE - down) + tmp_down)
+        } else {
+            let tmp_down = h[i - 8] * (F::ONE - down);
+            (tmp_down, m[i - 8] * down + tmp_down)
+        };
+        let tmp_is_par = m_is_parent * is_parent;
+        out_m[i] = m[i] * (F::ONE - is_parent) + tmp_is_par;
+        w.set(format!("{prefix}.tmp_down[{i}]"), tmp_down);
+        w.set(format!("{prefix}.m_is_parent[{i}]"), m_is_parent);
+        w.set(format!("{prefix}.tmp_is_par[{i}]"), tmp_is_par);
+    }
+    w.set_all(&format!("{prefix}.out_m"), &out_m);
+    out_m
+}
+
+/// Returns `(out, is_last_block)`
+fn get_flag<F: PrimeField + PrimeFieldBits>(
+    w: &mut Witness<F>,
+    prefix: &str,
+    is_parent: F,
+    is_root: F,
+    block_count: F,
+    n_blocks: F,
+) -> (F, F) {
+    w.set(format!("{prefix}.is_parent"), is_parent);
+    w.set(format!("{prefix}.is_root"), is_root);
+    w.set(format!("{prefix}.block_count"), block_count);
+    w.set(format!("{prefix}.n_blocks"), n_blocks);
+
+    not(w, &format!("{prefix}.not_root"), is_root);
+    let not_parent = not(w, &format!("{prefix}.not_parent"), is_parent);
+    let is_first = is_equal(
+        w,
+        &format!("{prefix}.check_block_counts[0]"),
+        block_count,
+        F::ZERO,
+    );
+    let is_last = is_equal(
+        w,
+        &format!("{prefix}.check_block_counts[1]"),
+        block_count,
+        n_blocks - F::ONE,
+    );
+    let is_last_block = is_last * not_parent;
+
+    let first_flag = and(
+        w,
+        &format!("{prefix}.first_block_flag_set"),
+        is_first,
+        not_parent,
+    );
+    let last_flag = and(
+        w,
+        &format!("{prefix}.last_block_flag_set"),
+        is_last,
+        not_parent,
+    );
+    let use_root_flag_tmp = or(
+        w,
+        &format!("{prefix}.use_root_flag_tmp"),
+        is_parent,
+        is_last,
+    );
+    let use_root_flag = use_root_flag_tmp * is_root;
+
+    let out = F::from(D_FLAGS)
+        + first_flag
+        + F::from(2u64) * last_flag
+        + F::from(8u64) * use_root_flag
+        + F::from(4u64) * is_parent;
+
+    w.set(format!("{prefix}.use_root_flag"), use_root_flag);
+    w.set(format!("{prefix}.is_last_block"), is_last_block);
+    w.set(format!("{prefix}.out"), out);
+    (out, is_last_block)
+}
+
+fn input<'a, F>(inputs: &'a HashMap<&str, &[F]>, name: &str, len: usize) -> &'a [F] {
+    let values = *inputs
+        .get(name)
+        .unwrap_or_else(|| panic!("Missing circom input `{}`", name));
+    assert_eq!(
+        values.len(),
+        len,
+        "Wrong length for circom input `{}`",
+        name
+    );
+    values
+}
+
+/// Compute the full witness of `Blake3Nova(0)` from the inputs built by
+/// `Blake3BlockCompressCircuit::format_input`. The result has the same layout as the witness
+/// returned by `circom_scotia::calculate_witness` for the compiled circuit described by `symbols`.
+/// Fails if `symbols` are not those of the circuit computed here.
+pub fn calculate_witness<F: PrimeField + PrimeFieldBits>(
+    symbols: &SymbolMap,
+    inputs: &[(String, Vec<F>)],
+) -> Result<Vec<F>, WitnessError> {
+    let inputs: HashMap<&str, &[F]> = inputs
+        .iter()
+        .map(|(name, values)| (name.as_str(), values.as_slice()))
+        .collect();
+    let scalar = |name: &str| input(&inputs, name, 1)[0];
+    let words = |name: &str, len: usize| -> Vec<u32> {
+        input(&inputs, name, len)
+            .iter()
+            .map(|x| low_u64(x) as u32)
+            .collect()
+    };
+
+    let n_blocks = scalar("n_blocks");
+    let block_count = scalar("block_count");
+    let chunk_idx_low = scalar("chunk_idx_low");
+    let chunk_idx_high = scalar("chunk_idx_high");
+    let leaf_depth = scalar("leaf_depth");
+    let total_depth = scalar("total_depth");
+    let depth = scalar("depth");
+    let b = scalar("b");
+    let h: [F; 8] = input(&inputs, "h", 8).try_into().unwrap();
+    let m: [F; 16] = input(&inputs, "m", 16).try_into().unwrap();
+    let h_words: [u32; 8] = words("h", 8).try_into().unwrap();
+
+    let mut w = Witness::new(symbols);
+    w.set("main.n_blocks".into(), n_blocks);
+    w.set("main.block_count".into(), block_count);
+    w.set_all("main.h", &h);
+    w.set("main.chunk_idx_low".into(), chunk_idx_low);
+    w.set("main.chunk_idx_high".into(), chunk_idx_high);
+    w.set("main.leaf_depth".into(), leaf_depth);
+    w.set("main.total_depth".into(), total_depth);
+    w.set("main.depth".into(), depth);
+    w.set_all("main.m", &m);
+    w.set("main.b".into(), b);
+
+    let (is_root, is_parent) = check_depth(&mut w, "main.check_depth", depth, leaf_depth);
+    let (d, is_last_block) = get_flag(
+        &mut w,
+        "main.comp_d",
+        is_parent,
+        is_root,
+        block_count,
+        n_blocks,
+    );
+
+    let iv = iv(&mut w, "main.iv");
+    let chunk_idx = chunk_idx_low + chunk_idx_high * F::from(1u64 << 32);
+    let final_m = get_final_m(
+        &mut w,
+        "main.final_m",
+        &h,
+        &m,
+        is_parent,
+        depth,
+        total_depth,
+        chunk_idx,
+    );
+
+    let parent = is_parent == F::ONE;
+    let mut h_compression = [0u32; 8];
+    for i in 0..8 {
+        let tmp_iv = if parent { iv[i] } else { 0 };
+        h_compression[i] = if parent { iv[i] } else { h_words[i] };
+        w.set_word(format!("main.tmpIV[{i}]"), tmp_iv);
+        w.set_word(format!("main.h_compression[{i}]"), h_compression[i]);
+    }
+
+    let final_m_words: [u32; 16] = std::array::from_fn(|i| low_u64(&final_m[i]) as u32);
+    let t = if parent {
+        [0, 0]
+    } else {
+        [
+            low_u64(&chunk_idx_low) as u32,
+            low_u64(&chunk_idx_high) as u32,
+        ]
+    };
+    let h_out = blake3_compression(
+        &mut w,
+        "main.blake3Compression",
+        &h_compression,
+        &final_m_words,
+        t,
+        low_u64(&b) as u32,
+        low_u64(&d) as u32,
+    );
+    w.set_all_words("main.h_out", &h_out[..8]);
+
+    let decr_depth_tmp = or(&mut w, "main.check_decr_depth", is_last_block, is_parent);
+    let decr_depth = decr_depth_tmp * (F::ONE - is_root);
+    w.set("main.decr_depth".into(), decr_depth);
+
+    w.set(
+        "main.block_count_out".into(),
+        block_count + (F::ONE - is_parent),
+    );
+    w.set("main.n_blocks_out".into(), n_blocks);
+    w.set("main.depth_out".into(), depth - decr_depth);
+    w.set("main.total_depth_out".into(), total_depth);
+    w.set("main.chunk_idx_low_out".into(), chunk_idx_low);
+    w.set("main.chunk_idx_high_out".into(), chunk_idx_high);
+    w.set("main.leaf_depth_out".into(), leaf_depth);
+
+    w.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::PallasEngine;
+    use arecibo::traits::Engine;
+    use circom_scotia::calculate_witness as calculate_wasm_witness;
+    use circom_scotia::r1cs::CircomConfig;
+
+    use std::time::Instant;
+    use tracing::info;
+
+    use super::*;
+    use crate::blake3_circuit::{
+        Blake3BlockCompressCircuit, Blake3CompressPubIO, CircomArtifacts, CIRCOM_IO_ARITY,
+    };
+    use crate::blake3_hash::hash_with_path;
+    use crate::{PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_SYM_PATH, PASTA_CIRCOM_WASM_PATH};
+
+    type G = <PallasEngine as Engine>::GE;
+    type F = <PallasEngine as Engine>::Scalar;
+
+    /// Step through every fold for `chunk_idx` of `data`, checking the native witness against
+    /// the wasm witness at each step
+    fn check_native_witness(data: &[u8], chunk_idx: usize) {
+        let cfg = CircomConfig::<F>::new(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let symbols = SymbolMap::from_file(PASTA_CIRCOM_SYM_PATH).unwrap();
+
+        let (_, hash_proof) = hash_with_path(data, chunk_idx).unwrap();
+        let mut circuit = Blake3BlockCompressCircuit::<G>::new(
+            hash_proof.bytes,
+            hash_proof.parent_path,
+            PASTA_CIRCOM_WASM_PATH.to_string(),
+            PASTA_CIRCOM_R1CS_PATH.to_string(),
+        );
+        let mut z = Blake3CompressPubIO::<G>::new(
+            hash_proof.chunk_idx,
+            F::from(circuit.total_depth as u64),
+            F::from(circuit.n_blocks as u64),
+            IV.iter().map(|iv| F::from(*iv as u64)).collect(),
+            F::from(circuit.total_depth as u64),
+        )
+        .to_vec();
+
+        let num_steps = circuit.n_blocks + circuit.total_depth - 1;
+        for i in 0..num_steps {
+            let input = circuit.format_input_from_vec(z.clone());
+            let expected = calculate_wasm_witness(&cfg, input.clone(), true).unwrap();
+            let native = calculate_witness(&symbols, &input).unwrap();
+            assert_eq!(native, expected, "Witness mismatch at step {}", i);
+            // The public outputs come first in the witness, and the nonce is passed through
+            z[..CIRCOM_IO_ARITY].copy_from_slice(&native[1..=CIRCOM_IO_ARITY]);
+            circuit.update_for_step();
+        }
+    }
+
+    #[test]
+    fn test_native_witness_single_chunk() {
+        check_native_witness(&[117u8; 17], 0);
+        check_native_witness(&[0u8; 1024], 0);
+    }
+
+    #[test]
+    fn test_native_witness_path() {
+        let data = vec![3u8; 1024 * 3 + 5];
+        check_native_witness(&data, 1);
+        check_native_witness(&data, 3);
+    }
+
+    #[test]
+    fn test_native_witness_speed() {
+        let start = Instant::now();
+        let cfg = CircomConfig::<F>::new(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let wasm_load = start.elapsed();
+        let start = Instant::now();
+        let native =
+            CircomArtifacts::<F>::load_native(PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_SYM_PATH)
+                .unwrap();
+        let native_load = start.elapsed();
+        let symbols = SymbolMap::from_file(PASTA_CIRCOM_SYM_PATH).unwrap();
+
+        // The inputs of every compression of a full chunk and its path
+        let data = vec![9u8; 1024 * 4];
+        let (_, hash_proof) = hash_with_path(&data, 2).unwrap();
+        let mut circuit = Blake3BlockCompressCircuit::<G>::with_artifacts(
+            hash_proof.bytes,
+            hash_proof.parent_path,
+            native,
+        );
+        let mut z =
+            Blake3CompressPubIO::<G>::initial(2, circuit.n_blocks, circuit.total_depth).to_vec();
+        let mut inputs = vec![];
+        for _ in 0..(circuit.n_blocks + circuit.total_depth - 1) {
+            let input = circuit.format_input_from_vec(z.clone());
+            // The public outputs come first in the witness, and the nonce is passed through
+            let witness = calculate_witness(&symbols, &input).unwrap();
+            z[..CIRCOM_IO_ARITY].copy_from_slice(&witness[1..=CIRCOM_IO_ARITY]);
+            inputs.push(input);
+            circuit.update_for_step();
+        }
+
+        let start = Instant::now();
+        for input in &inputs {
+            calculate_wasm_witness(&cfg, input.clone(), true).unwrap();
+        }
+        let wasm_witness = start.elapsed();
+        let start = Instant::now();
+        for input in &inputs {
+            calculate_witness(&symbols, input).unwrap();
+        }
+        let native_witness = start.elapsed();
+        info!(
+            n_compressions = inputs.len(),
+            ?wasm_load,
+            ?native_load,
+            ?wasm_witness,
+            ?native_witness,
+            "Witness generation times"
+        );
+    }
+
+    #[test]
+    fn test_parse_symbols() {
+        let symbols = SymbolMap::parse("1,1,2,main.out[0]\n2,-1,2,main.tmp\n3,4,2,main.in\n");
+        assert_eq!(symbols.get("main.out[0]"), Some(1));
+        assert_eq!(symbols.get("main.tmp"), None);
+        assert!(symbols.is_removed("main.tmp"));
+        assert_eq!(symbols.n_wires(), 5);
+    }
+
+    #[test]
+    fn test_witness_errors() {
+        let symbols = SymbolMap::parse("1,1,0,main.a\n2,-1,0,main.tmp\n3,2,0,main.b\n");
+        let mut w = Witness::<F>::new(&symbols);
+        w.set("main.a".into(), F::ONE);
+        w.set("main.tmp".into(), F::ONE);
+        assert_eq!(
+            w.finish(),
+            Err(WitnessError::UnsetWire {
+                index: 2,
+                name: Some("main.b".to_string())
+            })
+        );
+
+        let mut w = Witness::<F>::new(&symbols);
+        w.set("main.a".into(), F::ONE);
+        w.set("main.b".into(), F::ONE);
+        w.set("main.c".into(), F::ONE);
+        assert_eq!(
+            w.finish(),
+            Err(WitnessError::UnknownSignal("main.c".to_string()))
+        );
+
+        let mut w = Witness::<F>::new(&symbols);
+        w.set("main.a".into(), F::ONE);
+        w.set("main.b".into(), F::from(2));
+        assert_eq!(w.finish(), Ok(vec![F::ONE, F::ONE, F::from(2)]));
+    }
+}
@@ -0,0 +1,133 @@
+//! Prover cost numbers for a single proof, so they can be tracked across circuit versions
+//! rather than only showing up in the logs.
+use arecibo::traits::circuit::StepCircuit;
+use arecibo::traits::Engine;
+use arecibo::PublicParams;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Times are in milliseconds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProofStats {
+    pub primary_constraints: usize,
+    pub secondary_constraints: usize,
+    pub primary_variables: usize,
+    pub secondary_variables: usize,
+    pub num_steps: usize,
+    pub setup_ms: f64,
+    pub step_ms: Vec<f64>,
+    pub verify_ms: f64,
+    pub compress_ms: Option<f64>,
+    /// The bincode size of the recursive proof, or of the compressed proof once compressed
+    pub proof_size_bytes: u64,
+    /// The process's peak resident set size, where the platform reports it.
+    /// This covers everything the process did so far, not only this proof.
+    pub peak_memory_bytes: Option<u64>,
+}
+
+const CSV_HEADER: &str = "primary_constraints,secondary_constraints,primary_variables,\
+secondary_variables,num_steps,setup_ms,step_ms,verify_ms,compress_ms,proof_size_bytes,\
+peak_memory_bytes";
+
+impl ProofStats {
+    pub(crate) fn record_params<E1, E2, C1, C2>(&mut self, pp: &PublicParams<E1, E2, C1, C2>)
+    where
+        E1: Engine<Base = <E2 as Engine>::Scalar>,
+        E2: Engine<Base = <E1 as Engine>::Scalar>,
+        C1: StepCircuit<E1::Scalar>,
+        C2: StepCircuit<E2::Scalar>,
+    {
+        (self.primary_constraints, self.secondary_constraints) = pp.num_constraints();
+        (self.primary_variables, self.secondary_variables) = pp.num_variables();
+    }
+
+    /// Add the numbers from `compress_snark` to the ones from proving
+    pub fn with_compression(mut self, compression: &ProofStats) -> Self {
+        self.compress_ms = compression.compress_ms;
+        self.proof_size_bytes = compression.proof_size_bytes;
+        self.peak_memory_bytes = self.peak_memory_bytes.max(compression.peak_memory_bytes);
+        self
+    }
+
+    pub fn total_step_ms(&self) -> f64 {
+        self.step_ms.iter().sum()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("ProofStats is always serializable")
+    }
+
+    pub fn csv_header() -> &'static str {
+        CSV_HEADER
+    }
+
+    /// One CSV row matching `csv_header`. The step times are `;` separated within their column
+    pub fn to_csv_row(&self) -> String {
+        let step_ms = self
+            .step_ms
+            .iter()
+            .map(|ms| ms.to_string())
+            .collect::<Vec<_>>()
+            .join(";");
+        let optional = |v: Option<String>| v.unwrap_or_default();
+        [
+            self.primary_constraints.to_string(),
+            self.secondary_constraints.to_string(),
+            self.primary_variables.to_string(),
+            self.secondary_variables.to_string(),
+            self.num_steps.to_string(),
+            self.setup_ms.to_string(),
+            step_ms,
+            self.verify_ms.to_string(),
+            optional(self.compress_ms.map(|ms| ms.to_string())),
+            self.proof_size_bytes.to_string(),
+            optional(self.peak_memory_bytes.map(|b| b.to_string())),
+        ]
+        .join(",")
+    }
+}
+
+pub(crate) fn millis(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64() * 1000.0
+}
+
+/// The peak resident set size (`VmHWM`) from `/proc/self/status`. `None` off Linux
+pub(crate) fn peak_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    // e.g. "VmHWM:     123456 kB"
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_row() {
+        let stats = ProofStats {
+            primary_constraints: 10,
+            secondary_constraints: 20,
+            primary_variables: 30,
+            secondary_variables: 40,
+            num_steps: 2,
+            setup_ms: 1.5,
+            step_ms: vec![2.0, 3.25],
+            verify_ms: 4.0,
+            compress_ms: None,
+            proof_size_bytes: 1000,
+            peak_memory_bytes: Some(4096),
+        };
+        let row = stats.to_csv_row();
+        assert_eq!(row, "10,20,30,40,2,1.5,2;3.25,4,,1000,4096");
+        assert_eq!(
+            row.split(',').count(),
+            ProofStats::csv_header().split(',').count()
+        );
+        assert_eq!(stats.total_step_ms(), 5.25);
+
+        let parsed: ProofStats = serde_json::from_str(&stats.to_json()).unwrap();
+        assert_eq!(parsed, stats);
+    }
+}
@@ -0,0 +1,472 @@
+//! The storage provider side of proof-of-storage: a daemon which keeps the files it stores
+//! indexed by BLAKE3 root, and answers audit challenges for them with chunk proofs.
+//! Run it with `blake3_fold provide`.
+//!
+//! The provider keeps a bao outboard (the file's hash tree without the data) per file, so a
+//! challenged chunk's path is read from the outboard rather than by rehashing the file.
+//! Outboards are recomputed, streaming the file, when its length or modification time changes.
+//! A file or challenge which cannot be read is logged and skipped, so it never stops the
+//! daemon from serving the others.
+//!
+//! Challenges (`audit::Challenge`) are files dropped into an inbox directory. Each is answered
+//! by writing an `audit::Response` named after the challenge id to the outbox, and removing the
+//! challenge. Challenges past their deadline are dropped unanswered, since a late proof does
+//! not count.
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+use crate::audit::{Challenge, Response};
+use crate::backend::{Backend, ChunkProver};
+use crate::blake3_hash::{path_with_outboard, Blake3HashProof};
+
+const INDEX_FILE: &str = "index.json";
+const CHALLENGE_EXTENSION: &str = "challenge";
+const RESPONSE_EXTENSION: &str = "response";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct IndexedFile {
+    path: PathBuf,
+    len: u64,
+    modified: SystemTime,
+    /// The hex BLAKE3 root
+    root: String,
+}
+
+/// The files of a directory by BLAKE3 root, with their outboards in a state directory
+pub struct FileIndex {
+    data_dir: PathBuf,
+    state_dir: PathBuf,
+    files: HashMap<PathBuf, IndexedFile>,
+}
+
+impl FileIndex {
+    /// Open the index saved in `state_dir`, and bring it up to date with `data_dir`
+    pub fn open(data_dir: impl Into<PathBuf>, state_dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let state_dir = state_dir.into();
+        std::fs::create_dir_all(&state_dir)?;
+        let files = match std::fs::read(state_dir.join(INDEX_FILE)) {
+            Ok(json) => serde_json::from_slice::<Vec<IndexedFile>>(&json)?
+                .into_iter()
+                .map(|file| (file.path.clone(), file))
+                .collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        let mut index = FileIndex {
+            data_dir: data_dir.into(),
+            state_dir,
+            files,
+        };
+        index.refresh()?;
+        Ok(index)
+    }
+
+    fn outboard_path(&self, root: &str) -> PathBuf {
+        self.state_dir.join(format!("{}.obao", root))
+    }
+
+    /// Stream the file at `path` through a bao encoder into its outboard, which is named after
+    /// the root and so only moved into place once the whole file is read
+    fn index_file(&self, path: &Path, len: u64, modified: SystemTime) -> io::Result<IndexedFile> {
+        let tmp = self.state_dir.join("indexing.obao.tmp");
+        let outboard = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        let mut encoder = bao::encode::Encoder::new_outboard(outboard);
+        io::copy(&mut BufReader::new(File::open(path)?), &mut encoder)?;
+        let root = encoder.finalize()?.to_hex().to_string();
+        std::fs::rename(&tmp, self.outboard_path(&root))?;
+        info!(?path, %root, "Indexed file");
+        Ok(IndexedFile {
+            path: path.into(),
+            len,
+            modified,
+            root,
+        })
+    }
+
+    /// Index new and changed files, and forget removed ones. Files which cannot be read are
+    /// left out of the index until they can
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let old_roots: Vec<String> = self.files.values().map(|f| f.root.clone()).collect();
+        let mut files = HashMap::new();
+        for entry in std::fs::read_dir(&self.data_dir)? {
+            let (path, metadata) =
+                match entry.and_then(|entry| Ok((entry.path(), entry.metadata()?))) {
+                    Ok(found) => found,
+                    Err(err) => {
+                        warn!(%err, "Skipping unreadable data directory entry");
+                        continue;
+                    }
+                };
+            if !metadata.is_file() {
+                continue;
+            }
+            let len = metadata.len();
+            let res = metadata.modified().and_then(|modified| {
+                let unchanged = self.files.get(&path).filter(|file| {
+                    file.len == len
+                        && file.modified == modified
+                        && self.outboard_path(&file.root).exists()
+                });
+                match unchanged {
+                    Some(file) => Ok(file.clone()),
+                    None => self.index_file(&path, len, modified),
+                }
+            });
+            match res {
+                Ok(file) => {
+                    files.insert(path, file);
+                }
+                Err(err) => warn!(%err, ?path, "Failed to index file"),
+            }
+        }
+        for path in self.files.keys().filter(|path| !files.contains_key(*path)) {
+            info!(?path, "Dropped removed file");
+        }
+        // Outboards of removed or changed files
+        for root in old_roots {
+            if !files.values().any(|f: &IndexedFile| f.root == root) {
+                let _ = std::fs::remove_file(self.outboard_path(&root));
+            }
+        }
+        self.files = files;
+        let files: Vec<_> = self.files.values().collect();
+        std::fs::write(self.state_dir.join(INDEX_FILE), serde_json::to_vec(&files)?)
+    }
+
+    fn find(&self, root: &str) -> Option<&IndexedFile> {
+        self.files.values().find(|file| file.root == root)
+    }
+
+    pub fn roots(&self) -> Vec<String> {
+        self.files.values().map(|file| file.root.clone()).collect()
+    }
+
+    /// The path from the chunk `chunk_idx` of the file with hex root `root` to the root
+    pub fn hash_proof(&self, root: &str, chunk_idx: u64) -> io::Result<Blake3HashProof> {
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, format!("No file {}", root));
+        let file = self.find(root).ok_or_else(not_found)?;
+        let hash = blake3::Hash::from_hex(root).map_err(|_| not_found())?;
+        path_with_outboard(
+            File::open(&file.path)?,
+            File::open(self.outboard_path(root))?,
+            &hash,
+            file.len,
+            chunk_idx,
+        )
+    }
+}
+
+pub(crate) fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+#[derive(Debug, Clone)]
+pub struct ProviderConfig {
+    pub data_dir: PathBuf,
+    pub state_dir: PathBuf,
+    pub inbox_dir: PathBuf,
+    pub outbox_dir: PathBuf,
+    pub poll_interval: Duration,
+    /// Who the responses say they are from
+    pub prover_id: String,
+}
+
+pub struct Provider {
+    config: ProviderConfig,
+    index: FileIndex,
+    prover: Arc<dyn ChunkProver>,
+}
+
+impl Provider {
+    pub fn new(prover: Arc<dyn ChunkProver>, config: ProviderConfig) -> io::Result<Self> {
+        std::fs::create_dir_all(&config.inbox_dir)?;
+        std::fs::create_dir_all(&config.outbox_dir)?;
+        Ok(Provider {
+            index: FileIndex::open(&config.data_dir, &config.state_dir)?,
+            config,
+            prover,
+        })
+    }
+
+    /// The challenges waiting in the inbox, earliest deadline first
+    fn pending_challenges(&self) -> io::Result<Vec<(PathBuf, Challenge)>> {
+        let mut challenges = vec![];
+        for entry in std::fs::read_dir(&self.config.inbox_dir)? {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err) => {
+                    warn!(%err, "Skipping unreadable inbox entry");
+                    continue;
+                }
+            };
+            if path
+                .extension()
+                .map_or(true, |ext| ext != CHALLENGE_EXTENSION)
+            {
+                continue;
+            }
+            let bytes = match std::fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    warn!(%err, ?path, "Skipping unreadable challenge");
+                    continue;
+                }
+            };
+            match Challenge::from_bytes(&bytes) {
+                Ok(challenge) => challenges.push((path, challenge)),
+                Err(err) => {
+                    // Might still be being written, so leave it for the next poll
+                    debug!(%err, ?path, "Skipping unreadable challenge");
+                }
+            }
+        }
+        challenges.sort_by_key(|(_, challenge)| challenge.deadline_unix_ms);
+        Ok(challenges)
+    }
+
+    fn answer(&self, challenge: &Challenge) -> Result<Response, String> {
+        let root = blake3::Hash::from(challenge.root).to_hex();
+        let bundles = challenge
+            .chunk_indices()
+            .par_iter()
+            .map(|&chunk_idx| {
+                let hash_proof = self
+                    .index
+                    .hash_proof(&root, chunk_idx)
+                    .map_err(|err| format!("Chunk {}: {}", chunk_idx, err))?;
+                challenge
+                    .check_hash_proof(&hash_proof)
+                    .map_err(|err| err.to_string())?;
+                self.prover
+                    .prove(hash_proof, Some(challenge.nonce))
+                    .map_err(|err| format!("Chunk {}: {}", chunk_idx, err))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Response {
+            challenge_id: challenge.id(),
+            prover_id: self.config.prover_id.clone(),
+            bundles,
+        })
+    }
+
+    /// Refresh the index and answer every pending challenge. Returns how many were answered
+    pub fn poll(&mut self) -> io::Result<usize> {
+        if let Err(err) = self.index.refresh() {
+            warn!(%err, "Failed to refresh the file index, answering from the last one");
+        }
+        let mut n_answered = 0;
+        for (path, challenge) in self.pending_challenges()? {
+            let id = blake3::Hash::from(challenge.id()).to_hex();
+            let _span = tracing::info_span!("challenge", %id).entered();
+            let expired = || unix_ms(SystemTime::now()) > challenge.deadline_unix_ms;
+            if expired() {
+                warn!("Dropping expired challenge");
+            } else {
+                match self.answer(&challenge) {
+                    Ok(_) if expired() => warn!("Proved the challenge too late, dropping it"),
+                    Ok(response) => {
+                        let out = response_path(&self.config.outbox_dir, &challenge);
+                        if let Err(err) = std::fs::write(&out, response.to_bytes()) {
+                            // Left in the inbox to be answered again on the next poll
+                            warn!(%err, ?out, "Failed to write the response");
+                            continue;
+                        }
+                        info!(
+                            ?out,
+                            n_proofs = response.bundles.len(),
+                            "Answered challenge"
+                        );
+                        n_answered += 1;
+                    }
+                    Err(err) => warn!(%err, "Failed to answer challenge"),
+                }
+            }
+            if let Err(err) = std::fs::remove_file(&path) {
+                warn!(%err, ?path, "Failed to remove the challenge");
+            }
+        }
+        Ok(n_answered)
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        info!(
+            n_files = self.index.files.len(),
+            inbox = ?self.config.inbox_dir,
+            backend = %self.prover.backend(),
+            "Providing"
+        );
+        loop {
+            // Only the inbox itself being unreadable fails a poll, and it may come back
+            if let Err(err) = self.poll() {
+                warn!(%err, "Failed to poll the inbox");
+            }
+            std::thread::sleep(self.config.poll_interval);
+        }
+    }
+}
+
+/// `blake3_fold provide [--data DIR] [--state DIR] [--inbox DIR] [--outbox DIR] [--id ID]
+/// [--backend B] [--k N] [--poll-ms N]`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut config = ProviderConfig {
+        data_dir: "data".into(),
+        state_dir: "provider_state".into(),
+        inbox_dir: "inbox".into(),
+        outbox_dir: "outbox".into(),
+        poll_interval: Duration::from_secs(1),
+        prover_id: "provider".into(),
+    };
+    let mut backend = Backend::Bn256Zm;
+    let mut compressions_per_step = 1;
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+        let bad_value = |_| format!("Bad value for {}: {}", flag, value);
+        match flag.as_str() {
+            "--data" => config.data_dir = value.into(),
+            "--state" => config.state_dir = value.into(),
+            "--inbox" => config.inbox_dir = value.into(),
+            "--outbox" => config.outbox_dir = value.into(),
+            "--id" => config.prover_id = value.clone(),
+            "--backend" => backend = value.parse()?,
+            "--k" => compressions_per_step = value.parse().map_err(bad_value)?,
+            "--poll-ms" => {
+                config.poll_interval = Duration::from_millis(value.parse().map_err(bad_value)?)
+            }
+            _ => return Err(format!("Unknown flag {}", flag)),
+        }
+    }
+
+    let prover = backend
+        .prover(compressions_per_step)
+        .map_err(|err| err.to_string())?;
+    let mut provider = Provider::new(Arc::from(prover), config).map_err(|err| err.to_string())?;
+    provider.run().map_err(|err| err.to_string())
+}
+
+/// Where the response to `challenge` goes in the outbox
+pub fn response_path(outbox_dir: &Path, challenge: &Challenge) -> PathBuf {
+    outbox_dir.join(format!(
+        "{}.{}",
+        blake3::Hash::from(challenge.id()).to_hex(),
+        RESPONSE_EXTENSION
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::ChunkSelection;
+    use crate::backend::FakeProver;
+    use crate::blake3_hash::hash_with_path;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_file_index() {
+        let dir = TempDir::new("provider_index");
+        let data_dir = dir.path().join("data");
+        let state_dir = dir.path().join("state");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::create_dir_all(&state_dir).unwrap();
+        let a = vec![1u8; 1024 * 3 + 1];
+        let b = vec![2u8; 10];
+        std::fs::write(data_dir.join("a"), &a).unwrap();
+        std::fs::write(data_dir.join("b"), &b).unwrap();
+
+        let mut index = FileIndex::open(&data_dir, &state_dir).unwrap();
+        let a_root = blake3::hash(&a).to_hex().to_string();
+        let mut roots = index.roots();
+        roots.sort();
+        let mut expected = vec![a_root.clone(), blake3::hash(&b).to_hex().to_string()];
+        expected.sort();
+        assert_eq!(roots, expected);
+        // The streamed outboard is bao's
+        assert_eq!(
+            std::fs::read(index.outboard_path(&a_root)).unwrap(),
+            bao::encode::outboard(&a).0
+        );
+        assert_eq!(
+            index.hash_proof(&a_root, 2).unwrap(),
+            hash_with_path(&a, 2).unwrap().1
+        );
+
+        // Reopening picks up the saved index, and changes are noticed
+        std::fs::remove_file(data_dir.join("b")).unwrap();
+        let c = vec![3u8; 2048];
+        std::fs::write(data_dir.join("a"), &c).unwrap();
+        index.refresh().unwrap();
+        let index = FileIndex::open(&data_dir, &state_dir).unwrap();
+        assert_eq!(index.roots(), vec![blake3::hash(&c).to_hex().to_string()]);
+        assert!(index.hash_proof(&a_root, 0).is_err());
+    }
+
+    #[test]
+    fn test_answer_challenges() {
+        let dir = TempDir::new("provider_answer");
+        let root_dir = dir.path();
+        let config = ProviderConfig {
+            data_dir: root_dir.join("data"),
+            state_dir: root_dir.join("state"),
+            inbox_dir: root_dir.join("inbox"),
+            outbox_dir: root_dir.join("outbox"),
+            poll_interval: Duration::from_millis(10),
+            prover_id: "test provider".into(),
+        };
+        std::fs::create_dir_all(&config.data_dir).unwrap();
+        let data = vec![5u8; 1024 * 4];
+        std::fs::write(config.data_dir.join("file"), &data).unwrap();
+        let mut provider = Provider::new(Arc::new(FakeProver), config.clone()).unwrap();
+
+        let in_a_minute = unix_ms(SystemTime::now()) + 60_000;
+        let challenge = |root: [u8; 32], chunk_indices: Vec<u64>, deadline_unix_ms| Challenge {
+            root,
+            file_len: data.len() as u64,
+            nonce: [0u8; 32],
+            selection: ChunkSelection::Indices(chunk_indices),
+            deadline_unix_ms,
+        };
+        let root = *blake3::hash(&data).as_bytes();
+        let fresh = challenge(root, vec![3, 0], in_a_minute);
+        let expired = challenge(root, vec![1], 0);
+        let unknown = challenge([0u8; 32], vec![0], in_a_minute);
+        for (name, c) in [
+            ("fresh", &fresh),
+            ("expired", &expired),
+            ("unknown", &unknown),
+        ] {
+            std::fs::write(
+                config
+                    .inbox_dir
+                    .join(format!("{}.{}", name, CHALLENGE_EXTENSION)),
+                c.to_bytes(),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(provider.poll().unwrap(), 1);
+        let bytes = std::fs::read(response_path(&config.outbox_dir, &fresh)).unwrap();
+        let response = Response::from_bytes(&bytes).unwrap();
+        assert_eq!(response.prover_id, "test provider");
+        let chunk_indices: Vec<_> = response.bundles.iter().map(|p| p.chunk_idx).collect();
+        assert_eq!(chunk_indices, vec![3, 0]);
+        assert!(!response_path(&config.outbox_dir, &expired).exists());
+        assert!(!response_path(&config.outbox_dir, &unknown).exists());
+        // Every challenge was handled
+        assert_eq!(std::fs::read_dir(&config.inbox_dir).unwrap().count(), 0);
+    }
+}
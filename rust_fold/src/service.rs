@@ -0,0 +1,702 @@
+//! A local proving service, so consumers can ask for chunk proofs over HTTP rather than linking
+//! the crate and managing public parameters themselves. Run it with `blake3_fold serve`.
+//!
+//! - `POST /jobs` with `{"root": "<hex root hash>", "chunk_idx": 3}` queues a job and
+//!   returns it, e.g. `{"id": 0, "status": "queued", ...}`
+//! - `GET /jobs/<id>` returns the job
+//! - `GET /jobs/<id>/proof` returns the `codec` encoded `ChunkProof` once the job is done
+//!
+//! Files come from a store directory holding one file per root, named by its hex root hash.
+//! Jobs and their proofs are kept in a jobs directory, and jobs which were queued or running
+//! when the service stopped are queued again when it starts.
+//!
+//! The HTTP handling is deliberately minimal (one request per connection, no chunked bodies)
+//! and the service binds to localhost by default. It is not meant to face the internet.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
+
+use crate::backend::{Backend, ChunkProof, ChunkProver};
+use crate::blake3_hash::hash_with_path;
+use crate::proof_stats::ProofStats;
+use crate::MAX_BYTES_PER_CHUNK;
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+// Requests are small JSON objects
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    /// The hex root hash of the file
+    pub root: String,
+    pub chunk_idx: u64,
+    /// The hex nonce to bind the proof to, if any
+    #[serde(default)]
+    pub nonce: Option<String>,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub stats: Option<ProofStats>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobRequest {
+    root: String,
+    chunk_idx: u64,
+    #[serde(default)]
+    nonce: Option<String>,
+}
+
+/// A 32 byte hex nonce. It is the same shape as a hash, so is parsed as one
+fn parse_nonce(nonce: &str) -> Option<[u8; 32]> {
+    blake3::Hash::from_hex(nonce)
+        .ok()
+        .map(|hash| *hash.as_bytes())
+}
+
+/// The jobs, each persisted as `<id>.json` with its proof in `<id>.proof` once done
+struct JobStore {
+    dir: PathBuf,
+    jobs: Mutex<BTreeMap<u64, Job>>,
+}
+
+impl JobStore {
+    fn open(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let mut jobs = BTreeMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            match serde_json::from_slice::<Job>(&std::fs::read(&path)?) {
+                Ok(job) => {
+                    jobs.insert(job.id, job);
+                }
+                Err(err) => warn!(%err, ?path, "Skipping unreadable job"),
+            }
+        }
+        Ok(JobStore {
+            dir: dir.into(),
+            jobs: Mutex::new(jobs),
+        })
+    }
+
+    fn persist(&self, job: &Job) -> io::Result<()> {
+        let path = self.dir.join(format!("{}.json", job.id));
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(job)?)?;
+        std::fs::rename(tmp, path)
+    }
+
+    fn insert(&self, root: String, chunk_idx: u64, nonce: Option<String>) -> io::Result<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.keys().next_back().map_or(0, |id| id + 1);
+        let job = Job {
+            id,
+            root,
+            chunk_idx,
+            nonce,
+            status: JobStatus::Queued,
+            error: None,
+            stats: None,
+        };
+        self.persist(&job)?;
+        jobs.insert(id, job.clone());
+        Ok(job)
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut Job)) -> io::Result<()> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&id).expect("Updating an unknown job");
+        f(job);
+        self.persist(job)
+    }
+
+    fn get(&self, id: u64) -> Option<Job> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    /// The jobs which still need proving, in the order they came in
+    fn pending(&self) -> Vec<u64> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values()
+            .filter(|job| matches!(job.status, JobStatus::Queued | JobStatus::Running))
+            .map(|job| job.id)
+            .collect()
+    }
+
+    fn proof_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.proof", id))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    pub store_dir: PathBuf,
+    pub jobs_dir: PathBuf,
+    pub n_workers: usize,
+}
+
+pub struct Service {
+    prover: Arc<dyn ChunkProver>,
+    store_dir: PathBuf,
+    jobs: JobStore,
+    queue: Mutex<Sender<u64>>,
+}
+
+impl Service {
+    /// Open the job store and start `config.n_workers` workers proving with `prover`
+    pub fn start(prover: Arc<dyn ChunkProver>, config: ServiceConfig) -> io::Result<Arc<Self>> {
+        let (sender, receiver) = mpsc::channel();
+        let service = Arc::new(Service {
+            prover,
+            store_dir: config.store_dir,
+            jobs: JobStore::open(&config.jobs_dir)?,
+            queue: Mutex::new(sender),
+        });
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..config.n_workers.max(1) {
+            let service = service.clone();
+            let receiver = receiver.clone();
+            std::thread::spawn(move || service.run_worker(&receiver));
+        }
+        for id in service.jobs.pending() {
+            info!(id, "Requeuing job");
+            service.enqueue(id);
+        }
+        Ok(service)
+    }
+
+    fn enqueue(&self, id: u64) {
+        // The workers hold the service, so they outlive the sender
+        self.queue.lock().unwrap().send(id).unwrap();
+    }
+
+    fn run_worker(&self, queue: &Mutex<Receiver<u64>>) {
+        loop {
+            let id = match queue.lock().unwrap().recv() {
+                Ok(id) => id,
+                Err(_) => return,
+            };
+            let job = self.jobs.get(id).expect("Queued an unknown job");
+            let _span = tracing::info_span!("job", id, chunk_idx = job.chunk_idx).entered();
+            self.set_status(id, JobStatus::Running, None, None);
+            // A panic in the prover fails the job rather than killing the worker with the job
+            // left running
+            let res = panic::catch_unwind(AssertUnwindSafe(|| self.prove(&job)))
+                .unwrap_or_else(|payload| {
+                    Err(format!("Prover panicked: {}", panic_message(&*payload)))
+                })
+                .and_then(|proof| {
+                    std::fs::write(self.jobs.proof_path(id), proof.to_bytes())
+                        .map_err(|err| format!("Failed to store the proof: {}", err))?;
+                    Ok(proof.stats)
+                });
+            match res {
+                Ok(stats) => {
+                    info!("Job done");
+                    self.set_status(id, JobStatus::Done, None, Some(stats));
+                }
+                Err(err) => {
+                    error!(%err, "Job failed");
+                    self.set_status(id, JobStatus::Failed, Some(err), None);
+                }
+            }
+        }
+    }
+
+    fn set_status(
+        &self,
+        id: u64,
+        status: JobStatus,
+        error: Option<String>,
+        stats: Option<ProofStats>,
+    ) {
+        let res = self.jobs.update(id, |job| {
+            job.status = status;
+            job.error = error;
+            job.stats = stats;
+        });
+        if let Err(err) = res {
+            warn!(%err, id, "Failed to persist the job");
+        }
+    }
+
+    fn prove(&self, job: &Job) -> Result<ChunkProof, String> {
+        let root = blake3::Hash::from_hex(&job.root).map_err(|err| err.to_string())?;
+        let data = std::fs::read(self.store_dir.join(root.to_hex().as_str()))
+            .map_err(|err| format!("No file for root {}: {}", job.root, err))?;
+        let n_chunks = (data.len() + MAX_BYTES_PER_CHUNK - 1) / MAX_BYTES_PER_CHUNK;
+        if job.chunk_idx >= n_chunks as u64 {
+            return Err(format!(
+                "Chunk {} is out of range, the file has {} chunks",
+                job.chunk_idx, n_chunks
+            ));
+        }
+        let (hash, hash_proof) =
+            hash_with_path(&data, job.chunk_idx as usize).map_err(|err| err.to_string())?;
+        if hash != root {
+            return Err(format!("Stored file hashes to {}", hash.to_hex()));
+        }
+        let nonce = job
+            .nonce
+            .as_deref()
+            .map(|nonce| parse_nonce(nonce).ok_or("nonce must be 32 hex bytes"))
+            .transpose()?;
+        self.prover
+            .prove(hash_proof, nonce)
+            .map_err(|err| err.to_string())
+    }
+
+    /// Accept connections until the listener fails, handling each on its own thread
+    pub fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        info!(addr = %listener.local_addr()?, backend = %self.prover.backend(), "Serving");
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let service = self.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = service.handle_connection(stream) {
+                    warn!(%err, "Connection failed");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let response = match read_request(&mut reader) {
+            Ok(request) => self.handle(&request),
+            Err(err) => Response::error(400, &err.to_string()),
+        };
+        write_response(&mut &stream, &response)
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["jobs"]) => {
+                let job_request: JobRequest = match serde_json::from_slice(&request.body) {
+                    Ok(job_request) => job_request,
+                    Err(err) => return Response::error(400, &err.to_string()),
+                };
+                if blake3::Hash::from_hex(&job_request.root).is_err() {
+                    return Response::error(400, "root must be a hex BLAKE3 hash");
+                }
+                if job_request
+                    .nonce
+                    .as_deref()
+                    .map_or(false, |n| parse_nonce(n).is_none())
+                {
+                    return Response::error(400, "nonce must be 32 hex bytes");
+                }
+                match self
+                    .jobs
+                    .insert(job_request.root, job_request.chunk_idx, job_request.nonce)
+                {
+                    Ok(job) => {
+                        self.enqueue(job.id);
+                        Response::json(202, &job)
+                    }
+                    Err(err) => Response::error(500, &err.to_string()),
+                }
+            }
+            ("GET", ["jobs", id]) => match id.parse().ok().and_then(|id| self.jobs.get(id)) {
+                Some(job) => Response::json(200, &job),
+                None => Response::error(404, "No such job"),
+            },
+            ("GET", ["jobs", id, "proof"]) => {
+                match id.parse().ok().and_then(|id| self.jobs.get(id)) {
+                    Some(job) if job.status == JobStatus::Done => {
+                        match std::fs::read(self.jobs.proof_path(job.id)) {
+                            Ok(body) => Response {
+                                status: 200,
+                                content_type: "application/octet-stream",
+                                body,
+                            },
+                            Err(err) => Response::error(500, &err.to_string()),
+                        }
+                    }
+                    Some(_) => Response::error(409, "The job is not done"),
+                    None => Response::error(404, "No such job"),
+                }
+            }
+            _ => Response::error(404, "Not found"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(value).expect("Failed to serialize the response"),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, &serde_json::json!({ "error": message }))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(invalid("Malformed request line")),
+    };
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("Bad Content-Length"))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(invalid("Body too large"));
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, body })
+}
+
+fn write_response<W: Write>(writer: &mut W, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len()
+    )?;
+    writer.write_all(&response.body)?;
+    writer.flush()
+}
+
+/// `blake3_fold serve [--addr A] [--store DIR] [--jobs DIR] [--backend B] [--workers N] [--k N]`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut addr = DEFAULT_ADDR.to_string();
+    let mut config = ServiceConfig {
+        store_dir: "store".into(),
+        jobs_dir: "jobs".into(),
+        n_workers: 1,
+    };
+    let mut backend = Backend::Bn256Zm;
+    let mut compressions_per_step = 1;
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+        let bad_value = |_| format!("Bad value for {}: {}", flag, value);
+        match flag.as_str() {
+            "--addr" => addr = value.clone(),
+            "--store" => config.store_dir = value.into(),
+            "--jobs" => config.jobs_dir = value.into(),
+            "--backend" => backend = value.parse()?,
+            "--workers" => config.n_workers = value.parse().map_err(bad_value)?,
+            "--k" => compressions_per_step = value.parse().map_err(bad_value)?,
+            _ => return Err(format!("Unknown flag {}", flag)),
+        }
+    }
+
+    let prover = backend
+        .prover(compressions_per_step)
+        .map_err(|err| err.to_string())?;
+    let service = Service::start(Arc::from(prover), config).map_err(|err| err.to_string())?;
+    let listener = TcpListener::bind(&addr).map_err(|err| err.to_string())?;
+    service.serve(listener).map_err(|err| err.to_string())
+}
+
+/// The message a panic was raised with, for the `panic!("...")` and `panic!("{}", ..)` forms
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{FakeProver, ProverError};
+    use crate::blake3_hash::Blake3HashProof;
+    use crate::utils::TempDir;
+    use std::time::Duration;
+
+    fn request(addr: &str, method: &str, path: &str, body: &str) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&response[..split]);
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, response[split + 4..].to_vec())
+    }
+
+    fn wait_for(addr: &str, id: u64) -> Job {
+        for _ in 0..600 {
+            let (_, body) = request(addr, "GET", &format!("/jobs/{}", id), "");
+            let job: Job = serde_json::from_slice(&body).unwrap();
+            if matches!(job.status, JobStatus::Done | JobStatus::Failed) {
+                return job;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("Job {} did not finish", id);
+    }
+
+    /// Serve from a fresh store, which the jobs are kept under too
+    fn serve(prover: Arc<dyn ChunkProver>, name: &str) -> (String, TempDir) {
+        let store_dir = TempDir::new(&format!("service_{}", name));
+        let config = ServiceConfig {
+            store_dir: store_dir.path().into(),
+            jobs_dir: store_dir.path().join("jobs"),
+            n_workers: 2,
+        };
+        let service = Service::start(prover, config).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || service.serve(listener));
+        (addr, store_dir)
+    }
+
+    #[test]
+    fn test_read_request() {
+        let raw = b"POST /jobs HTTP/1.1\r\ncontent-length: 2\r\n\r\n{}";
+        let request = read_request(&mut &raw[..]).unwrap();
+        assert_eq!(
+            request,
+            Request {
+                method: "POST".into(),
+                path: "/jobs".into(),
+                body: b"{}".to_vec(),
+            }
+        );
+        assert!(read_request(&mut &b"\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_jobs() {
+        let (addr, store_dir) = serve(Arc::new(FakeProver), "jobs");
+        let data = vec![1u8; 1024 * 2 + 10];
+        let root = blake3::hash(&data).to_hex().to_string();
+        std::fs::write(store_dir.path().join(&root), &data).unwrap();
+
+        let (status, body) = request(
+            &addr,
+            "POST",
+            "/jobs",
+            &format!(r#"{{"root": "{}", "chunk_idx": 2}}"#, root),
+        );
+        assert_eq!(status, 202);
+        let job: Job = serde_json::from_slice(&body).unwrap();
+        assert_eq!(wait_for(&addr, job.id).status, JobStatus::Done);
+        let (status, body) = request(&addr, "GET", &format!("/jobs/{}/proof", job.id), "");
+        assert_eq!(status, 200);
+        assert_eq!(ChunkProof::from_bytes(&body).unwrap().chunk_idx, 2);
+
+        // A nonce is passed on to the prover
+        let nonce = "07".repeat(32);
+        let (_, body) = request(
+            &addr,
+            "POST",
+            "/jobs",
+            &format!(
+                r#"{{"root": "{}", "chunk_idx": 0, "nonce": "{}"}}"#,
+                root, nonce
+            ),
+        );
+        let job: Job = serde_json::from_slice(&body).unwrap();
+        assert_eq!(wait_for(&addr, job.id).status, JobStatus::Done);
+        let (_, body) = request(&addr, "GET", &format!("/jobs/{}/proof", job.id), "");
+        assert_eq!(
+            ChunkProof::from_bytes(&body).unwrap().nonce,
+            Some([7u8; 32])
+        );
+        let (status, _) = request(
+            &addr,
+            "POST",
+            "/jobs",
+            &format!(r#"{{"root": "{}", "chunk_idx": 0, "nonce": "07"}}"#, root),
+        );
+        assert_eq!(status, 400);
+
+        // Out of range chunks and unknown roots fail
+        for (root, chunk_idx) in [
+            (root.clone(), 3),
+            (blake3::hash(b"").to_hex().to_string(), 0),
+        ] {
+            let (_, body) = request(
+                &addr,
+                "POST",
+                "/jobs",
+                &format!(r#"{{"root": "{}", "chunk_idx": {}}}"#, root, chunk_idx),
+            );
+            let job: Job = serde_json::from_slice(&body).unwrap();
+            let job = wait_for(&addr, job.id);
+            assert_eq!(job.status, JobStatus::Failed);
+            assert!(job.error.is_some());
+            let (status, _) = request(&addr, "GET", &format!("/jobs/{}/proof", job.id), "");
+            assert_eq!(status, 409);
+        }
+
+        assert_eq!(request(&addr, "POST", "/jobs", "{}").0, 400);
+        assert_eq!(request(&addr, "GET", "/jobs/1000", "").0, 404);
+    }
+
+    struct PanickingProver;
+
+    impl ChunkProver for PanickingProver {
+        fn backend(&self) -> Backend {
+            Backend::Pasta
+        }
+
+        fn prove(
+            &self,
+            _hash_proof: Blake3HashProof,
+            _nonce: Option<[u8; 32]>,
+        ) -> Result<ChunkProof, ProverError> {
+            panic!("out of constraints");
+        }
+
+        fn verify(&self, _proof: &ChunkProof) -> Result<Vec<u8>, ProverError> {
+            Err(ProverError::InvalidStatement)
+        }
+    }
+
+    #[test]
+    fn test_prover_panic() {
+        let (addr, store_dir) = serve(Arc::new(PanickingProver), "panic");
+        let data = vec![2u8; 100];
+        let root = blake3::hash(&data).to_hex().to_string();
+        std::fs::write(store_dir.path().join(&root), &data).unwrap();
+
+        // More jobs than workers, so the workers must survive the panics to run them all
+        for _ in 0..3 {
+            let (_, body) = request(
+                &addr,
+                "POST",
+                "/jobs",
+                &format!(r#"{{"root": "{}", "chunk_idx": 0}}"#, root),
+            );
+            let job: Job = serde_json::from_slice(&body).unwrap();
+            let job = wait_for(&addr, job.id);
+            assert_eq!(job.status, JobStatus::Failed);
+            assert!(job.error.unwrap().contains("out of constraints"));
+        }
+    }
+
+    #[test]
+    fn test_requeue_on_start() {
+        let dir = TempDir::new("service_requeue");
+        let jobs_dir = dir.path().join("jobs");
+        let store = JobStore::open(&jobs_dir).unwrap();
+        let job = store.insert("00".repeat(32), 0, None).unwrap();
+        store
+            .update(job.id, |job| job.status = JobStatus::Running)
+            .unwrap();
+        drop(store);
+
+        let config = ServiceConfig {
+            store_dir: dir.path().join("store"),
+            jobs_dir,
+            n_workers: 1,
+        };
+        let service = Service::start(Arc::new(FakeProver), config).unwrap();
+        for _ in 0..100 {
+            if service.jobs.get(job.id).unwrap().status == JobStatus::Failed {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("The running job was not requeued");
+    }
+
+    #[test]
+    fn test_prove_pasta() {
+        let prover = Backend::Pasta.prover(4).unwrap();
+        let prover: Arc<dyn ChunkProver> = Arc::from(prover);
+        let (addr, store_dir) = serve(prover.clone(), "pasta");
+        let data = vec![42u8; 1024 + 100];
+        let root = blake3::hash(&data);
+        std::fs::write(store_dir.path().join(root.to_hex().as_str()), &data).unwrap();
+
+        let (_, body) = request(
+            &addr,
+            "POST",
+            "/jobs",
+            &format!(r#"{{"root": "{}", "chunk_idx": 1}}"#, root.to_hex()),
+        );
+        let job: Job = serde_json::from_slice(&body).unwrap();
+        let job = wait_for(&addr, job.id);
+        assert_eq!(job.status, JobStatus::Done, "{:?}", job.error);
+        let (_, body) = request(&addr, "GET", &format!("/jobs/{}/proof", job.id), "");
+        let proof = ChunkProof::from_bytes(&body).unwrap();
+        assert_eq!(prover.verify(&proof).unwrap(), root.as_bytes().to_vec());
+    }
+}
@@ -0,0 +1,106 @@
+//! Fingerprints of the proving setup, so proofs can say which keys they were made for and a
+//! verifier with other keys rejects them up front instead of failing somewhere in the SNARK.
+//!
+//! The parameters and keys are made from a dummy one byte chunk (see `setup_params`). The tests
+//! below check that this does not matter: circuits for any chunk and depth give the same digests.
+use arecibo::traits::circuit::StepCircuit;
+use arecibo::traits::snark::RelaxedR1CSSNARKTrait;
+use arecibo::traits::Engine;
+use arecibo::{PublicParams, VerifierKey};
+use ff::PrimeField;
+
+pub type SetupDigest = [u8; 32];
+
+const PARAMS_CONTEXT: &str = "blake3_fold 2024-01 public params digest";
+const VERIFIER_KEY_CONTEXT: &str = "blake3_fold 2024-01 verifier key digest";
+
+/// Nova's own digest of `pp`, which covers the circuit shapes and commitment keys
+pub fn params_digest<E1, E2, C1, C2>(pp: &PublicParams<E1, E2, C1, C2>) -> SetupDigest
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    C1: StepCircuit<E1::Scalar>,
+    C2: StepCircuit<E2::Scalar>,
+{
+    let mut hasher = blake3::Hasher::new_derive_key(PARAMS_CONTEXT);
+    hasher.update(pp.digest().to_repr().as_ref());
+    *hasher.finalize().as_bytes()
+}
+
+/// The hash of the bincode encoded `vk`
+pub fn verifier_key_digest<E1, E2, C1, C2, S1, S2>(
+    vk: &VerifierKey<E1, E2, C1, C2, S1, S2>,
+) -> SetupDigest
+where
+    E1: Engine<Base = <E2 as Engine>::Scalar>,
+    E2: Engine<Base = <E1 as Engine>::Scalar>,
+    C1: StepCircuit<E1::Scalar>,
+    C2: StepCircuit<E2::Scalar>,
+    S1: RelaxedR1CSSNARKTrait<E1>,
+    S2: RelaxedR1CSSNARKTrait<E2>,
+{
+    let bytes = bincode::serialize(vk).expect("Failed to serialize the verifier key");
+    blake3::derive_key(VERIFIER_KEY_CONTEXT, &bytes)
+}
+
+pub fn to_hex(digest: &SetupDigest) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arecibo::provider::ipa_pc;
+    use arecibo::provider::{PallasEngine, VestaEngine};
+    use arecibo::traits::circuit::TrivialCircuit;
+    use arecibo::{spartan, CompressedSNARK};
+
+    use crate::blake3_circuit::{Blake3BlockCompressCircuit, CircomArtifacts};
+    use crate::blake3_hash::hash_with_path;
+    use crate::{setup_params, C1, C2, PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_WASM_PATH};
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = ipa_pc::EvaluationEngine<E1>;
+    type EE2 = ipa_pc::EvaluationEngine<E2>;
+    type S1 = spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+
+    fn digests_for(circuit: &C1<E1>) -> (SetupDigest, SetupDigest) {
+        let pp = PublicParams::<E1, E2, C1<E1>, C2<E2>>::setup(
+            circuit,
+            &TrivialCircuit::default(),
+            &*S1::ck_floor(),
+            &*S2::ck_floor(),
+        );
+        let (_, vk) = CompressedSNARK::<_, _, _, _, S1, S2>::setup(&pp).unwrap();
+        (params_digest(&pp), verifier_key_digest(&vk))
+    }
+
+    #[test]
+    fn test_digest_independent_of_inputs() {
+        let artifacts =
+            CircomArtifacts::load(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let compressions_per_step = 2;
+        let pp = setup_params::<E1, E2, S1, S2>(&artifacts, compressions_per_step).pp;
+        let (_, vk) = CompressedSNARK::<_, _, _, _, S1, S2>::setup(&pp).unwrap();
+        let expected = (params_digest(&pp), verifier_key_digest(&vk));
+
+        // Single chunk files, multi block chunks, and deep paths with partial last chunks
+        for (n_bytes, chunk_idx) in [(1, 0), (1024, 0), (1024 * 4 + 7, 3), (1024 * 9, 8)] {
+            let data = vec![(n_bytes % 251) as u8; n_bytes];
+            let (_, hash_proof) = hash_with_path(&data, chunk_idx).unwrap();
+            let circuit = Blake3BlockCompressCircuit::with_artifacts(
+                hash_proof.bytes,
+                hash_proof.parent_path,
+                artifacts.clone(),
+            )
+            .with_compressions_per_step(compressions_per_step);
+            assert_eq!(digests_for(&circuit), expected, "{} bytes", n_bytes);
+        }
+
+        // But the setup itself is covered
+        let other = setup_params::<E1, E2, S1, S2>(&artifacts, compressions_per_step + 1).pp;
+        assert_ne!(params_digest(&other), expected.0);
+    }
+}
@@ -0,0 +1,207 @@
+//! Paths into huge virtual files (up to 2^64 bytes), to test the circuits at depths and chunk
+//! indices `hash_with_path` cannot reach as it needs the whole file in memory.
+//!
+//! The content of every chunk is derived from a seed, so any chunk can be made on demand. The
+//! chaining value of a subtree is computed from its chunks when it is small or holds one of the
+//! chunks the tree is opened at. Any other (large) subtree stands in for content nobody will
+//! look at, and gets a chaining value derived from the seed and its position instead. Every path
+//! out of one `SparseTree` therefore leads to the same root, and for files small enough that
+//! every subtree is computed, that root is the file's BLAKE3 hash.
+use blake3::guts::{parent_cv, ChunkState};
+use blake3::Hash;
+
+use crate::blake3_circuit::{PathDirection, PathNode};
+use crate::blake3_hash::Blake3HashProof;
+use crate::MAX_BYTES_PER_CHUNK;
+
+const VIRTUAL_CV_CONTEXT: &str = "blake3_fold 2024-01 sparse tree virtual subtree";
+
+/// Subtrees of at most this many chunks are always computed from their content
+const DENSE_CHUNKS: u64 = 16;
+
+pub(crate) struct SparseTree {
+    seed: [u8; 32],
+    file_len: u64,
+    n_chunks: u64,
+    /// The chunks paths can be taken to, sorted
+    opened: Vec<u64>,
+}
+
+/// The number of chunks in the left subtree of a node over `n_chunks > 1` chunks: the largest
+/// power of two less than `n_chunks`
+fn left_len(n_chunks: u64) -> u64 {
+    1 << (63 - (n_chunks - 1).leading_zeros())
+}
+
+impl SparseTree {
+    pub(crate) fn new(seed: [u8; 32], file_len: u64, opened: &[u64]) -> Self {
+        // An empty file still has one (empty) chunk
+        let n_chunks = file_len.div_ceil(MAX_BYTES_PER_CHUNK as u64).max(1);
+        let mut opened = opened.to_vec();
+        opened.sort();
+        opened.dedup();
+        assert!(opened.iter().all(|&chunk_idx| chunk_idx < n_chunks));
+        SparseTree {
+            seed,
+            file_len,
+            n_chunks,
+            opened,
+        }
+    }
+
+    pub(crate) fn chunk_bytes(&self, chunk_idx: u64) -> Vec<u8> {
+        let start = chunk_idx * MAX_BYTES_PER_CHUNK as u64;
+        let len = (self.file_len - start).min(MAX_BYTES_PER_CHUNK as u64);
+        let mut bytes = vec![0u8; len as usize];
+        blake3::Hasher::new_keyed(&self.seed)
+            .update(&chunk_idx.to_le_bytes())
+            .finalize_xof()
+            .fill(&mut bytes);
+        bytes
+    }
+
+    fn chunk_cv(&self, chunk_idx: u64, is_root: bool) -> Hash {
+        ChunkState::new(chunk_idx)
+            .update(&self.chunk_bytes(chunk_idx))
+            .finalize(is_root)
+    }
+
+    fn is_opened_in(&self, start: u64, n_chunks: u64) -> bool {
+        let first = self.opened.partition_point(|&chunk_idx| chunk_idx < start);
+        self.opened
+            .get(first)
+            .map_or(false, |&chunk_idx| chunk_idx - start < n_chunks)
+    }
+
+    /// The chaining value of the subtree over `n_chunks` chunks from `start`
+    fn cv(&self, start: u64, n_chunks: u64, is_root: bool) -> Hash {
+        if n_chunks == 1 {
+            return self.chunk_cv(start, is_root);
+        }
+        if n_chunks > DENSE_CHUNKS && !is_root && !self.is_opened_in(start, n_chunks) {
+            let mut hasher = blake3::Hasher::new_derive_key(VIRTUAL_CV_CONTEXT);
+            hasher.update(&self.seed);
+            hasher.update(&start.to_le_bytes());
+            hasher.update(&n_chunks.to_le_bytes());
+            return hasher.finalize();
+        }
+        let left = left_len(n_chunks);
+        parent_cv(
+            &self.cv(start, left, false),
+            &self.cv(start + left, n_chunks - left, false),
+            is_root,
+        )
+    }
+
+    pub(crate) fn root(&self) -> Hash {
+        self.cv(0, self.n_chunks, true)
+    }
+
+    /// The path to the opened chunk `chunk_idx`, from the root down
+    pub(crate) fn hash_proof(&self, chunk_idx: u64) -> Blake3HashProof {
+        assert!(self.opened.binary_search(&chunk_idx).is_ok());
+        let (mut start, mut n_chunks) = (0, self.n_chunks);
+        let mut parent_path = vec![];
+        while n_chunks > 1 {
+            let left = left_len(n_chunks);
+            if chunk_idx - start < left {
+                let sibling = self.cv(start + left, n_chunks - left, false);
+                parent_path.push(PathNode::new(PathDirection::Left, *sibling.as_bytes()));
+                n_chunks = left;
+            } else {
+                let sibling = self.cv(start, left, false);
+                parent_path.push(PathNode::new(PathDirection::Right, *sibling.as_bytes()));
+                start += left;
+                n_chunks -= left;
+            }
+        }
+        Blake3HashProof {
+            chunk_idx,
+            parent_path,
+            bytes: self.chunk_bytes(chunk_idx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::{PallasEngine, VestaEngine};
+    use arecibo::spartan;
+
+    use super::*;
+    use crate::audit::leaf_depth;
+    use crate::blake3_circuit::CircomArtifacts;
+    use crate::blake3_hash::hash_with_path;
+    use crate::{
+        prove_chunk_hash_with_params, setup_params, PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_WASM_PATH,
+    };
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = arecibo::provider::ipa_pc::EvaluationEngine<E1>;
+    type EE2 = arecibo::provider::ipa_pc::EvaluationEngine<E2>;
+    type S1 = spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+
+    #[test]
+    fn test_matches_materialized_file() {
+        let seed = [5u8; 32];
+        // Every subtree is dense, so the tree is the real one
+        for file_len in [0, 1, 1024, 1024 * 5 + 7, 1024 * 16] {
+            let tree = SparseTree::new(seed, file_len, &[0]);
+            let data: Vec<u8> = (0..tree.n_chunks)
+                .flat_map(|chunk_idx| tree.chunk_bytes(chunk_idx))
+                .collect();
+            assert_eq!(data.len() as u64, file_len);
+            assert_eq!(tree.root(), blake3::hash(&data));
+        }
+
+        // The paths match bao's
+        let file_len = 1024 * 8;
+        let tree = SparseTree::new(seed, file_len, &[0, 3, 7]);
+        let data: Vec<u8> = (0..8).flat_map(|i| tree.chunk_bytes(i)).collect();
+        for chunk_idx in [0, 3, 7] {
+            let (hash, expected) = hash_with_path(&data, chunk_idx as usize).unwrap();
+            assert_eq!(tree.root(), hash);
+            assert_eq!(tree.hash_proof(chunk_idx), expected);
+        }
+    }
+
+    #[test]
+    fn test_huge_tree() {
+        let chunks = [3, (1 << 40) + 17, (1 << 53) - 1];
+        let tree = SparseTree::new([6u8; 32], 1 << 63, &chunks);
+        assert_eq!(tree.n_chunks, 1 << 53);
+        let proofs: Vec<_> = chunks.iter().map(|&i| tree.hash_proof(i)).collect();
+        for proof in &proofs {
+            assert_eq!(
+                proof.parent_path.len() + 1,
+                leaf_depth(tree.n_chunks, proof.chunk_idx)
+            );
+            assert_eq!(proof.parent_path.len(), 53);
+        }
+        // Siblings which do not hold an opened chunk are shared between paths
+        assert_eq!(proofs[0].parent_path[0], proofs[1].parent_path[0]);
+        assert_ne!(proofs[1].parent_path[0], proofs[2].parent_path[0]);
+
+        // A file of almost 2^64 bytes, with a short last chunk
+        let tree = SparseTree::new([6u8; 32], u64::MAX, &[u64::MAX / 1024]);
+        let proof = tree.hash_proof(u64::MAX / 1024);
+        assert_eq!(proof.parent_path.len(), 54);
+        assert_eq!(proof.bytes.len(), 1023);
+    }
+
+    #[test]
+    fn test_prove_huge_tree() {
+        let artifacts =
+            CircomArtifacts::load(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let compressions_per_step = 8;
+        let params = setup_params::<E1, E2, S1, S2>(&artifacts, compressions_per_step);
+        // Above 2^32, 54 deep
+        let chunk_idx = (1 << 45) + (1 << 33) + 5;
+        let tree = SparseTree::new([7u8; 32], 1 << 63, &[chunk_idx]);
+        let (output_hash, _, _) =
+            prove_chunk_hash_with_params(&params, tree.hash_proof(chunk_idx), artifacts).unwrap();
+        assert_eq!(output_hash, tree.root().as_bytes().to_vec());
+    }
+}
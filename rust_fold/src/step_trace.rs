@@ -0,0 +1,154 @@
+//! An opt-in trace of the step circuit, for debugging a wrong hash without `println!`s.
+//!
+//! Every compression synthesized with values (so not the shape synthesized at setup) appends one
+//! JSON object to the trace file: where it is in the chunk and path, the circom inputs it was
+//! given, its IO before and after, and the whole witness keyed by the signal names in the
+//! circuit's `.sym` file. Scalars which fit in 64 bits are written in decimal, so message and
+//! chaining value words read as they would in a Blake3 implementation.
+use ff::PrimeFieldBits;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::blake3_circuit::CircuitCursor;
+use crate::native_witness::{low_u64, SymbolMap};
+
+pub struct StepTrace {
+    /// The name of each wire of the witness, if it has one
+    names: Vec<Option<String>>,
+    out: Mutex<BufWriter<File>>,
+}
+
+fn scalar_to_json<F: PrimeFieldBits>(f: &F) -> Value {
+    let low = low_u64(f);
+    if F::from(low) == *f {
+        Value::from(low)
+    } else {
+        Value::from(format!("{:?}", f))
+    }
+}
+
+fn scalars_to_json<F: PrimeFieldBits>(values: &[F]) -> Value {
+    Value::Array(values.iter().map(scalar_to_json).collect())
+}
+
+impl StepTrace {
+    pub fn create(path: &str, symbols: &SymbolMap) -> Result<Self, std::io::Error> {
+        Ok(StepTrace {
+            names: symbols
+                .names()
+                .into_iter()
+                .map(|name| name.map(String::from))
+                .collect(),
+            out: Mutex::new(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    /// Append the compression at `cursor`. The line is flushed right away so that the trace
+    /// is complete up to a step which panics. A trace which cannot be written is only a
+    /// debugging aid lost, so the error is logged and proving carries on.
+    pub(crate) fn record<F: PrimeFieldBits>(
+        &self,
+        cursor: &CircuitCursor,
+        inputs: &[(String, Vec<F>)],
+        witness: &[F],
+        z_in: &[F],
+        z_out: &[F],
+    ) {
+        let inputs: Map<String, Value> = inputs
+            .iter()
+            .map(|(name, values)| (name.clone(), scalars_to_json(values)))
+            .collect();
+        let witness: Map<String, Value> = self
+            .names
+            .iter()
+            .zip(witness)
+            .filter_map(|(name, value)| Some((name.clone()?, scalar_to_json(value))))
+            .collect();
+        let line = json!({
+            "cursor": cursor,
+            "inputs": inputs,
+            "z_in": scalars_to_json(z_in),
+            "z_out": scalars_to_json(z_out),
+            "witness": witness,
+        });
+
+        // A step which panicked mid line leaves the lock poisoned, the next line is still valid
+        let mut out = self.out.lock().unwrap_or_else(|err| err.into_inner());
+        let res = serde_json::to_writer(&mut *out, &line)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(out))
+            .and_then(|_| out.flush());
+        if let Err(err) = res {
+            warn!(%err, "Failed to write the step trace");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arecibo::provider::{PallasEngine, VestaEngine};
+    use std::io::{BufRead, BufReader};
+
+    use super::*;
+    use crate::blake3_circuit::CircomArtifacts;
+    use crate::blake3_hash::hash_with_path;
+    use crate::utils::TempDir;
+    use crate::{
+        prove_chunk_hash_with_params, setup_params, PASTA_CIRCOM_R1CS_PATH, PASTA_CIRCOM_SYM_PATH,
+        PASTA_CIRCOM_WASM_PATH,
+    };
+
+    type E1 = PallasEngine;
+    type E2 = VestaEngine;
+    type EE1 = arecibo::provider::ipa_pc::EvaluationEngine<E1>;
+    type EE2 = arecibo::provider::ipa_pc::EvaluationEngine<E2>;
+    type S1 = arecibo::spartan::snark::RelaxedR1CSSNARK<E1, EE1>;
+    type S2 = arecibo::spartan::snark::RelaxedR1CSSNARK<E2, EE2>;
+
+    #[test]
+    fn test_step_trace() {
+        let dir = TempDir::new("step_trace");
+        let path = dir.path().join("trace.jsonl");
+        let path = path.to_str().unwrap();
+        let artifacts =
+            CircomArtifacts::load(PASTA_CIRCOM_WASM_PATH, PASTA_CIRCOM_R1CS_PATH).unwrap();
+        let params = setup_params::<E1, E2, S1, S2>(&artifacts, 2);
+
+        // 2 blocks and 2 parents, over 2 steps
+        let data = vec![4u8; 1024 * 3 + 100];
+        let (hash, hash_proof) = hash_with_path(&data, 3).unwrap();
+        let artifacts = artifacts.with_trace(path, PASTA_CIRCOM_SYM_PATH).unwrap();
+        prove_chunk_hash_with_params(&params, hash_proof, artifacts).unwrap();
+
+        let lines: Vec<Value> = BufReader::new(File::open(path).unwrap())
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(line["cursor"]["compressions_done"], i);
+            assert!(line["inputs"]["m"].as_array().unwrap().len() == 16);
+            assert_eq!(line["witness"]["main.b"], line["inputs"]["b"][0]);
+            if i > 0 {
+                assert_eq!(line["z_in"], lines[i - 1]["z_out"]);
+            }
+        }
+        // The second block has 100 - 64 bytes
+        assert_eq!(lines[1]["inputs"]["b"][0], 36);
+
+        // The last output holds the root's words
+        let words: Vec<u64> = lines[3]["z_out"].as_array().unwrap()[2..10]
+            .iter()
+            .map(|word| word.as_u64().unwrap())
+            .collect();
+        let expected: Vec<u64> = hash
+            .as_bytes()
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()) as u64)
+            .collect();
+        assert_eq!(words, expected);
+    }
+}
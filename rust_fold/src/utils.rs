@@ -1,5 +1,5 @@
-
 use arecibo::traits::Group;
+use bellpepper_core::boolean::Boolean;
 use bellpepper_core::{num::AllocatedNum, ConstraintSystem, LinearCombination, SynthesisError};
 use circom_scotia::r1cs::R1CS;
 use ff::Field;
@@ -87,6 +87,37 @@ pub fn synthesize_with_vec<F: PrimeField, CS: ConstraintSystem<F>>(
     Ok(output)
 }
 
+/// Returns `if_true` if `condition` is set and `if_false` otherwise
+pub(crate) fn conditionally_select<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    if_true: &AllocatedNum<F>,
+    if_false: &AllocatedNum<F>,
+    condition: &Boolean,
+) -> Result<AllocatedNum<F>, SynthesisError> {
+    let out = AllocatedNum::alloc(cs.namespace(|| "select"), || {
+        if condition
+            .get_value()
+            .ok_or(SynthesisError::AssignmentMissing)?
+        {
+            if_true.get_value().ok_or(SynthesisError::AssignmentMissing)
+        } else {
+            if_false
+                .get_value()
+                .ok_or(SynthesisError::AssignmentMissing)
+        }
+    })?;
+
+    // condition * (if_true - if_false) = out - if_false
+    cs.enforce(
+        || "conditional select",
+        |_| condition.lc(CS::one(), F::ONE),
+        |lc| lc + if_true.get_variable() - if_false.get_variable(),
+        |lc| lc + out.get_variable() - if_false.get_variable(),
+    );
+
+    Ok(out)
+}
+
 pub(crate) fn bytes_to_u32_le(bytes: &[u8]) -> Vec<u32> {
     bytes
         .chunks(4)
@@ -170,3 +201,33 @@ pub(crate) fn format_bytes(v: &[u8]) -> Vec<String> {
         .map(|x| format!("0x{:08x}", x).to_string())
         .collect::<Vec<String>>()
 }
+
+/// A directory of its own for a test, removed when dropped. The name holds the process id and
+/// a random suffix, so tests running at the same time (or a stale run) never share files
+#[cfg(test)]
+pub(crate) struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "blake3_fold_{}_{}_{:016x}",
+            name,
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub(crate) fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}